@@ -1,23 +1,81 @@
 use crossterm::{
-    cursor::{Hide, MoveTo, Show},
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers, MouseEventKind},
+    cursor::{Hide, Show},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, MouseButton, MouseEvent, MouseEventKind},
     execute,
-    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use std::io::{self, Write};
-use std::time::Instant;
+use std::ffi::OsString;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
 
-use crate::git::GitDiff;
-use crate::types::{BranchInfo, CommitInfo, DiffHunk, FileChange};
-use crate::ui::Ui;
+use crate::clipboard;
+use crate::config::Layout;
+use crate::export::{self, ExportFile};
+use crate::highlighter::{self, SyntaxTheme};
+use crate::keymap::{Action, Keymap};
+use crate::source::DiffSource;
+use crate::theme::Theme;
+use crate::types::{BranchInfo, CommitInfo, DiffHunk, DiffLine, DiffSettings, FileChange, Highlights, LineRef, MoveInfo};
+use similar::ChangeTag;
+use crate::ui::{Boundary, LineFormat, Screen, Ui};
+use crate::watcher::{Changes, RepoWatcher};
+use crate::worker::{Request, Response, Slot, Startup, Worker};
 
-const REFRESH_INTERVAL_MS: u128 = 1000;
-const MAX_COMMITS: usize = 50;
+const STATUS_MESSAGE_DURATION: Duration = Duration::from_secs(4);
+/// Columns a horizontal scroll moves the diff by
+const HORIZONTAL_STEP: usize = 8;
+/// Rows a wheel step scrolls a panel by
+const WHEEL_STEP: usize = 3;
+/// Lines a click on a hunk separator adds on each side of the gap, which closes it
+/// when it is no larger than twice this
+const CONTEXT_STEP: usize = 10;
+/// Longest time between the clicks of a double-click
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
 
 #[derive(PartialEq)]
 enum AppMode {
     Normal,
     BranchSelect,
+    /// Key bindings overlay; any key closes it
+    Help,
+}
+
+/// Settings from the command line and config file
+pub struct AppOptions {
+    pub theme: Theme,
+    /// Color the diff panel like the syntax theme's editor background
+    pub match_syntax_theme: bool,
+    pub keymap: Keymap,
+    pub layout: Layout,
+    /// Tab width and whitespace display of the diff
+    pub line_format: LineFormat,
+    /// Wrap long diff lines instead of cutting them off
+    pub wrap: bool,
+    /// Polling interval when the repository can't be watched
+    pub refresh_interval: Duration,
+    pub max_commits: usize,
+}
+
+/// Why the file list was requested, which decides what is kept when it arrives
+enum FilesReload {
+    /// A different commit was selected: start at the first file
+    Select,
+    /// Diff settings changed: keep the selected file if it is still there
+    Settings(Option<String>),
+    /// The worktree changed: only re-diff if the selected file was affected
+    Refresh(Changes),
+}
+
+/// Why the diff was requested
+enum DiffReload {
+    Select,
+    /// Keep the scroll position, the file was edited
+    Refresh,
+    /// Scroll to the other side of a move once the diff is loaded
+    Jump(ChangeTag, MoveInfo),
 }
 
 pub struct App {
@@ -33,20 +91,66 @@ pub struct App {
     selected_file: usize,
     file_scroll_offset: usize,
     diff_hunks: Vec<DiffHunk>,
+    /// First diff row in view; wrapped lines count once per row
     scroll_offset: usize,
-    git: GitDiff,
+    /// First content column in view when lines aren't wrapped
+    h_scroll: usize,
+    /// Wrap width `scroll_offset` was counted with
+    wrap_width: Option<usize>,
+    worker: Worker,
+    /// Whether the source can change underneath us (a repository, not a piped patch)
+    live: bool,
+    settings: DiffSettings,
+    files_reload: FilesReload,
+    diff_reload: DiffReload,
+    /// Scroll position whose surroundings were last sent for highlighting
+    highlight_window: Option<usize>,
+    /// The selected file is too large to highlight
+    highlight_skipped: bool,
+    syntax_themes: Vec<SyntaxTheme>,
+    syntax_theme: String,
+    /// UI colors as configured, before matching them to the syntax theme
+    theme: Theme,
+    /// Color the diff panel like the syntax theme's editor background
+    match_syntax_theme: bool,
     ui: Ui,
-    needs_full_redraw: bool,
+    /// Frame being drawn, and what the terminal shows
+    screen: Screen,
     mouse_enabled: bool,
     last_refresh: Instant,
+    /// Scroll position of the line the last move jump landed on
+    move_anchor: Option<usize>,
+    /// Feedback shown in the status bar until it expires
+    status_message: Option<(String, Instant)>,
+    /// File system watch; without one the repository is polled every `refresh_interval`
+    watcher: Option<RepoWatcher>,
+    refresh_interval: Duration,
+    max_commits: usize,
+    keymap: Keymap,
+    /// Panel boundary being dragged with the mouse
+    dragging: Option<Boundary>,
+    /// Diff lines picked with the mouse for copying or staging: the separator/line index
+    /// (as in `item_rows`) the drag started at and the one it is at now
+    selection: Option<(usize, usize)>,
+    /// The mouse button is down and extending `selection`
+    selecting: bool,
+    /// Time and cell of the last click, to tell a double-click
+    last_click: Option<(Instant, u16, u16)>,
+    /// Where the changed files are when the current directory is elsewhere in the repository
+    workdir: Option<PathBuf>,
 }
 
 impl App {
-    pub fn new(staged: bool, commit: Option<String>, context_lines: usize) -> Result<Self, git2::Error> {
-        let git = GitDiff::new(staged, commit, context_lines)?;
-        let current_branch = git.get_current_branch().unwrap_or("main").to_string();
-        let commits = git.load_commits_for_branch(&current_branch, MAX_COMMITS).unwrap_or_default();
-        let ui = Ui::new();
+    /// `open` creates the source on the worker thread, where all git and diff work happens
+    pub fn new<F>(open: F, watcher: Option<RepoWatcher>, options: AppOptions) -> Result<Self, git2::Error>
+    where
+        F: FnOnce() -> Result<Box<dyn DiffSource>, git2::Error> + Send + 'static,
+    {
+        let AppOptions { theme, match_syntax_theme, keymap, layout, line_format, wrap, refresh_interval, max_commits } = options;
+        let (worker, startup) = Worker::spawn(open, max_commits)?;
+        let Startup { current_branch, commits, live, settings, syntax_themes, syntax_theme, workdir } = startup;
+        let mut ui = Ui::new(theme.clone(), layout, line_format);
+        ui.set_wrap(wrap);
 
         let mut app = App {
             mode: AppMode::Normal,
@@ -62,15 +166,38 @@ impl App {
             file_scroll_offset: 0,
             diff_hunks: Vec::new(),
             scroll_offset: 0,
-            git,
+            h_scroll: 0,
+            wrap_width: ui.wrap_width(),
+            worker,
+            live,
+            settings,
+            files_reload: FilesReload::Select,
+            diff_reload: DiffReload::Select,
+            highlight_window: None,
+            highlight_skipped: false,
+            syntax_themes,
+            syntax_theme,
+            theme,
+            match_syntax_theme,
+            screen: Screen::new(ui.term_width, ui.term_height),
             ui,
-            needs_full_redraw: true,
             mouse_enabled: true,
             last_refresh: Instant::now(),
+            move_anchor: None,
+            status_message: None,
+            watcher,
+            refresh_interval,
+            max_commits,
+            keymap,
+            dragging: None,
+            selection: None,
+            selecting: false,
+            last_click: None,
+            workdir,
         };
 
-        app.load_files_for_selected_commit()?;
-
+        app.update_ui_theme();
+        app.load_files_for_selected_commit();
         Ok(app)
     }
 
@@ -78,207 +205,738 @@ impl App {
         !self.commits.is_empty()
     }
 
-    fn load_files_for_selected_commit(&mut self) -> Result<(), git2::Error> {
-        if self.commits.is_empty() {
+    fn load_files_for_selected_commit(&mut self) {
+        self.files.clear();
+        self.selected_file = 0;
+        self.file_scroll_offset = 0;
+        self.diff_hunks.clear();
+        self.request_files(FilesReload::Select);
+    }
+
+    /// Ask the worker for the files of the selected commit; any pending diff is now stale
+    fn request_files(&mut self, reload: FilesReload) {
+        self.worker.cancel(Slot::Diff);
+        self.worker.cancel(Slot::Highlight);
+        let Some(commit) = self.commits.get(self.selected_commit).cloned() else {
+            self.worker.cancel(Slot::Files);
             self.files.clear();
             self.diff_hunks.clear();
-            return Ok(());
+            return;
+        };
+        self.files_reload = reload;
+        self.worker.send(Request::Files { commit });
+    }
+
+    /// Re-diff with new algorithm/whitespace settings, keeping the selected file if it still has changes
+    fn apply_diff_settings(&mut self, settings: DiffSettings) {
+        self.settings = settings;
+        self.worker.send(Request::Settings(settings));
+        if self.commits.is_empty() {
+            return;
         }
 
-        let commit = &self.commits[self.selected_commit];
-        
-        if commit.is_local_changes {
-            self.files = self.git.load_files()?;
-        } else {
-            self.files = self.git.load_files_for_commit(&commit.sha)?;
+        let selected_path = self.files.get(self.selected_file).map(|f| f.path.clone());
+        self.diff_hunks.clear();
+        self.request_files(FilesReload::Settings(selected_path));
+    }
+
+    /// Switch to the next syntax theme and re-highlight the diff on screen with it
+    fn cycle_syntax_theme(&mut self) {
+        let Some(current) = self.syntax_themes.iter().position(|t| t.name == self.syntax_theme) else {
+            return;
+        };
+        let next = self.syntax_themes[(current + 1) % self.syntax_themes.len()].name.clone();
+
+        // Spans in flight were colored with the old theme
+        self.worker.cancel(Slot::Highlight);
+        self.worker.send(Request::SyntaxTheme(next.clone()));
+        for line in self.diff_hunks.iter_mut().flat_map(|h| h.lines.iter_mut()) {
+            line.highlighted = None;
         }
+        self.highlight_window = None;
+        self.syntax_theme = next;
+        self.update_ui_theme();
+        self.show_message(format!("Syntax theme: {}", self.syntax_theme));
+    }
 
-        self.selected_file = 0;
-        self.file_scroll_offset = 0;
-        self.load_diff_for_selected()?;
-        self.needs_full_redraw = true;
-        Ok(())
+    fn update_ui_theme(&mut self) {
+        if !self.match_syntax_theme {
+            return;
+        }
+        self.ui.theme = self.theme.clone();
+        if let Some(syntax) = self.syntax_themes.iter().find(|t| t.name == self.syntax_theme) {
+            self.ui.theme.match_syntax_theme(syntax);
+        }
     }
 
     fn refresh_if_needed(&mut self) {
-        if self.mode != AppMode::Normal {
+        if self.mode != AppMode::Normal || !self.live {
             return;
         }
-        
-        if self.last_refresh.elapsed().as_millis() < REFRESH_INTERVAL_MS {
+
+        if let Some(ref mut watcher) = self.watcher {
+            if let Some(changes) = watcher.poll() {
+                self.apply_changes(changes);
+            }
+            return;
+        }
+
+        if self.last_refresh.elapsed() < self.refresh_interval {
             return;
         }
         self.last_refresh = Instant::now();
+        self.apply_changes(Changes::everything());
+    }
+
+    /// Reload only what `changes` can have affected
+    fn apply_changes(&mut self, changes: Changes) {
+        let worktree_changed = changes.index || changes.rescan || !changes.worktree.is_empty();
+        let has_local_row = self.commits.first().is_some_and(|c| c.is_local_changes);
+
+        // The "Local Changes" row may need to appear, so commits are reloaded for worktree edits too.
+        // If they turn out to differ, the file request below is superseded.
+        if changes.refs || (worktree_changed && !has_local_row) {
+            self.reload_commits();
+        }
+
+        // Only refresh files/diff for local changes
+        if !worktree_changed || self.commits.is_empty() || !self.commits[self.selected_commit].is_local_changes {
+            return;
+        }
+        // Don't let a refresh supersede a selection that is still loading
+        if self.worker.is_pending(Slot::Files) || self.worker.is_pending(Slot::Diff) {
+            return;
+        }
+        self.request_files(FilesReload::Refresh(changes));
+    }
+
+    fn reload_commits(&mut self) {
+        self.worker.send(Request::Commits { branch: self.current_branch.clone(), limit: self.max_commits });
+    }
+
+    fn load_diff_for_selected(&mut self) {
+        self.diff_hunks.clear();
+        self.scroll_offset = 0;
+        self.h_scroll = 0;
+        self.selection = None;
+        self.move_anchor = None;
+        self.highlight_skipped = false;
+        self.request_diff(DiffReload::Select);
+    }
+
+    fn request_diff(&mut self, reload: DiffReload) {
+        self.worker.cancel(Slot::Highlight);
+        let (Some(commit), Some(file)) = (self.commits.get(self.selected_commit), self.files.get(self.selected_file)) else {
+            self.worker.cancel(Slot::Diff);
+            self.diff_hunks.clear();
+            return;
+        };
+        let request = Request::Diff { commit: commit.clone(), path: file.path.clone() };
+        self.diff_reload = reload;
+        self.worker.send(request);
+    }
 
-        // Reload commits for current branch
-        let new_commits = match self.git.load_commits_for_branch(&self.current_branch, MAX_COMMITS) {
-            Ok(c) => c,
-            Err(_) => return,
+    /// Ask for highlighting of the lines around the viewport that don't have it yet
+    fn request_highlight(&mut self) {
+        if self.mode != AppMode::Normal
+            || self.highlight_skipped
+            || self.highlight_window == Some(self.scroll_offset)
+            || self.worker.is_pending(Slot::Diff)
+            || self.worker.is_pending(Slot::Highlight)
+        {
+            return;
+        }
+        let (Some(commit), Some(file)) = (self.commits.get(self.selected_commit), self.files.get(self.selected_file)) else {
+            return;
         };
+        let (commit, path) = (commit.clone(), file.path.clone());
+
+        // A page either side, so scrolling doesn't show uncolored lines
+        let visible = self.ui.diff_rows();
+        let window = self.scroll_offset.saturating_sub(visible)..self.scroll_offset + 2 * visible;
+        let lines: Vec<LineRef> = self
+            .positioned_lines()
+            .into_iter()
+            .filter(|(pos, line)| window.contains(pos) && line.highlighted.is_none())
+            .map(|(_, line)| (line.old_num, line.new_num))
+            .collect();
+
+        self.highlight_window = Some(self.scroll_offset);
+        if !lines.is_empty() {
+            self.worker.send(Request::Highlight { commit, path, lines });
+        }
+    }
 
+    /// Apply the results the worker has finished so far
+    fn process_responses(&mut self) {
+        while let Some(response) = self.worker.try_recv() {
+            match response {
+                Response::Commits(result) => self.receive_commits(result.unwrap_or_default()),
+                Response::Branches(result) => {
+                    self.branches = result.unwrap_or_default();
+                    self.selected_branch = self.branches.iter().position(|b| b.is_current).unwrap_or(0);
+                }
+                Response::Files(result) => self.receive_files(result),
+                Response::Diff(result) => self.receive_diff(result),
+                Response::Highlights { lines, result } => match result {
+                    Ok(Highlights::Lines(spans)) => highlighter::apply_highlights(&mut self.diff_hunks, &lines, spans),
+                    Ok(Highlights::TooLarge) => self.highlight_skipped = true,
+                    Err(_) => {}
+                },
+                Response::Context { hunk, result } => self.receive_context(hunk, result.unwrap_or_default()),
+                Response::Staged { path, result } => {
+                    let staged = matches!(result, Ok(count) if count > 0);
+                    self.show_message(match result {
+                        Ok(0) => format!("Nothing left to stage in {}", path),
+                        Ok(1) => format!("Staged 1 line of {}", path),
+                        Ok(count) => format!("Staged {} lines of {}", count, path),
+                        Err(e) => format!("Staging failed: {}", e.message()),
+                    });
+                    self.selection = None;
+                    // Reload now rather than waiting for the watcher to notice the index write
+                    if staged {
+                        self.request_files(FilesReload::Refresh(Changes::everything()));
+                    }
+                }
+                Response::Exported(message) => self.show_message(message),
+            }
+        }
+    }
+
+    fn receive_commits(&mut self, new_commits: Vec<CommitInfo>) {
         let commits_changed = new_commits.len() != self.commits.len()
             || new_commits.iter().zip(self.commits.iter()).any(|(a, b)| a.sha != b.sha || a.is_local_changes != b.is_local_changes);
 
         if commits_changed {
             self.commits = new_commits;
             self.selected_commit = self.selected_commit.min(self.commits.len().saturating_sub(1));
-            let _ = self.load_files_for_selected_commit();
+            self.commit_scroll_offset = keep_in_view(self.commit_scroll_offset, self.selected_commit, self.ui.commit_rows());
+            self.load_files_for_selected_commit();
+        }
+    }
+
+    fn receive_files(&mut self, result: Result<Vec<FileChange>, git2::Error>) {
+        let reload = std::mem::replace(&mut self.files_reload, FilesReload::Select);
+        match reload {
+            FilesReload::Select => {
+                self.files = result.unwrap_or_default();
+                self.selected_file = 0;
+                self.file_scroll_offset = 0;
+                self.load_diff_for_selected();
+            }
+            FilesReload::Settings(selected_path) => {
+                self.files = result.unwrap_or_default();
+                self.selected_file = selected_path
+                    .and_then(|path| self.files.iter().position(|f| f.path == path))
+                    .unwrap_or(0);
+                self.file_scroll_offset = self.file_scroll_offset.min(self.selected_file);
+                self.load_diff_for_selected();
+            }
+            FilesReload::Refresh(changes) => {
+                let Ok(new_files) = result else {
+                    return;
+                };
+
+                if new_files.is_empty() {
+                    // Everything was committed or reverted: drop the "Local Changes" row
+                    self.reload_commits();
+                    return;
+                }
+
+                let files_changed = new_files.len() != self.files.len()
+                    || new_files.iter().zip(self.files.iter()).any(|(a, b)| a.path != b.path);
+
+                if files_changed {
+                    self.files = new_files;
+                    self.selected_file = self.selected_file.min(self.files.len().saturating_sub(1));
+                    self.file_scroll_offset = keep_in_view(self.file_scroll_offset, self.selected_file, self.ui.file_rows());
+                }
+
+                let file_path = &self.files[self.selected_file].path;
+                if files_changed || changes.touches(file_path) {
+                    self.request_diff(DiffReload::Refresh);
+                }
+            }
+        }
+    }
+
+    fn receive_diff(&mut self, result: Result<Vec<DiffHunk>, git2::Error>) {
+        let reload = std::mem::replace(&mut self.diff_reload, DiffReload::Select);
+        let Ok(new_hunks) = result else {
+            return;
+        };
+        // An unchanged refresh keeps the selection and highlights; a jump still has to scroll
+        if matches!(reload, DiffReload::Refresh) && new_hunks == self.diff_hunks {
             return;
         }
+        self.diff_hunks = new_hunks;
+        self.highlight_window = None;
+        self.selection = None;
 
-        // Only refresh files/diff for local changes
-        if !self.commits.is_empty() && self.commits[self.selected_commit].is_local_changes {
-            let new_files = match self.git.load_files() {
-                Ok(f) => f,
-                Err(_) => return,
-            };
+        match reload {
+            DiffReload::Select => {}
+            DiffReload::Refresh => {
+                let visible = self.ui.diff_rows();
+                let max_scroll = self.total_diff_lines().saturating_sub(visible);
+                self.scroll_offset = self.scroll_offset.min(max_scroll);
+            }
+            DiffReload::Jump(tag, target) => self.scroll_to_move_target(tag, &target),
+        }
+    }
+
+    /// Fill in the lines between hunk `hunk` and the one above it
+    fn expand_context(&mut self, hunk: usize) {
+        if hunk == 0 || self.worker.is_pending(Slot::Diff) {
+            return;
+        }
+        let (Some(commit), Some(file)) = (self.commits.get(self.selected_commit), self.files.get(self.selected_file)) else {
+            return;
+        };
+        let (above, below) = (&self.diff_hunks[hunk - 1], &self.diff_hunks[hunk]);
+        // Notices above have no line numbers to continue from
+        let last_old = above.lines.iter().filter_map(|line| line.old_num).max();
+        let last_new = above.lines.iter().filter_map(|line| line.new_num).max();
+        let (Some(last_old), Some(last_new)) = (last_old, last_new) else {
+            return;
+        };
+        // Without a number on a side, the hunk below runs to the end of the file on it
+        let next_old = below.lines.iter().find_map(|line| line.old_num);
+        let next_new = below.lines.iter().find_map(|line| line.new_num);
+        let request = Request::Context {
+            commit: commit.clone(),
+            path: file.path.clone(),
+            hunk,
+            from: (Some(last_old + 1), Some(last_new + 1)),
+            to: (next_old, next_new),
+        };
+        self.worker.send(request);
+    }
 
-            let files_changed = new_files.len() != self.files.len()
-                || new_files.iter().zip(self.files.iter()).any(|(a, b)| a.path != b.path);
+    /// Add `lines` of the gap above hunk `hunk` to the hunks around it, joining them when
+    /// the whole gap fits
+    fn receive_context(&mut self, hunk: usize, mut lines: Vec<DiffLine>) {
+        if lines.is_empty() || hunk == 0 || hunk >= self.diff_hunks.len() {
+            return;
+        }
+        if lines.len() <= 2 * CONTEXT_STEP {
+            let below = self.diff_hunks.remove(hunk);
+            let above = &mut self.diff_hunks[hunk - 1];
+            above.lines.append(&mut lines);
+            above.lines.extend(below.lines);
+        } else {
+            let tail = lines.split_off(lines.len() - CONTEXT_STEP);
+            lines.truncate(CONTEXT_STEP);
+            self.diff_hunks[hunk - 1].lines.append(&mut lines);
+            self.diff_hunks[hunk].lines.splice(0..0, tail);
+        }
+        self.selection = None;
+        self.highlight_window = None;
+    }
 
-            if files_changed {
-                self.files = new_files;
-                self.selected_file = self.selected_file.min(self.files.len().saturating_sub(1));
-                self.needs_full_redraw = true;
+    /// Diff lines with their scroll positions (each hunk starts with a separator row)
+    fn positioned_lines(&self) -> Vec<(usize, &DiffLine)> {
+        let mut result = Vec::new();
+        let mut pos = 0;
+        for hunk in &self.diff_hunks {
+            pos += 1;
+            for line in &hunk.lines {
+                result.push((pos, line));
+                pos += self.ui.line_rows(line, self.wrap_width);
             }
+        }
+        result
+    }
 
-            if !self.files.is_empty() {
-                let file_path = self.files[self.selected_file].path.clone();
-                if let Ok(new_hunks) = self.git.load_diff_for_file(&file_path) {
-                    if new_hunks != self.diff_hunks {
-                        self.diff_hunks = new_hunks;
-                        self.needs_full_redraw = true;
-                    }
+    /// Scroll position of the line numbered `number` in the new file (the old one for
+    /// deleted lines), or of the shown line numbered closest to it
+    fn line_number_row(&self, number: usize) -> Option<usize> {
+        self.positioned_lines()
+            .into_iter()
+            .filter_map(|(pos, line)| Some((pos, line.new_num.or(line.old_num)? as usize)))
+            .min_by_key(|(_, num)| num.abs_diff(number))
+            .map(|(pos, _)| pos)
+    }
+
+    /// Rows of each hunk separator and diff line, in order
+    fn item_rows(&self) -> impl Iterator<Item = usize> + '_ {
+        self.diff_hunks.iter().flat_map(|hunk| {
+            std::iter::once(1).chain(hunk.lines.iter().map(|line| self.ui.line_rows(line, self.wrap_width)))
+        })
+    }
+
+    /// Separator or line (index as in `item_rows`) on screen row `row` of the diff panel;
+    /// rows above or below the lines give the first or last one in view
+    fn item_at(&self, row: u16) -> Option<usize> {
+        let offset = (row.saturating_sub(self.ui.diff_area.y + 1) as usize).min(self.ui.diff_rows().saturating_sub(1));
+        // The first hunk has no separator, so an unscrolled diff starts with its first line
+        let target = self.scroll_offset.max(1) + offset;
+        let mut start = 0;
+        let mut last = None;
+        for (item, rows) in self.item_rows().enumerate() {
+            if target < start + rows {
+                return Some(item);
+            }
+            start += rows;
+            last = Some(item);
+        }
+        last
+    }
+
+    /// Hunk of separator or line `item`, and the line's index in it (`None` for the separator)
+    fn locate_item(&self, mut item: usize) -> Option<(usize, Option<usize>)> {
+        for (idx, hunk) in self.diff_hunks.iter().enumerate() {
+            if item <= hunk.lines.len() {
+                return Some((idx, item.checked_sub(1)));
+            }
+            item -= hunk.lines.len() + 1;
+        }
+        None
+    }
+
+    fn selection_range(&self) -> Option<RangeInclusive<usize>> {
+        self.selection.map(|(anchor, end)| anchor.min(end)..=anchor.max(end))
+    }
+
+    /// Lines of the mouse selection, without separators and notices
+    fn selected_lines(&self) -> Vec<&DiffLine> {
+        let Some(range) = self.selection_range() else {
+            return Vec::new();
+        };
+        self.diff_hunks
+            .iter()
+            .flat_map(|hunk| std::iter::once(None).chain(hunk.lines.iter().map(Some)))
+            .enumerate()
+            .filter(|(item, _)| range.contains(item))
+            .filter_map(|(_, line)| line.filter(|line| !line.is_notice()))
+            .collect()
+    }
+
+    /// Move the end of the selection to screen row `row`, scrolling when it is dragged
+    /// past the top or bottom of the diff
+    fn extend_selection(&mut self, row: u16) {
+        let area = self.ui.diff_area;
+        if row <= area.y {
+            self.scroll_to(self.scroll_offset.saturating_sub(1));
+        } else if (row - area.y - 1) as usize >= self.ui.diff_rows() {
+            self.scroll_to(self.scroll_offset + 1);
+        }
+        if let (Some((anchor, _)), Some(item)) = (self.selection, self.item_at(row)) {
+            self.selection = Some((anchor, item));
+        }
+    }
+
+    /// Stage the changed lines of the mouse selection
+    fn stage_selection(&mut self) {
+        let (Some(commit), Some(file)) = (self.commits.get(self.selected_commit), self.files.get(self.selected_file)) else {
+            return;
+        };
+        if !commit.is_local_changes {
+            self.show_message("Only local changes can be staged".to_string());
+            return;
+        }
+        let path = file.path.clone();
+        let lines: Vec<LineRef> = self
+            .selected_lines()
+            .into_iter()
+            .filter(|line| line.tag != ChangeTag::Equal)
+            .map(|line| (line.old_num, line.new_num))
+            .collect();
+        if lines.is_empty() {
+            self.show_message("Drag over changed lines to select them for staging".to_string());
+            return;
+        }
+        self.worker.send(Request::StageLines { path, lines });
+    }
+
+    /// Index of the hunk the mouse selection starts in, or else of the first one in view
+    /// with lines of the file
+    fn current_hunk(&self) -> Option<usize> {
+        let item = self.selection.map_or_else(|| self.top_item(), |(anchor, _)| anchor);
+        let (hunk, _) = self.locate_item(item)?;
+        (hunk..self.diff_hunks.len()).find(|&idx| !self.diff_hunks[idx].lines.iter().all(DiffLine::is_notice))
+    }
+
+    /// Put what a copy action names on the clipboard
+    fn copy(&mut self, action: Action, stdout: &mut io::Stdout) -> io::Result<()> {
+        let commit = self.commits.get(self.selected_commit);
+        let file = self.files.get(self.selected_file);
+        let copied = match action {
+            Action::CopyPath => file.map(|file| (file.path.clone(), "path".to_string())),
+            Action::CopySha => match commit {
+                Some(commit) if !commit.is_local_changes && !commit.sha.is_empty() => {
+                    Some((commit.sha.clone(), commit.short_sha.clone()))
+                }
+                _ => {
+                    self.show_message("Local changes have no commit SHA".to_string());
+                    return Ok(());
+                }
+            },
+            Action::CopyHunk => file.zip(self.current_hunk()).map(|(file, hunk)| {
+                let file = ExportFile { change: file.clone(), hunks: self.diff_hunks.clone() };
+                (export::hunk_patch(&file, hunk), "hunk as a patch".to_string())
+            }),
+            _ => {
+                let lines = self.selected_lines();
+                if lines.is_empty() {
+                    self.show_message("Drag over diff lines to select them for copying".to_string());
+                    return Ok(());
                 }
+                let text: String = lines.iter().map(|line| format!("{}\n", line.content)).collect();
+                let what = if lines.len() == 1 { "1 line".to_string() } else { format!("{} lines", lines.len()) };
+                Some((text, what))
             }
+        };
+        if let Some((text, what)) = copied {
+            clipboard::copy(stdout, &text)?;
+            self.show_message(format!("Copied {}", what));
         }
+        Ok(())
     }
 
-    fn load_diff_for_selected(&mut self) -> Result<(), git2::Error> {
-        if self.files.is_empty() {
-            self.diff_hunks.clear();
+    /// New-side number of the line the mouse selection starts at, or else of the first line
+    /// in view; a deleted line goes by the line after it
+    fn cursor_line(&self) -> Option<u32> {
+        let start = self.selection.map_or_else(|| self.top_item(), |(anchor, _)| anchor);
+        self.diff_hunks
+            .iter()
+            .flat_map(|hunk| std::iter::once(None).chain(hunk.lines.iter().map(Some)))
+            .skip(start)
+            .flatten()
+            .find_map(|line| line.new_num)
+    }
+
+    /// Hand the terminal to $VISUAL or $EDITOR on the selected file, at `line` when given,
+    /// then show what changed
+    fn open_in_editor(&mut self, stdout: &mut io::Stdout, line: Option<u32>) -> io::Result<()> {
+        let Some(file) = self.files.get(self.selected_file) else {
             return Ok(());
-        }
+        };
+        let path = match self.workdir {
+            Some(ref dir) => dir.join(&file.path),
+            None => PathBuf::from(&file.path),
+        };
+        let editor = ["VISUAL", "EDITOR"]
+            .iter()
+            .find_map(|name| std::env::var(name).ok().filter(|value| !value.trim().is_empty()))
+            .unwrap_or_else(|| "vi".to_string());
+        // The variable can carry arguments, like `code --wait`
+        let mut words = editor.split_whitespace();
+        let program = words.next().unwrap_or("vi");
 
-        let file_path = self.files[self.selected_file].path.clone();
-        let commit = &self.commits[self.selected_commit];
+        execute!(stdout, Show, DisableMouseCapture, LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()?;
+        let status = Command::new(program).args(words).args(editor_args(program, &path, line)).status();
+        terminal::enable_raw_mode()?;
+        execute!(stdout, EnterAlternateScreen, Hide)?;
+        if self.mouse_enabled {
+            execute!(stdout, EnableMouseCapture)?;
+        }
+        self.screen.invalidate();
+        // The terminal may have been resized while the editor had it
+        if let Ok((width, height)) = terminal::size() {
+            if (width, height) != (self.ui.term_width, self.ui.term_height) {
+                self.handle_resize(width, height);
+            }
+        }
 
-        if commit.is_local_changes {
-            self.diff_hunks = self.git.load_diff_for_file(&file_path)?;
-        } else {
-            self.diff_hunks = self.git.load_diff_for_commit_file(&commit.sha, &file_path)?;
+        match status {
+            Ok(status) if !status.success() => self.show_message(format!("{} exited with {}", program, status)),
+            Err(e) => self.show_message(format!("Couldn't run {}: {}", program, e)),
+            Ok(_) => {}
+        }
+        if self.live {
+            self.request_files(FilesReload::Refresh(Changes::everything()));
         }
-        
-        self.scroll_offset = 0;
-        self.needs_full_redraw = true;
         Ok(())
     }
 
-    fn draw(&mut self, stdout: &mut io::Stdout) -> io::Result<()> {
-        if self.needs_full_redraw {
-            execute!(stdout, Clear(ClearType::All))?;
-            self.needs_full_redraw = false;
+    /// Jump from a moved line in view to the other side of the move
+    fn jump_to_move_counterpart(&mut self) {
+        let visible = self.ui.diff_rows();
+        let view = self.scroll_offset..self.scroll_offset + visible;
+        let start = self.move_anchor.filter(|a| view.contains(a)).unwrap_or(self.scroll_offset);
+
+        let found = self
+            .positioned_lines()
+            .into_iter()
+            .find(|(pos, line)| *pos >= start && view.contains(pos) && line.moved.is_some())
+            .and_then(|(_, line)| line.moved.clone().map(|m| (line.tag, m)));
+        let Some((tag, target)) = found else {
+            return;
+        };
+
+        let current_path = self.files.get(self.selected_file).map(|f| f.path.as_str());
+        if current_path != Some(target.path.as_str()) {
+            let Some(idx) = self.files.iter().position(|f| f.path == target.path) else {
+                return;
+            };
+            self.selected_file = idx;
+            if self.selected_file < self.file_scroll_offset {
+                self.file_scroll_offset = self.selected_file;
+            }
+            let visible_files = self.ui.file_rows();
+            if self.selected_file >= self.file_scroll_offset + visible_files {
+                self.file_scroll_offset = self.selected_file - visible_files + 1;
+            }
+            self.load_diff_for_selected();
+            self.diff_reload = DiffReload::Jump(tag, target);
+            return;
         }
-        execute!(stdout, MoveTo(0, 0))?;
+
+        self.scroll_to_move_target(tag, &target);
+    }
+
+    /// Scroll to the line `target` points at, on the opposite side of a `tag` line
+    fn scroll_to_move_target(&mut self, tag: ChangeTag, target: &MoveInfo) {
+        let visible = self.ui.diff_rows();
+        let target_pos = self.positioned_lines().into_iter().find(|(_, line)| match tag {
+            ChangeTag::Delete => line.tag == ChangeTag::Insert && line.new_num == Some(target.line),
+            _ => line.tag == ChangeTag::Delete && line.old_num == Some(target.line),
+        });
+        if let Some((pos, _)) = target_pos {
+            let max_scroll = self.total_diff_lines().saturating_sub(visible);
+            self.scroll_offset = pos.saturating_sub(3).min(max_scroll);
+            self.move_anchor = Some(pos);
+        }
+    }
+
+    fn draw(&mut self, stdout: &mut io::Stdout) -> io::Result<()> {
+        self.screen.clear();
 
         match self.mode {
             AppMode::Normal => {
-                self.ui.draw_commit_panel(stdout, &self.commits, self.selected_commit, self.commit_scroll_offset, &self.current_branch)?;
-                self.ui.draw_file_panel(stdout, &self.files, self.selected_file, self.file_scroll_offset)?;
-                self.ui.draw_separator(stdout)?;
+                if self.ui.shows_lists() {
+                    self.ui.draw_commit_panel(&mut self.screen, &self.commits, self.selected_commit, self.commit_scroll_offset, &self.current_branch)?;
+                    self.ui.draw_file_panel(&mut self.screen, &self.files, self.selected_file, self.file_scroll_offset)?;
+                    self.ui.draw_separator(&mut self.screen)?;
+                }
 
-                let file_name = if !self.files.is_empty() {
-                    &self.files[self.selected_file].path
-                } else {
-                    "No files"
+                let file_name = match self.files.get(self.selected_file) {
+                    Some(file) if self.highlight_skipped => format!("{}  (too large to highlight)", file.path),
+                    Some(file) => file.path.clone(),
+                    None => "No files".to_string(),
                 };
-                self.ui.draw_diff_panel(stdout, file_name, &self.diff_hunks, self.scroll_offset)?;
+                let loading = self.worker.is_pending(Slot::Files) || self.worker.is_pending(Slot::Diff);
+                let selection = self.selection_range();
+                self.ui.draw_diff_panel(&mut self.screen, &file_name, &self.diff_hunks, self.scroll_offset, self.h_scroll, selection, loading)?;
                 
                 let total = self.total_diff_lines();
-                let visible = (self.ui.term_height - 3) as usize;
-                self.ui.draw_status_bar(stdout, self.scroll_offset, total, visible, self.mouse_enabled, &self.current_branch)?;
+                let visible = self.ui.diff_rows();
+                let pending = self.keymap.pending();
+                let mut diff_mode = self.settings.label();
+                if self.ui.is_wrapping() {
+                    diff_mode.push_str(" wrap");
+                }
+                if !pending.is_empty() {
+                    diff_mode = format!("{} │ {}", pending, diff_mode);
+                }
+                if self.status_message.as_ref().is_some_and(|(_, at)| at.elapsed() > STATUS_MESSAGE_DURATION) {
+                    self.status_message = None;
+                }
+                let message = self.status_message.as_ref().map(|(m, _)| m.as_str());
+                self.ui.draw_status_bar(&mut self.screen, self.scroll_offset, total, visible, self.mouse_enabled, &diff_mode, message)?;
             }
             AppMode::BranchSelect => {
-                self.ui.draw_branch_panel(stdout, &self.branches, self.selected_branch, self.branch_scroll_offset)?;
+                self.ui.draw_branch_panel(&mut self.screen, &self.branches, self.selected_branch, self.branch_scroll_offset)?;
+            }
+            AppMode::Help => {
+                self.ui.draw_help(&mut self.screen, &self.keymap.help())?;
             }
         }
 
-        stdout.flush()
+        self.screen.render(stdout)
+    }
+
+    fn show_message(&mut self, message: String) {
+        self.status_message = Some((message, Instant::now()));
+    }
+
+    /// Write the selected commit (or local changes) as a format-patch file in the current directory
+    fn export_patch(&mut self) {
+        let Some(commit) = self.commits.get(self.selected_commit).cloned() else {
+            return;
+        };
+        let file_name = export::patch_file_name(&commit);
+        self.worker.send(Request::ExportPatch { commit, file_name });
+    }
+
+    /// Write the selected commit (or local changes) as an HTML report in the current directory
+    fn export_html(&mut self) {
+        let Some(commit) = self.commits.get(self.selected_commit).cloned() else {
+            return;
+        };
+        let file_name = export::html_file_name(&commit);
+        self.worker.send(Request::ExportHtml { commit, file_name, theme: self.ui.theme.clone() });
     }
 
     fn enter_branch_mode(&mut self) {
-        self.branches = self.git.load_branches().unwrap_or_default();
-        self.selected_branch = self.branches.iter().position(|b| b.is_current).unwrap_or(0);
+        self.branches.clear();
+        self.worker.send(Request::Branches);
+        self.selected_branch = 0;
         self.branch_scroll_offset = 0;
         self.mode = AppMode::BranchSelect;
-        self.needs_full_redraw = true;
     }
 
     fn select_branch(&mut self) {
         if let Some(branch) = self.branches.get(self.selected_branch) {
             self.current_branch = branch.name.clone();
-            self.commits = self.git.load_commits_for_branch(&self.current_branch, MAX_COMMITS).unwrap_or_default();
+            self.commits.clear();
             self.selected_commit = 0;
             self.commit_scroll_offset = 0;
-            let _ = self.load_files_for_selected_commit();
+            self.load_files_for_selected_commit();
+            self.reload_commits();
         }
         self.mode = AppMode::Normal;
-        self.needs_full_redraw = true;
     }
 
     fn cancel_branch_mode(&mut self) {
         self.mode = AppMode::Normal;
-        self.needs_full_redraw = true;
     }
 
-    fn select_prev_commit(&mut self) -> Result<(), git2::Error> {
+    fn select_prev_commit(&mut self) {
         if self.selected_commit > 0 {
             self.selected_commit -= 1;
             // Scroll up if needed
             if self.selected_commit < self.commit_scroll_offset {
                 self.commit_scroll_offset = self.selected_commit;
             }
-            self.load_files_for_selected_commit()?;
+            self.load_files_for_selected_commit();
         }
-        Ok(())
     }
 
-    fn select_next_commit(&mut self) -> Result<(), git2::Error> {
+    fn select_next_commit(&mut self) {
         if self.selected_commit < self.commits.len().saturating_sub(1) {
             self.selected_commit += 1;
             // Scroll down if needed
-            let visible_commits = (self.ui.commit_panel_height - 1) as usize;
+            let visible_commits = self.ui.commit_rows();
             if self.selected_commit >= self.commit_scroll_offset + visible_commits {
                 self.commit_scroll_offset = self.selected_commit - visible_commits + 1;
             }
-            self.load_files_for_selected_commit()?;
+            self.load_files_for_selected_commit();
         }
-        Ok(())
     }
 
-    fn select_prev_file(&mut self) -> Result<(), git2::Error> {
+    fn select_prev_file(&mut self) {
         if self.selected_file > 0 {
             self.selected_file -= 1;
             // Scroll up if needed
             if self.selected_file < self.file_scroll_offset {
                 self.file_scroll_offset = self.selected_file;
             }
-            self.load_diff_for_selected()?;
+            self.load_diff_for_selected();
         }
-        Ok(())
     }
 
-    fn select_next_file(&mut self) -> Result<(), git2::Error> {
+    fn select_next_file(&mut self) {
         if self.selected_file < self.files.len().saturating_sub(1) {
             self.selected_file += 1;
             // Scroll down if needed
-            let visible_files = (self.ui.term_height - self.ui.commit_panel_height - 2) as usize;
+            let visible_files = self.ui.file_rows();
             if self.selected_file >= self.file_scroll_offset + visible_files {
                 self.file_scroll_offset = self.selected_file - visible_files + 1;
             }
-            self.load_diff_for_selected()?;
+            self.load_diff_for_selected();
         }
-        Ok(())
     }
 
     fn scroll_up(&mut self) {
@@ -286,25 +944,339 @@ impl App {
     }
 
     fn scroll_down(&mut self) {
-        let total_lines: usize = self.diff_hunks.iter().map(|h| h.lines.len() + 1).sum();
-        let max_scroll = total_lines.saturating_sub((self.ui.term_height - 3) as usize);
+        let max_scroll = self.total_diff_lines().saturating_sub(self.ui.diff_rows());
         self.scroll_offset = (self.scroll_offset + 3).min(max_scroll);
     }
 
+    fn scroll_left(&mut self) {
+        self.h_scroll = self.h_scroll.saturating_sub(HORIZONTAL_STEP);
+    }
+
+    /// Until the end of the longest line is in view; wrapped lines have nothing to scroll to
+    fn scroll_right(&mut self) {
+        if self.ui.is_wrapping() {
+            return;
+        }
+        let widest = self.diff_hunks.iter().flat_map(|h| &h.lines).map(|line| self.ui.line_width(line)).max().unwrap_or(0);
+        let max_scroll = widest.saturating_sub(self.ui.diff_content_width());
+        self.h_scroll = (self.h_scroll + HORIZONTAL_STEP).min(max_scroll.max(self.h_scroll));
+    }
+
     fn page_up(&mut self) {
-        let page_size = (self.ui.term_height - 4) as usize;
+        let page_size = self.ui.diff_rows().saturating_sub(1);
         self.scroll_offset = self.scroll_offset.saturating_sub(page_size);
     }
 
     fn page_down(&mut self) {
-        let total_lines: usize = self.diff_hunks.iter().map(|h| h.lines.len() + 1).sum();
-        let max_scroll = total_lines.saturating_sub((self.ui.term_height - 3) as usize);
-        let page_size = (self.ui.term_height - 4) as usize;
+        let max_scroll = self.total_diff_lines().saturating_sub(self.ui.diff_rows());
+        let page_size = self.ui.diff_rows().saturating_sub(1);
         self.scroll_offset = (self.scroll_offset + page_size).min(max_scroll);
     }
 
+    fn half_page_up(&mut self) {
+        let half_page = self.ui.diff_rows() / 2;
+        self.scroll_offset = self.scroll_offset.saturating_sub(half_page);
+    }
+
+    fn half_page_down(&mut self) {
+        let half_page = self.ui.diff_rows() / 2;
+        self.scroll_to(self.scroll_offset + half_page);
+    }
+
+    /// Scroll so `row` is at the top, or as close as the end of the diff allows
+    fn scroll_to(&mut self, row: usize) {
+        let max_scroll = self.total_diff_lines().saturating_sub(self.ui.diff_rows());
+        self.scroll_offset = row.min(max_scroll);
+    }
+
+    /// Rows of the hunk separators
+    fn hunk_starts(&self) -> Vec<usize> {
+        let mut pos = 0;
+        self.diff_hunks
+            .iter()
+            .map(|hunk| {
+                let start = pos;
+                pos += 1 + hunk.lines.iter().map(|line| self.ui.line_rows(line, self.wrap_width)).sum::<usize>();
+                start
+            })
+            .collect()
+    }
+
+    fn prev_hunk(&mut self) {
+        if let Some(start) = self.hunk_starts().into_iter().rev().find(|start| *start < self.scroll_offset) {
+            self.scroll_to(start);
+        }
+    }
+
+    fn next_hunk(&mut self) {
+        if let Some(start) = self.hunk_starts().into_iter().find(|start| *start > self.scroll_offset) {
+            self.scroll_to(start);
+        }
+    }
+
+    /// Index in `item_rows` of the separator or line at the top of the view
+    fn top_item(&self) -> usize {
+        let mut row = 0;
+        self.item_rows()
+            .take_while(|rows| {
+                row += rows;
+                row <= self.scroll_offset
+            })
+            .count()
+    }
+
+    /// Rows the whole diff takes
     fn total_diff_lines(&self) -> usize {
-        self.diff_hunks.iter().map(|h| h.lines.len() + 1).sum()
+        self.item_rows().sum()
+    }
+
+    /// Run `action` in the normal view, `count` times for movements; returns false when the app should quit
+    fn handle_action(&mut self, action: Action, count: Option<usize>, stdout: &mut io::Stdout) -> io::Result<bool> {
+        let times = count.unwrap_or(1);
+        match action {
+            Action::Quit => return Ok(false),
+            Action::Help => self.mode = AppMode::Help,
+            Action::Branches => self.enter_branch_mode(),
+            Action::PrevCommit => (0..times).for_each(|_| self.select_prev_commit()),
+            Action::NextCommit => (0..times).for_each(|_| self.select_next_commit()),
+            Action::SelectPrev => (0..times).for_each(|_| self.select_prev_file()),
+            Action::SelectNext => (0..times).for_each(|_| self.select_next_file()),
+            Action::ScrollUp => (0..times).for_each(|_| self.scroll_up()),
+            Action::ScrollDown => (0..times).for_each(|_| self.scroll_down()),
+            Action::ScrollLeft => (0..times).for_each(|_| self.scroll_left()),
+            Action::ScrollRight => (0..times).for_each(|_| self.scroll_right()),
+            Action::HalfPageUp => (0..times).for_each(|_| self.half_page_up()),
+            Action::HalfPageDown => (0..times).for_each(|_| self.half_page_down()),
+            Action::PageUp => (0..times).for_each(|_| self.page_up()),
+            Action::PageDown => (0..times).for_each(|_| self.page_down()),
+            Action::GoToTop => self.scroll_to(count.and_then(|line| self.line_number_row(line)).unwrap_or(0)),
+            Action::GoToBottom => self.scroll_to(count.and_then(|line| self.line_number_row(line)).unwrap_or(usize::MAX)),
+            Action::PrevHunk => (0..times).for_each(|_| self.prev_hunk()),
+            Action::NextHunk => (0..times).for_each(|_| self.next_hunk()),
+            Action::JumpToMove => self.jump_to_move_counterpart(),
+            Action::ExportPatch => self.export_patch(),
+            Action::ExportHtml => self.export_html(),
+            Action::StageLines => self.stage_selection(),
+            Action::CopyPath | Action::CopySha | Action::CopyHunk | Action::CopyLines => self.copy(action, stdout)?,
+            Action::OpenInEditor => self.open_in_editor(stdout, self.cursor_line())?,
+            Action::CycleAlgorithm => {
+                let mut settings = self.settings;
+                settings.algorithm = settings.algorithm.next();
+                self.apply_diff_settings(settings);
+            }
+            Action::CycleWhitespace => {
+                let mut settings = self.settings;
+                settings.whitespace = settings.whitespace.next();
+                self.apply_diff_settings(settings);
+            }
+            Action::CycleSyntaxTheme => self.cycle_syntax_theme(),
+            Action::ToggleMouse => {
+                self.mouse_enabled = !self.mouse_enabled;
+                if self.mouse_enabled {
+                    execute!(stdout, EnableMouseCapture)?;
+                } else {
+                    execute!(stdout, DisableMouseCapture)?;
+                }
+            }
+            Action::ToggleWhitespace => {
+                // Glyphs and markers can change how many rows wrapped lines take
+                let top = self.top_item();
+                self.ui.set_show_whitespace(!self.ui.is_showing_whitespace());
+                self.scroll_to(self.item_rows().take(top).sum());
+            }
+            Action::ToggleWrap => {
+                self.ui.set_wrap(!self.ui.is_wrapping());
+                self.handle_layout_change();
+            }
+            Action::Zoom => {
+                self.ui.set_zoomed(!self.ui.is_zoomed());
+                self.handle_layout_change();
+            }
+            Action::ToggleStacked => {
+                self.ui.toggle_stacked();
+                self.handle_layout_change();
+                self.save_layout();
+            }
+            Action::ShrinkLists | Action::GrowLists | Action::ShrinkCommits | Action::GrowCommits => {
+                let (boundary, step) = match action {
+                    Action::ShrinkLists => (Boundary::Lists, -1),
+                    Action::GrowLists => (Boundary::Lists, 1),
+                    Action::ShrinkCommits => (Boundary::Commits, -1),
+                    _ => (Boundary::Commits, 1),
+                };
+                self.ui.move_boundary(boundary, step * times as i32);
+                self.handle_layout_change();
+                self.save_layout();
+            }
+            Action::ToggleDrawer => {
+                self.ui.set_drawer_open(!self.ui.is_drawer_open());
+                self.handle_layout_change();
+            }
+            // Picking from the drawer folds it away again
+            Action::Confirm | Action::Cancel if self.ui.is_drawer_open() => {
+                self.ui.set_drawer_open(false);
+                self.handle_layout_change();
+            }
+            Action::Cancel => self.selection = None,
+            Action::Confirm => {}
+        }
+        Ok(true)
+    }
+
+    /// Keys in the branch list: move the selection, pick a branch, or leave
+    fn handle_branch_action(&mut self, action: Action, count: Option<usize>) {
+        let last = self.branches.len().saturating_sub(1);
+        match action {
+            Action::Quit | Action::Cancel | Action::Branches => self.cancel_branch_mode(),
+            Action::SelectPrev => self.select_branch_at(self.selected_branch.saturating_sub(count.unwrap_or(1))),
+            Action::SelectNext => self.select_branch_at((self.selected_branch + count.unwrap_or(1)).min(last)),
+            Action::GoToTop => self.select_branch_at(count.map_or(0, |n| (n - 1).min(last))),
+            Action::GoToBottom => self.select_branch_at(count.map_or(last, |n| (n - 1).min(last))),
+            Action::Confirm => self.select_branch(),
+            _ => {}
+        }
+    }
+
+    /// Wheel scrolls the panel under the pointer; clicks select commits and files, open a
+    /// file on a double-click, widen the context at a hunk separator and start selecting
+    /// diff lines, which a drag extends
+    fn handle_mouse(&mut self, mouse: MouseEvent, stdout: &mut io::Stdout) -> io::Result<()> {
+        let (column, row) = (mouse.column, mouse.row);
+        let commits_area = self.ui.commits_area;
+        let files_area = self.ui.files_area;
+        let diff_area = self.ui.diff_area;
+
+        match mouse.kind {
+            MouseEventKind::ScrollUp | MouseEventKind::ScrollDown => {
+                let down = mouse.kind == MouseEventKind::ScrollDown;
+                if commits_area.contains(column, row) {
+                    self.commit_scroll_offset = wheel_offset(self.commit_scroll_offset, self.commits.len(), self.ui.commit_rows(), down);
+                } else if files_area.contains(column, row) {
+                    self.file_scroll_offset = wheel_offset(self.file_scroll_offset, self.files.len(), self.ui.file_rows(), down);
+                } else if down {
+                    self.scroll_down();
+                } else {
+                    self.scroll_up();
+                }
+            }
+            MouseEventKind::ScrollLeft => self.scroll_left(),
+            MouseEventKind::ScrollRight => self.scroll_right(),
+            MouseEventKind::Down(MouseButton::Left) if self.ui.boundary_at(column, row).is_some() => {
+                self.dragging = self.ui.boundary_at(column, row);
+            }
+            MouseEventKind::Drag(MouseButton::Left) => {
+                if let Some(boundary) = self.dragging {
+                    self.ui.drag_boundary(boundary, column, row);
+                    self.handle_layout_change();
+                } else if self.selecting {
+                    self.extend_selection(row);
+                }
+            }
+            MouseEventKind::Up(MouseButton::Left) if self.dragging.take().is_some() => self.save_layout(),
+            MouseEventKind::Up(MouseButton::Left) => self.selecting = false,
+            MouseEventKind::Down(button) => {
+                let double_click = button == MouseButton::Left
+                    && self.last_click.is_some_and(|(at, c, r)| at.elapsed() < DOUBLE_CLICK && (c, r) == (column, row));
+                self.last_click = (button == MouseButton::Left && !double_click).then(|| (Instant::now(), column, row));
+
+                if commits_area.contains(column, row) && row > commits_area.y {
+                    // Click in commit panel
+                    let clicked = (row - commits_area.y - 1) as usize + self.commit_scroll_offset;
+                    if clicked < self.commits.len() && clicked != self.selected_commit {
+                        self.selected_commit = clicked;
+                        self.load_files_for_selected_commit();
+                    }
+                } else if files_area.contains(column, row) && row > files_area.y {
+                    // Click in file panel
+                    let clicked = (row - files_area.y - 1) as usize + self.file_scroll_offset;
+                    if clicked < self.files.len() && clicked != self.selected_file {
+                        self.selected_file = clicked;
+                        self.load_diff_for_selected();
+                    } else if clicked == self.selected_file && double_click {
+                        self.open_in_editor(stdout, None)?;
+                    }
+                } else if button == MouseButton::Left
+                    && diff_area.contains(column, row)
+                    && row > diff_area.y
+                    && ((row - diff_area.y - 1) as usize) < self.ui.diff_rows()
+                {
+                    let clicked = self.item_at(row).and_then(|item| Some((item, self.locate_item(item)?)));
+                    match clicked {
+                        Some((_, (hunk, None))) => self.expand_context(hunk),
+                        Some((item, _)) => {
+                            self.selection = Some((item, item));
+                            self.selecting = true;
+                        }
+                        None => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Wheel scrolls the branch list, a click picks the branch under the pointer
+    fn handle_branch_mouse(&mut self, mouse: MouseEvent) {
+        match mouse.kind {
+            MouseEventKind::ScrollUp | MouseEventKind::ScrollDown => {
+                let visible = (self.ui.term_height - 4) as usize;
+                let down = mouse.kind == MouseEventKind::ScrollDown;
+                self.branch_scroll_offset = wheel_offset(self.branch_scroll_offset, self.branches.len(), visible, down);
+            }
+            MouseEventKind::Down(MouseButton::Left) => {
+                let clicked = self.ui.branch_at(mouse.column, mouse.row, self.branch_scroll_offset);
+                if let Some(index) = clicked.filter(|index| *index < self.branches.len()) {
+                    self.select_branch_at(index);
+                    self.select_branch();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Lay out for the new terminal size and bring the selections back into view
+    fn handle_resize(&mut self, width: u16, height: u16) {
+        self.ui.resize(width, height);
+        self.screen.resize(self.ui.term_width, self.ui.term_height);
+        self.handle_layout_change();
+    }
+
+    /// Panels changed size: keep the scroll positions valid and the selections in view
+    fn handle_layout_change(&mut self) {
+        let wrap_width = self.ui.wrap_width();
+        if wrap_width != self.wrap_width {
+            // Rows above moved: keep the line at the top of the view there
+            let top = self.top_item();
+            self.wrap_width = wrap_width;
+            self.scroll_offset = self.item_rows().take(top).sum();
+        }
+        self.scroll_to(self.scroll_offset);
+
+        let visible_commits = self.ui.commit_rows();
+        self.commit_scroll_offset = keep_in_view(self.commit_scroll_offset, self.selected_commit, visible_commits);
+        let visible_files = self.ui.file_rows();
+        self.file_scroll_offset = keep_in_view(self.file_scroll_offset, self.selected_file, visible_files);
+        let visible_branches = (self.ui.term_height - 4) as usize;
+        self.branch_scroll_offset = keep_in_view(self.branch_scroll_offset, self.selected_branch, visible_branches);
+    }
+
+    /// Remember the panel arrangement for the next session
+    fn save_layout(&mut self) {
+        if let Err(e) = self.ui.layout().save() {
+            self.show_message(format!("Couldn't save layout: {}", e));
+        }
+    }
+
+    /// Move the branch list selection, keeping it in view
+    fn select_branch_at(&mut self, index: usize) {
+        self.selected_branch = index;
+        let visible = (self.ui.term_height - 4) as usize;
+        if self.selected_branch < self.branch_scroll_offset {
+            self.branch_scroll_offset = self.selected_branch;
+        } else if self.selected_branch >= self.branch_scroll_offset + visible {
+            self.branch_scroll_offset = self.selected_branch - visible + 1;
+        }
     }
 
     pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
@@ -314,94 +1286,32 @@ impl App {
         execute!(stdout, EnterAlternateScreen, EnableMouseCapture, Hide)?;
 
         loop {
+            self.process_responses();
             self.refresh_if_needed();
+            self.request_highlight();
             self.draw(&mut stdout)?;
 
-            if event::poll(std::time::Duration::from_millis(100))? {
+            // Wake up sooner while the worker has results on the way
+            let timeout = if self.worker.is_busy() { 10 } else { 100 };
+            if event::poll(std::time::Duration::from_millis(timeout))? {
                 match event::read()? {
                     Event::Key(key) => {
-                        if self.mode == AppMode::BranchSelect {
-                            match key.code {
-                                KeyCode::Char('q') | KeyCode::Esc | KeyCode::Char('b') => self.cancel_branch_mode(),
-                                KeyCode::Up => {
-                                    if self.selected_branch > 0 {
-                                        self.selected_branch -= 1;
-                                        if self.selected_branch < self.branch_scroll_offset {
-                                            self.branch_scroll_offset = self.selected_branch;
-                                        }
-                                    }
-                                }
-                                KeyCode::Down => {
-                                    if self.selected_branch < self.branches.len().saturating_sub(1) {
-                                        self.selected_branch += 1;
-                                        let visible = (self.ui.term_height - 4) as usize;
-                                        if self.selected_branch >= self.branch_scroll_offset + visible {
-                                            self.branch_scroll_offset = self.selected_branch - visible + 1;
-                                        }
-                                    }
-                                }
-                                KeyCode::Enter => self.select_branch(),
-                                _ => {}
-                            }
-                        } else {
-                            match key.code {
-                                KeyCode::Char('q') => break,
-                                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break,
-                                KeyCode::Char('b') => self.enter_branch_mode(),
-                                KeyCode::Left => {
-                                    let _ = self.select_prev_commit();
-                                }
-                                KeyCode::Right => {
-                                    let _ = self.select_next_commit();
-                                }
-                                KeyCode::Up => {
-                                    let _ = self.select_prev_file();
-                                }
-                                KeyCode::Down => {
-                                    let _ = self.select_next_file();
-                                }
-                                KeyCode::Char('k') => self.scroll_up(),
-                                KeyCode::Char('j') => self.scroll_down(),
-                                KeyCode::PageUp => self.page_up(),
-                                KeyCode::PageDown => self.page_down(),
-                                KeyCode::Char('m') => {
-                                    self.mouse_enabled = !self.mouse_enabled;
-                                    if self.mouse_enabled {
-                                        execute!(stdout, EnableMouseCapture)?;
-                                    } else {
-                                        execute!(stdout, DisableMouseCapture)?;
-                                    }
-                                }
-                                _ => {}
-                            }
+                        if self.mode == AppMode::Help {
+                            self.mode = AppMode::Normal;
+                            continue;
                         }
-                    },
-                    Event::Mouse(mouse) if self.mouse_enabled && self.mode == AppMode::Normal => match mouse.kind {
-                        MouseEventKind::ScrollUp => self.scroll_up(),
-                        MouseEventKind::ScrollDown => self.scroll_down(),
-                        MouseEventKind::Down(_) => {
-                            let commit_panel_height = self.ui.commit_panel_height;
-                            
-                            if mouse.column < self.ui.left_panel_width {
-                                if mouse.row >= 1 && mouse.row < commit_panel_height {
-                                    // Click in commit panel
-                                    let clicked = (mouse.row - 1) as usize + self.commit_scroll_offset;
-                                    if clicked < self.commits.len() && clicked != self.selected_commit {
-                                        self.selected_commit = clicked;
-                                        let _ = self.load_files_for_selected_commit();
-                                    }
-                                } else if mouse.row >= commit_panel_height + 1 {
-                                    // Click in file panel
-                                    let clicked = (mouse.row - commit_panel_height - 1) as usize + self.file_scroll_offset;
-                                    if clicked < self.files.len() && clicked != self.selected_file {
-                                        self.selected_file = clicked;
-                                        let _ = self.load_diff_for_selected();
-                                    }
-                                }
-                            }
+                        let Some((action, count)) = self.keymap.feed(key) else {
+                            continue;
+                        };
+                        if self.mode == AppMode::BranchSelect {
+                            self.handle_branch_action(action, count);
+                        } else if !self.handle_action(action, count, &mut stdout)? {
+                            break;
                         }
-                        _ => {}
-                    },
+                    }
+                    Event::Mouse(mouse) if self.mouse_enabled && self.mode == AppMode::Normal => self.handle_mouse(mouse, &mut stdout)?,
+                    Event::Mouse(mouse) if self.mouse_enabled && self.mode == AppMode::BranchSelect => self.handle_branch_mouse(mouse),
+                    Event::Resize(width, height) => self.handle_resize(width, height),
                     _ => {}
                 }
             }
@@ -413,3 +1323,40 @@ impl App {
         Ok(())
     }
 }
+
+/// Scroll offset of a list showing `visible` rows so that `selected` is among them
+fn keep_in_view(offset: usize, selected: usize, visible: usize) -> usize {
+    if selected < offset {
+        selected
+    } else if selected >= offset + visible {
+        selected + 1 - visible.max(1)
+    } else {
+        offset
+    }
+}
+
+/// Scroll offset of a list of `len` entries showing `visible` rows after a wheel step
+fn wheel_offset(offset: usize, len: usize, visible: usize, down: bool) -> usize {
+    if down {
+        (offset + WHEEL_STEP).min(len.saturating_sub(visible).max(offset))
+    } else {
+        offset.saturating_sub(WHEEL_STEP)
+    }
+}
+
+/// Arguments that open `path` at `line` in `program`: `+line file` as vi, Emacs, nano and
+/// most terminal editors take it, `--goto file:line` for VS Code and its forks, and
+/// `file:line` for editors that only understand that
+fn editor_args(program: &str, path: &Path, line: Option<u32>) -> Vec<OsString> {
+    let Some(line) = line else {
+        return vec![path.into()];
+    };
+    let mut at_line = path.as_os_str().to_owned();
+    at_line.push(format!(":{}", line));
+    let name = Path::new(program).file_stem().and_then(|name| name.to_str()).unwrap_or(program);
+    match name {
+        "code" | "code-insiders" | "codium" | "cursor" => vec!["--goto".into(), at_line],
+        "subl" | "zed" | "hx" => vec![at_line],
+        _ => vec![format!("+{}", line).into(), path.into()],
+    }
+}
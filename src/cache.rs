@@ -0,0 +1,129 @@
+use git2::Oid;
+use std::collections::HashMap;
+use std::mem::{size_of, size_of_val};
+use syntect::highlighting::Style;
+
+use crate::highlighter::DiffHighlight;
+use crate::types::{DiffHunk, DiffLine, DiffSettings};
+
+/// Everything a computed diff depends on
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct DiffKey {
+    pub old: Oid,
+    pub new: Oid,
+    /// Picks the syntax and is what move annotations point back to
+    pub path: String,
+    pub context_lines: usize,
+    pub settings: DiffSettings,
+    /// Commit whose other files were scanned for moves (zero when only this file was)
+    pub move_scope: Oid,
+}
+
+pub struct CachedDiff {
+    /// Lines gain their spans here as they are highlighted, so revisits start out colored
+    pub hunks: Vec<DiffHunk>,
+    pub highlight: DiffHighlight,
+}
+
+impl CachedDiff {
+    fn size(&self) -> usize {
+        hunks_size(&self.hunks) + self.highlight.size()
+    }
+}
+
+struct Entry {
+    diff: CachedDiff,
+    size: usize,
+    last_used: u64,
+}
+
+/// Least-recently-used cache of diffed and highlighted hunks, bounded by an approximate byte budget.
+/// The most recent entry is always kept, so the diff on screen can be highlighted further.
+pub struct DiffCache {
+    entries: HashMap<DiffKey, Entry>,
+    budget: usize,
+    used: usize,
+    clock: u64,
+}
+
+impl DiffCache {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            budget,
+            used: 0,
+            clock: 0,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &DiffKey) -> Option<&mut CachedDiff> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(key)?;
+        entry.last_used = self.clock;
+        Some(&mut entry.diff)
+    }
+
+    pub fn insert(&mut self, key: DiffKey, diff: CachedDiff) {
+        self.clock += 1;
+        if let Some(old) = self.entries.remove(&key) {
+            self.used -= old.size;
+        }
+
+        let size = diff.size();
+        self.used += size;
+        self.entries.insert(key, Entry { diff, size, last_used: self.clock });
+        self.evict();
+    }
+
+    /// Re-account an entry that grew (e.g. gained highlighting)
+    pub fn update_size(&mut self, key: &DiffKey) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            let size = entry.diff.size();
+            self.used = self.used - entry.size + size;
+            entry.size = size;
+        }
+        self.evict();
+    }
+
+    /// Forget the spans of every entry (e.g. the theme changed); the diffs themselves stay valid
+    pub fn clear_highlights(&mut self) {
+        self.used = 0;
+        for entry in self.entries.values_mut() {
+            for line in entry.diff.hunks.iter_mut().flat_map(|h| h.lines.iter_mut()) {
+                line.highlighted = None;
+            }
+            entry.size = entry.diff.size();
+            self.used += entry.size;
+        }
+    }
+
+    /// Drop least recently used entries until within budget, keeping the newest one
+    fn evict(&mut self) {
+        while self.used > self.budget && self.entries.len() > 1 {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(entry) = oldest.and_then(|key| self.entries.remove(&key)) {
+                self.used -= entry.size;
+            }
+        }
+    }
+}
+
+/// Rough heap footprint of hunks, dominated by line text and highlight spans
+fn hunks_size(hunks: &[DiffHunk]) -> usize {
+    hunks
+        .iter()
+        .flat_map(|h| h.lines.iter())
+        .map(|line| {
+            let spans = line.highlighted.as_ref().map_or(0, |spans| {
+                spans.iter().map(|(_, text)| size_of::<(Style, String)>() + text.len()).sum()
+            });
+            let moved = line.moved.as_ref().map_or(0, |m| m.path.len());
+            size_of::<DiffLine>() + line.content.len() + spans + moved
+        })
+        .sum::<usize>()
+        + size_of_val(hunks)
+}
@@ -0,0 +1,16 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::io::{self, Write};
+
+/// Put `text` on the system clipboard with the OSC 52 escape sequence. The terminal does
+/// the copying, so it works over SSH too.
+pub fn copy(out: &mut impl Write, text: &str) -> io::Result<()> {
+    let sequence = format!("\x1b]52;c;{}\x07", STANDARD.encode(text));
+    out.write_all(sequence.as_bytes())?;
+    if std::env::var_os("TMUX").is_some() {
+        // tmux takes the plain sequence with `set-clipboard on`; with `allow-passthrough on`
+        // this hands it to the outer terminal instead, its escapes doubled
+        write!(out, "\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))?;
+    }
+    out.flush()
+}
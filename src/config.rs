@@ -0,0 +1,347 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::keymap::KeymapPreset;
+use crate::theme::{ColorDepth, ThemeName};
+use crate::types::{DiffAlgorithm, WhitespaceMode};
+
+/// Settings from `config.toml` in the config directory, overridden per repository by
+/// `git config gitti.*`. Options left out fall back to the command line defaults.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub context: Option<usize>,
+    pub diff_algorithm: Option<DiffAlgorithm>,
+    pub whitespace: Option<WhitespaceMode>,
+    /// Megabytes of diffs kept for revisits
+    pub diff_cache_size: Option<usize>,
+    /// Megabytes of a file (old and new side together) above which it isn't highlighted
+    pub max_highlight_size: Option<usize>,
+    /// Columns between tab stops
+    pub tab_width: Option<usize>,
+    /// Wrap long diff lines instead of cutting them off
+    pub wrap: Option<bool>,
+    /// Show tabs, trailing spaces and line endings, and mark whitespace errors
+    pub show_whitespace: Option<bool>,
+    /// Built-in UI color theme
+    pub theme: Option<ThemeName>,
+    pub syntax_theme: Option<String>,
+    pub match_syntax_theme: Option<bool>,
+    pub color_depth: Option<ColorDepth>,
+    /// How often the repository is polled when it can't be watched
+    pub refresh_interval_ms: u64,
+    /// Commits listed per branch
+    pub max_commits: usize,
+    /// Pathspecs of files to leave out of the file list, e.g. `["*.lock", "vendor/"]`
+    pub exclude: Vec<String>,
+    pub layout: Layout,
+    /// Key bindings to start from
+    pub keymap: Option<KeymapPreset>,
+    /// Individual theme colors to override, e.g. `bg_added = "#203820"` or `fg_dim = 245`
+    pub colors: BTreeMap<String, ColorValue>,
+    /// Keys per action, replacing the preset's keys, e.g. `scroll-down = ["j", "ctrl-e"]`
+    pub keys: BTreeMap<String, KeyList>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            context: None,
+            diff_algorithm: None,
+            whitespace: None,
+            diff_cache_size: None,
+            max_highlight_size: None,
+            tab_width: None,
+            wrap: None,
+            show_whitespace: None,
+            theme: None,
+            syntax_theme: None,
+            match_syntax_theme: None,
+            color_depth: None,
+            refresh_interval_ms: 1000,
+            max_commits: 50,
+            exclude: Vec::new(),
+            layout: Layout::default(),
+            keymap: None,
+            colors: BTreeMap::new(),
+            keys: BTreeMap::new(),
+        }
+    }
+}
+
+/// Panel sizes and arrangement; unset sizes scale with the terminal
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Layout {
+    /// Columns of the commit and file lists
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub left_panel_width: Option<u16>,
+    /// Rows of the commit list
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit_panel_height: Option<u16>,
+    /// Rows of the file list when stacked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_panel_height: Option<u16>,
+    /// Lists above the diff instead of beside it
+    pub stacked: bool,
+}
+
+impl Layout {
+    /// The layout last arranged in the viewer
+    fn load_saved() -> Option<Self> {
+        let text = std::fs::read_to_string(layout_path()?).ok()?;
+        // Only ever written by us; a damaged one is as good as none
+        toml::from_str(&text).ok()
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = layout_path().ok_or("no config directory")?;
+        let text = toml::to_string(self).map_err(|e| e.to_string())?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(&path, text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+fn layout_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("layout.toml"))
+}
+
+/// `"#rrggbb"` or a 256-color palette index
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum ColorValue {
+    Hex(String),
+    Index(u8),
+}
+
+/// One key or several
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum KeyList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl KeyList {
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        let keys = match self {
+            KeyList::One(key) => std::slice::from_ref(key),
+            KeyList::Many(keys) => keys.as_slice(),
+        };
+        keys.iter().map(String::as_str)
+    }
+}
+
+/// Options that can be set with `git config gitti.<name>`; `layout.*` go in a `[gitti "layout"]` section
+const GIT_CONFIG_OPTIONS: &[&str] = &[
+    "context",
+    "diff_algorithm",
+    "whitespace",
+    "diff_cache_size",
+    "max_highlight_size",
+    "tab_width",
+    "wrap",
+    "show_whitespace",
+    "theme",
+    "syntax_theme",
+    "match_syntax_theme",
+    "color_depth",
+    "refresh_interval_ms",
+    "max_commits",
+    "exclude",
+    "keymap",
+    "layout.left_panel_width",
+    "layout.commit_panel_height",
+    "layout.file_panel_height",
+    "layout.stacked",
+];
+
+const STRING_OPTIONS: &[&str] = &["diff_algorithm", "whitespace", "theme", "syntax_theme", "color_depth", "keymap"];
+
+impl Config {
+    /// The config file, if there is one, with the repository's git config on top
+    pub fn load() -> Result<Self, String> {
+        let mut table = match config_dir().map(|dir| dir.join("config.toml")) {
+            Some(path) => read_table(&path)?,
+            None => toml::Table::new(),
+        };
+        if let Ok(repo) = git2::Repository::discover(".") {
+            let config = repo.config().map_err(|e| e.message().to_string())?;
+            apply_git_config(&mut table, &config).map_err(|e| format!("git config: {}", e.message()))?;
+        }
+        if let Some(layout) = Layout::load_saved() {
+            fill_layout(&mut table, &layout);
+        }
+        let config: Self = toml::Value::Table(table).try_into().map_err(|e| format!("config: {}", e))?;
+        if config.refresh_interval_ms == 0 {
+            // Would re-diff on every pass through the event loop
+            return Err("config: refresh_interval_ms must be at least 1".to_string());
+        }
+        Ok(config)
+    }
+}
+
+/// Fill in the `[layout]` options that neither the config file nor git config set from
+/// `saved`, so settings made on purpose win over panels dragged around in the viewer
+fn fill_layout(table: &mut toml::Table, saved: &Layout) {
+    let Ok(saved) = toml::Table::try_from(saved) else {
+        return;
+    };
+    let toml::Value::Table(layout) = table.entry("layout").or_insert_with(|| toml::Table::new().into()) else {
+        return;
+    };
+    for (key, value) in saved {
+        layout.entry(key).or_insert(value);
+    }
+}
+
+fn read_table(path: &Path) -> Result<toml::Table, String> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(toml::Table::new()),
+        Err(e) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+/// Merge `gitti.*` entries into `table`. Git lowercases names and doesn't allow underscores,
+/// so `gitti.syntaxTheme` and `gitti.syntax-theme` both set `syntax_theme`.
+/// `gitti.exclude` may be given several times; `[gitti "keys"]` and `[gitti "colors"]` map to `[keys]` and `[colors]`.
+fn apply_git_config(table: &mut toml::Table, config: &git2::Config) -> Result<(), git2::Error> {
+    let mut entries = Vec::new();
+    config.entries(Some("^gitti\\."))?.for_each(|entry| {
+        if let (Some(name), Some(value)) = (entry.name(), entry.value()) {
+            entries.push((name["gitti.".len()..].to_string(), value.to_string()));
+        }
+    })?;
+
+    let normalize = |name: &str| name.replace(['-', '_'], "").to_lowercase();
+    let mut excludes = Vec::new();
+    for (name, value) in entries {
+        if let Some(action) = name.strip_prefix("keys.") {
+            section(table, "keys").insert(action.to_string(), toml::Value::String(value));
+            continue;
+        }
+        if let Some(color) = name.strip_prefix("colors.") {
+            section(table, "colors").insert(color.replace('-', "_"), git_value(&value));
+            continue;
+        }
+
+        let Some(option) = GIT_CONFIG_OPTIONS.iter().find(|option| normalize(option) == normalize(&name)) else {
+            return Err(git2::Error::from_str(&format!("unknown option gitti.{}", name)));
+        };
+        if *option == "refresh_interval_ms" && value.trim().parse::<u64>() == Ok(0) {
+            return Err(git2::Error::from_str(&format!("gitti.{} must be at least 1", name)));
+        }
+        if *option == "exclude" {
+            excludes.push(toml::Value::String(value));
+        } else if let Some(key) = option.strip_prefix("layout.") {
+            section(table, "layout").insert(key.to_string(), git_value(&value));
+        } else if STRING_OPTIONS.contains(option) {
+            // Even when they look like numbers (`color_depth = "256"`)
+            table.insert(option.to_string(), toml::Value::String(value));
+        } else {
+            table.insert(option.to_string(), git_value(&value));
+        }
+    }
+    if !excludes.is_empty() {
+        table.insert("exclude".to_string(), toml::Value::Array(excludes));
+    }
+    Ok(())
+}
+
+fn section<'a>(table: &'a mut toml::Table, name: &str) -> &'a mut toml::Table {
+    let value = table
+        .entry(name.to_string())
+        .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+    if !value.is_table() {
+        *value = toml::Value::Table(toml::Table::new());
+    }
+    value.as_table_mut().expect("just made a table")
+}
+
+/// Git config values are untyped; numbers and booleans are recognized as such
+fn git_value(value: &str) -> toml::Value {
+    if let Ok(number) = value.parse::<i64>() {
+        return toml::Value::Integer(number);
+    }
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" => toml::Value::Boolean(true),
+        "false" | "no" | "off" => toml::Value::Boolean(false),
+        _ => toml::Value::String(value.to_string()),
+    }
+}
+
+/// `$XDG_CONFIG_HOME/gitti`, falling back to `~/.config/gitti`
+pub fn config_dir() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("gitti"))
+}
+
+/// Files left out of the file list, matched like git pathspecs
+#[derive(Default)]
+pub struct Excludes(Option<git2::Pathspec>);
+
+impl Excludes {
+    pub fn new(patterns: &[String]) -> Result<Self, git2::Error> {
+        if patterns.is_empty() {
+            return Ok(Self(None));
+        }
+        Ok(Self(Some(git2::Pathspec::new(patterns)?)))
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        self.0
+            .as_ref()
+            .is_some_and(|spec| spec.matches_path(Path::new(path), git2::PathspecFlags::DEFAULT))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_layout_fills_only_unset_options() {
+        let path = std::env::temp_dir().join(format!("gitti-test-layout-{}", std::process::id()));
+        let mut git = git2::Config::open(&path).unwrap();
+        git.set_i64("gitti.layout.leftPanelWidth", 30).unwrap();
+        let mut table: toml::Table = toml::from_str("[layout]\nstacked = false\n").unwrap();
+        apply_git_config(&mut table, &git).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let saved = Layout { left_panel_width: Some(50), commit_panel_height: Some(10), file_panel_height: None, stacked: true };
+        fill_layout(&mut table, &saved);
+        let config: Config = toml::Value::Table(table).try_into().unwrap();
+        let expected = Layout { left_panel_width: Some(30), commit_panel_height: Some(10), file_panel_height: None, stacked: false };
+        assert!(config.layout == expected);
+    }
+
+    #[test]
+    fn highlight_size_from_git_config() {
+        let path = std::env::temp_dir().join(format!("gitti-test-highlight-{}", std::process::id()));
+        let mut git = git2::Config::open(&path).unwrap();
+        git.set_i64("gitti.maxHighlightSize", 16).unwrap();
+        let mut table: toml::Table = toml::from_str("max_highlight_size = 2\n").unwrap();
+        apply_git_config(&mut table, &git).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let config: Config = toml::Value::Table(table).try_into().unwrap();
+        assert_eq!(config.max_highlight_size, Some(16));
+    }
+
+    #[test]
+    fn zero_refresh_interval_is_rejected() {
+        let path = std::env::temp_dir().join(format!("gitti-test-refresh-{}", std::process::id()));
+        let mut git = git2::Config::open(&path).unwrap();
+        git.set_i64("gitti.refreshIntervalMs", 0).unwrap();
+        let result = apply_git_config(&mut toml::Table::new(), &git);
+        let _ = std::fs::remove_file(&path);
+        assert!(result.is_err());
+    }
+}
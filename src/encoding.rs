@@ -0,0 +1,178 @@
+// Turning file contents into text: byte order marks, UTF-16, the `working-tree-encoding`
+// attribute and a guess for legacy 8-bit encodings; and telling how lines end
+
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use std::borrow::Cow;
+use std::fmt;
+
+/// Bytes looked at when guessing whether a file without a byte order mark is UTF-16
+const UTF16_SAMPLE: usize = 4096;
+
+/// A file's text with the encoding it was read in
+pub struct Decoded {
+    pub text: String,
+    pub encoding: &'static Encoding,
+    /// Started with a byte order mark, which isn't part of `text`
+    pub bom: bool,
+}
+
+/// Decode `bytes`, in `declared` unless a byte order mark says otherwise. Binary data
+/// stays as it is, NULs included, so it is still recognized as binary.
+pub fn decode(bytes: &[u8], declared: Option<&'static Encoding>) -> Decoded {
+    if let Some((encoding, bom_len)) = Encoding::for_bom(bytes) {
+        let text = encoding.decode_without_bom_handling(&bytes[bom_len..]).0.into_owned();
+        return Decoded { text, encoding, bom: true };
+    }
+
+    let encoding = match declared {
+        Some(encoding) => encoding,
+        // Mostly ASCII UTF-16 is valid UTF-8 too, NULs and all, so it's looked for first
+        None => match utf16_without_bom(bytes) {
+            Some(encoding) => encoding,
+            None if std::str::from_utf8(bytes).is_ok() => UTF_8,
+            None if bytes.contains(&0) => {
+                return Decoded { text: String::from_utf8_lossy(bytes).into_owned(), encoding: UTF_8, bom: false };
+            }
+            None => {
+                let mut detector = EncodingDetector::new();
+                detector.feed(bytes, true);
+                detector.guess(None, true)
+            }
+        },
+    };
+    let text = encoding.decode_without_bom_handling(bytes).0.into_owned();
+    Decoded { text, encoding, bom: false }
+}
+
+/// UTF-16 text that is mostly ASCII has every other byte zero
+fn utf16_without_bom(bytes: &[u8]) -> Option<&'static Encoding> {
+    let sample = &bytes[..bytes.len().min(UTF16_SAMPLE) & !1];
+    if sample.is_empty() {
+        return None;
+    }
+    let zeros_at = |parity: usize| sample.iter().skip(parity).step_by(2).filter(|b| **b == 0).count();
+    let (even, odd) = (zeros_at(0), zeros_at(1));
+    let half = sample.len() / 2;
+    if odd > half * 3 / 4 && even == 0 {
+        Some(UTF_16LE)
+    } else if even > half * 3 / 4 && odd == 0 {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// What the file's encoding or byte order mark did between the two sides; an added or
+/// deleted side has nothing to compare
+pub fn notices(old: &Decoded, new: &Decoded) -> Vec<String> {
+    let old = (!old.text.is_empty() || old.bom).then_some(old);
+    let new = (!new.text.is_empty() || new.bom).then_some(new);
+    let mut notices = Vec::new();
+    match (old, new) {
+        (Some(old), Some(new)) if old.encoding != new.encoding => {
+            notices.push(format!("Encoding changed from {} to {}", old.encoding.name(), new.encoding.name()));
+        }
+        (old, new) => {
+            if let Some(side) = new.or(old).filter(|side| side.encoding != UTF_8) {
+                notices.push(format!("Shown converted from {}", side.encoding.name()));
+            }
+        }
+    }
+    if let (Some(old), Some(new)) = (old, new) {
+        if old.bom != new.bom {
+            notices.push(if new.bom { "Byte order mark added" } else { "Byte order mark removed" }.to_string());
+        }
+    }
+    notices
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    Mixed,
+}
+
+impl LineEnding {
+    /// How the lines of `text` end, `None` when it has a single line
+    fn of(text: &str) -> Option<Self> {
+        let newlines = text.matches('\n').count();
+        let crlf = text.matches("\r\n").count();
+        match (newlines, crlf) {
+            (0, _) => None,
+            (_, 0) => Some(LineEnding::Lf),
+            (n, c) if n == c => Some(LineEnding::Crlf),
+            _ => Some(LineEnding::Mixed),
+        }
+    }
+}
+
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+            LineEnding::Mixed => "mixed",
+        })
+    }
+}
+
+/// The two sides to compare, with `\r\n` turned into `\n` when the line endings changed so
+/// that only real edits show, and a note saying so
+pub fn unify_line_endings<'a>(old: &'a str, new: &'a str) -> (Cow<'a, str>, Cow<'a, str>, Option<String>) {
+    match (LineEnding::of(old), LineEnding::of(new)) {
+        (Some(from), Some(to)) if from != to => (
+            Cow::Owned(old.replace("\r\n", "\n")),
+            Cow::Owned(new.replace("\r\n", "\n")),
+            Some(format!("Line endings changed from {} to {}", from, to)),
+        ),
+        _ => (Cow::Borrowed(old), Cow::Borrowed(new), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::tests::TestRepo;
+    use crate::types::WhitespaceMode;
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    #[test]
+    fn utf16le_with_bom() {
+        let bytes = [&[0xff, 0xfe][..], &utf16le("héllo\nwörld\n")].concat();
+        let decoded = decode(&bytes, None);
+        assert_eq!((decoded.text.as_str(), decoded.encoding, decoded.bom), ("héllo\nwörld\n", UTF_16LE, true));
+    }
+
+    #[test]
+    fn utf16le_without_bom() {
+        let decoded = decode(&utf16le("hello\nworld\n"), None);
+        assert_eq!((decoded.text.as_str(), decoded.encoding, decoded.bom), ("hello\nworld\n", UTF_16LE, false));
+    }
+
+    #[test]
+    fn latin1_is_guessed() {
+        let decoded = decode(b"Le caf\xe9 \xe9tait d\xe9j\xe0 ferm\xe9 quand nous sommes arriv\xe9s.\n", None);
+        assert_eq!(decoded.text, "Le café était déjà fermé quand nous sommes arrivés.\n");
+        assert_eq!(decoded.encoding.name(), "windows-1252");
+        assert_eq!(notices(&decode(b"", None), &decoded), ["Shown converted from windows-1252"]);
+    }
+
+    #[test]
+    fn line_ending_change_is_a_notice_not_a_hunk() {
+        let repo = TestRepo::new("crlf");
+        repo.write("a.txt", "one\r\ntwo\r\nthree\r\n");
+        repo.commit("init");
+        repo.write("a.txt", "one\ntwo\nthree\n");
+
+        let hunks = repo.diff(WhitespaceMode::None).load_diff_for_file("a.txt").unwrap();
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(hunks[0].lines.len(), 1);
+        assert!(hunks[0].lines[0].is_notice());
+        assert_eq!(hunks[0].lines[0].content, "Line endings changed from CRLF to LF");
+    }
+}
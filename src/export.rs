@@ -0,0 +1,366 @@
+use similar::ChangeTag;
+use std::fmt::Write as _;
+
+use crate::highlighter;
+use crate::source::DiffSource;
+use crate::theme::Theme;
+use crate::types::{CommitInfo, DiffHunk, DiffLine, FileChange, Highlights};
+
+pub struct ExportFile {
+    pub change: FileChange,
+    pub hunks: Vec<DiffHunk>,
+}
+
+/// Files and hunks of one commit (or of the local changes)
+pub fn collect_files<S: DiffSource + ?Sized>(
+    source: &mut S,
+    commit: &CommitInfo,
+) -> Result<Vec<ExportFile>, git2::Error> {
+    source
+        .files_of(commit)?
+        .into_iter()
+        .map(|change| {
+            let (mut hunks, commit_sha) = if commit.is_local_changes {
+                (source.load_diff_for_file(&change.path)?, None)
+            } else {
+                (source.load_diff_for_commit_file(&commit.sha, &change.path)?, Some(commit.sha.as_str()))
+            };
+
+            let lines = highlighter::unhighlighted_lines(&hunks);
+            if let Highlights::Lines(spans) = source.highlight(commit_sha, &change.path, &lines)? {
+                highlighter::apply_highlights(&mut hunks, &lines, spans);
+            }
+            Ok(ExportFile { change, hunks })
+        })
+        .collect()
+}
+
+/// File name `git format-patch` would pick, e.g. `0001-fix-the-thing.patch`
+pub fn patch_file_name(commit: &CommitInfo) -> String {
+    let slug: String = commit
+        .message
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .collect();
+    let slug: Vec<&str> = slug.split('-').filter(|s| !s.is_empty()).collect();
+    let slug = slug.join("-");
+    format!("0001-{}.patch", &slug[..slug.len().min(52)].trim_end_matches('-'))
+}
+
+pub fn html_file_name(commit: &CommitInfo) -> String {
+    if commit.is_local_changes {
+        "gitti-local-changes.html".to_string()
+    } else {
+        format!("gitti-{}.html", commit.short_sha)
+    }
+}
+
+/// Mail-formatted patch built from hunks, for sources without a repository
+pub fn to_mbox_patch(commit: &CommitInfo, files: &[ExportFile]) -> String {
+    let sha = if commit.sha.is_empty() { "0".repeat(40) } else { commit.sha.clone() };
+    let author = if commit.author.is_empty() { "gitti <gitti@localhost>" } else { &commit.author };
+
+    let mut out = String::new();
+    let _ = writeln!(out, "From {} Mon Sep 17 00:00:00 2001", sha);
+    let _ = writeln!(out, "From: {}", author);
+    let _ = writeln!(out, "Subject: [PATCH] {}", commit.message);
+    let _ = writeln!(out);
+    let _ = writeln!(out, "---");
+    out.push_str(&to_unified_diff(files));
+    let _ = writeln!(out, "-- ");
+    let _ = writeln!(out, "gitti {}", env!("CARGO_PKG_VERSION"));
+    out
+}
+
+pub fn to_unified_diff(files: &[ExportFile]) -> String {
+    let mut out = String::new();
+    for file in files {
+        if write_file_header(&mut out, file) {
+            write_hunks(&mut out, &file.hunks, None);
+        }
+    }
+    out
+}
+
+/// Hunk `index` of `file` on its own, as a patch `git apply` takes
+pub fn hunk_patch(file: &ExportFile, index: usize) -> String {
+    let mut out = String::new();
+    if write_file_header(&mut out, file) {
+        write_hunks(&mut out, &file.hunks, Some(index));
+    }
+    out
+}
+
+/// The `diff --git` and `---`/`+++` lines of a file; false for a binary file, which has
+/// no hunks to follow
+fn write_file_header(out: &mut String, file: &ExportFile) -> bool {
+    let path = &file.change.path;
+    let _ = writeln!(out, "diff --git a/{} b/{}", path, path);
+
+    let binary = file
+        .hunks
+        .iter()
+        .flat_map(|h| h.lines.iter())
+        .any(|l| l.old_num.is_none() && l.content == "[Binary file]");
+    if binary {
+        let _ = writeln!(out, "Binary files a/{} and b/{} differ", path, path);
+        return false;
+    }
+
+    let (old_path, new_path) = match file.change.status.as_str() {
+        "added" => ("/dev/null".to_string(), format!("b/{}", path)),
+        "deleted" => (format!("a/{}", path), "/dev/null".to_string()),
+        _ => (format!("a/{}", path), format!("b/{}", path)),
+    };
+    let _ = writeln!(out, "--- {}", old_path);
+    let _ = writeln!(out, "+++ {}", new_path);
+    true
+}
+
+/// Hunks with their `@@` headers, or only hunk `only`; its header still accounts for the
+/// hunks before it
+fn write_hunks(out: &mut String, hunks: &[DiffHunk], only: Option<usize>) {
+    // New-minus-old line offset of the hunks so far, for hunks without lines on one side
+    let mut offset: i64 = 0;
+    for (idx, hunk) in hunks.iter().enumerate() {
+        // Notes about the file aren't part of the patch
+        if hunk.lines.iter().all(DiffLine::is_notice) {
+            continue;
+        }
+        let old_count = hunk.lines.iter().filter(|l| l.old_num.is_some()).count() as i64;
+        let new_count = hunk.lines.iter().filter(|l| l.new_num.is_some()).count() as i64;
+        let first_old = hunk.lines.iter().find_map(|l| l.old_num).map(i64::from);
+        let first_new = hunk.lines.iter().find_map(|l| l.new_num).map(i64::from);
+        let old_start = first_old.unwrap_or_else(|| (first_new.unwrap_or(1) - 1 - offset).max(0));
+        let new_start = first_new.unwrap_or_else(|| (first_old.unwrap_or(1) - 1 + offset).max(0));
+        offset += new_count - old_count;
+        if only.is_some_and(|only| only != idx) {
+            continue;
+        }
+
+        let _ = writeln!(out, "@@ -{},{} +{},{} @@", old_start, old_count, new_start, new_count);
+        for line in &hunk.lines {
+            match (line.tag, &line.old_content) {
+                // Context that only matches with whitespace ignored: the patch needs both sides
+                (ChangeTag::Equal, Some(old)) => {
+                    write_line(out, '-', old.trim_end_matches('\n'), !old.ends_with('\n'));
+                    write_line(out, '+', &line.content, line.no_newline);
+                }
+                (tag, _) => {
+                    let sign = match tag {
+                        ChangeTag::Insert => '+',
+                        ChangeTag::Delete => '-',
+                        ChangeTag::Equal => ' ',
+                    };
+                    write_line(out, sign, &line.content, line.no_newline);
+                }
+            }
+        }
+    }
+}
+
+fn write_line(out: &mut String, sign: char, content: &str, no_newline: bool) {
+    let _ = writeln!(out, "{}{}", sign, content);
+    if no_newline {
+        out.push_str("\\ No newline at end of file\n");
+    }
+}
+
+/// Self-contained HTML page with the same gutter and syntax colors as the viewer
+pub fn to_html(commits: &[(CommitInfo, Vec<ExportFile>)], theme: &Theme) -> String {
+    let title = match commits {
+        [(commit, _)] => commit_title(commit),
+        _ => "gitti diff".to_string(),
+    };
+
+    let mut out = String::new();
+    let _ = writeln!(out, "<!DOCTYPE html>");
+    let _ = writeln!(out, "<html><head><meta charset=\"utf-8\"><title>{}</title>", escape_html(&title));
+    let _ = writeln!(out, "<style>{}</style></head><body>", html_style(theme));
+
+    for (commit, files) in commits {
+        let _ = writeln!(out, "<h1>{}</h1>", escape_html(&commit_title(commit)));
+        if !commit.author.is_empty() {
+            let _ = writeln!(out, "<p class=\"author\">{}</p>", escape_html(&commit.author));
+        }
+        for file in files {
+            write_html_file(&mut out, file);
+        }
+    }
+
+    let _ = writeln!(out, "</body></html>");
+    out
+}
+
+fn commit_title(commit: &CommitInfo) -> String {
+    if commit.is_local_changes {
+        commit.message.clone()
+    } else {
+        format!("{} {}", commit.short_sha, commit.message)
+    }
+}
+
+fn write_html_file(out: &mut String, file: &ExportFile) {
+    let _ = writeln!(out, "<div class=\"file\"><h2>{} <span class=\"status\">({})</span></h2><table>",
+        escape_html(&file.change.path), escape_html(&file.change.status));
+    for (idx, hunk) in file.hunks.iter().enumerate() {
+        if idx > 0 {
+            let _ = writeln!(out, "<tr class=\"sep\"><td colspan=\"4\"></td></tr>");
+        }
+        for line in &hunk.lines {
+            let (class, sign) = match (line.tag, line.moved.is_some()) {
+                (ChangeTag::Insert, false) => ("ins", "+"),
+                (ChangeTag::Insert, true) => ("moved-to", "+"),
+                (ChangeTag::Delete, false) => ("del", "-"),
+                (ChangeTag::Delete, true) => ("moved-from", "-"),
+                (ChangeTag::Equal, _) => ("eq", " "),
+            };
+            let num = |n: Option<u32>| n.map(|n| n.to_string()).unwrap_or_default();
+
+            let mut code = String::new();
+            match line.highlighted {
+                Some(ref spans) => {
+                    for (style, text) in spans {
+                        let c = style.foreground;
+                        let _ = write!(code, "<span style=\"color:#{:02x}{:02x}{:02x}\">{}</span>",
+                            c.r, c.g, c.b, escape_html(text));
+                    }
+                }
+                None => code.push_str(&escape_html(&line.content)),
+            }
+
+            let _ = writeln!(out, "<tr class=\"{}\"><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"sign\">{}</td><td class=\"code\">{}</td></tr>",
+                class, num(line.old_num), num(line.new_num), sign, code);
+        }
+    }
+    let _ = writeln!(out, "</table></div>");
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Page styles in the colors of `theme`, overrides included
+fn html_style(theme: &Theme) -> String {
+    let rows = [
+        ("eq", theme.bg_diff, theme.fg_context),
+        ("ins", theme.bg_added, theme.fg_added),
+        ("del", theme.bg_removed, theme.fg_removed),
+        ("moved-to", theme.bg_moved_to, theme.fg_moved_to),
+        ("moved-from", theme.bg_moved_from, theme.fg_moved_from),
+    ];
+    let mut style = format!(
+        "\
+body {{ background: {}; color: {}; font-family: Menlo, Consolas, monospace; font-size: 13px; margin: 1em; }}
+h1 {{ font-size: 16px; color: {}; }}
+.author {{ color: {}; }}
+.file {{ margin: 1.5em 0; border: 1px solid {}; }}
+.file h2 {{ background: {}; color: {}; font-size: 13px; margin: 0; padding: 4px 8px; }}
+.status {{ color: {}; font-weight: normal; }}
+table {{ border-collapse: collapse; width: 100%; }}
+td {{ padding: 0 6px; white-space: pre; vertical-align: top; }}
+td.num {{ background: {}; color: {}; text-align: right; width: 1%; user-select: none; }}
+td.sign {{ width: 1%; user-select: none; }}
+tr.sep td {{ background: {}; height: 4px; padding: 0; }}
+",
+        theme.bg_panel.css(),
+        theme.fg_default.css(),
+        theme.fg_added.css(),
+        theme.fg_dim.css(),
+        theme.bg_header.css(),
+        theme.bg_header.css(),
+        theme.fg_header.css(),
+        theme.fg_dim.css(),
+        theme.bg_diff.css(),
+        theme.fg_line_number.css(),
+        theme.bg_hunk.css(),
+    );
+    for (class, bg, fg) in rows {
+        let _ = writeln!(style, "tr.{0} td.sign, tr.{0} td.code {{ background: {1}; color: {2}; }}", class, bg.css(), fg.css());
+    }
+    style
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::tests::TestRepo;
+    use crate::types::WhitespaceMode;
+    use std::process::Command;
+
+    /// Every hunk of `path`'s local changes, copied alone and all together, applies to HEAD
+    fn assert_hunks_apply(repo: &TestRepo, path: &str, whitespace: WhitespaceMode) {
+        let hunks = repo.diff(whitespace).load_diff_for_file(path).unwrap();
+        assert!(!hunks.is_empty());
+        let file = ExportFile { change: FileChange { path: path.to_string(), status: "modified".to_string() }, hunks };
+
+        let patches = (0..file.hunks.len()).map(|idx| hunk_patch(&file, idx));
+        for patch in patches.chain(std::iter::once(to_unified_diff(std::slice::from_ref(&file)))) {
+            let patch_file = repo.dir.join(".git").join("copied.patch");
+            std::fs::write(&patch_file, &patch).unwrap();
+            let output = Command::new("git")
+                .arg("-C")
+                .arg(&repo.dir)
+                .args(["apply", "--check", "--cached"])
+                .arg(&patch_file)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{}\n{}", String::from_utf8_lossy(&output.stderr), patch);
+        }
+    }
+
+    #[test]
+    fn copied_hunks_apply() {
+        let repo = TestRepo::new("copy-hunks");
+        let old: String = (1..=40).map(|n| format!("line {}\n", n)).collect();
+        repo.write("a.txt", &old);
+        repo.commit("init");
+        repo.write("a.txt", &old.replace("line 5\n", "five\n").replace("line 30\n", "line 30\nadded\n"));
+
+        assert_hunks_apply(&repo, "a.txt", WhitespaceMode::None);
+    }
+
+    #[test]
+    fn copied_hunk_keeps_missing_newline() {
+        let repo = TestRepo::new("copy-no-newline");
+        repo.write("a.txt", "a\nb\nc");
+        repo.commit("init");
+        repo.write("a.txt", "a\nb\nC");
+        assert!(hunk_patch_of(&repo, "a.txt", WhitespaceMode::None).contains("\\ No newline at end of file"));
+        assert_hunks_apply(&repo, "a.txt", WhitespaceMode::None);
+
+        repo.write("a.txt", "a\nb\nc\nd\n");
+        assert_hunks_apply(&repo, "a.txt", WhitespaceMode::None);
+    }
+
+    #[test]
+    fn copied_hunk_with_ignored_whitespace_applies() {
+        let repo = TestRepo::new("copy-ignored");
+        repo.write("a.txt", "a\n\nb\nc  d\ne\nf\n");
+        repo.commit("init");
+        repo.write("a.txt", "a\nb\nc d\nE\nf\n");
+
+        assert_hunks_apply(&repo, "a.txt", WhitespaceMode::IgnoreBlankLines);
+        assert_hunks_apply(&repo, "a.txt", WhitespaceMode::IgnoreAmount);
+    }
+
+    fn hunk_patch_of(repo: &TestRepo, path: &str, whitespace: WhitespaceMode) -> String {
+        let hunks = repo.diff(whitespace).load_diff_for_file(path).unwrap();
+        let file = ExportFile { change: FileChange { path: path.to_string(), status: "modified".to_string() }, hunks };
+        hunk_patch(&file, 0)
+    }
+
+    #[test]
+    fn html_uses_the_theme_colors() {
+        let mut theme = Theme::named(crate::theme::ThemeName::Light);
+        let colors = [("bg_added".to_string(), crate::config::ColorValue::Hex("#123456".to_string()))];
+        theme.override_colors(&colors.into_iter().collect()).unwrap();
+
+        let html = to_html(&[], &theme);
+        assert!(html.contains("tr.ins td.sign, tr.ins td.code { background: #123456;"));
+        assert!(html.contains(&format!("tr.del td.sign, tr.del td.code {{ background: {};", theme.bg_removed.css())));
+    }
+}
@@ -1,37 +1,144 @@
-use git2::{DiffOptions, Repository};
-use similar::{ChangeTag, TextDiff};
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE};
+use git2::{AttrCheckFlags, DiffOptions, Email, EmailCreateOptions, IndexEntry, IndexTime, ObjectType, Oid, Repository};
+use similar::{ChangeTag, DiffOp, DiffTag};
+use std::collections::HashSet;
 
-use crate::highlighter::Highlighter;
-use crate::types::{BranchInfo, CommitInfo, DiffHunk, DiffLine, FileChange};
+use crate::cache::{CachedDiff, DiffCache, DiffKey};
+use crate::config::Excludes;
+use crate::encoding::{self, Decoded};
+use crate::highlighter::{self, Highlighter};
+use crate::moves::{self, ChangedLines};
+use crate::types::{
+    BranchInfo, CommitInfo, DiffHunk, DiffLine, DiffSettings, FileChange, Highlights, LineRef,
+    WhitespaceMode,
+};
 
 pub struct GitDiff {
     repo: Repository,
     staged: bool,
     commit: Option<String>,
     context_lines: usize,
+    settings: DiffSettings,
     highlighter: Highlighter,
     current_branch: Option<String>,
+    /// Changed lines of every file in the loaded commit, for cross-file move detection
+    commit_changes: Option<(String, CommitChanges)>,
+    cache: DiffCache,
+    /// Commit (`None` for local changes) and cache key of the diff loaded last, which highlighting applies to
+    active: Option<(Option<String>, DiffKey)>,
+    excludes: Excludes,
 }
 
+/// Files of a commit for cross-file move detection, diffed when the first of them is shown
+/// rather than when the commit's files are listed
+enum CommitChanges {
+    Pending(Vec<String>),
+    Scanned(Vec<ChangedLines>),
+}
+
+/// Both sides of a local change as they are stored
+struct FileBytes {
+    old: Vec<u8>,
+    new: Vec<u8>,
+    /// Encoding of the worktree side when its `working-tree-encoding` attribute names one;
+    /// blobs are stored as UTF-8 then
+    new_encoding: Option<&'static Encoding>,
+}
+
+/// Commits touching more files than this skip cross-file move detection
+const MAX_MOVE_SCAN_FILES: usize = 200;
+
 impl GitDiff {
-    pub fn new(staged: bool, commit: Option<String>, context_lines: usize) -> Result<Self, git2::Error> {
-        let repo = Repository::discover(".")?;
+    pub fn new(
+        staged: bool,
+        commit: Option<String>,
+        context_lines: usize,
+        settings: DiffSettings,
+        cache_budget: usize,
+    ) -> Result<Self, git2::Error> {
+        Ok(Self::with_repo(Repository::discover(".")?, staged, commit, context_lines, settings, cache_budget))
+    }
+
+    fn with_repo(
+        repo: Repository,
+        staged: bool,
+        commit: Option<String>,
+        context_lines: usize,
+        settings: DiffSettings,
+        cache_budget: usize,
+    ) -> Self {
         let current_branch = repo.head().ok()
             .and_then(|h| h.shorthand().map(|s| s.to_string()));
-        Ok(Self {
+        Self {
             repo,
             staged,
             commit,
             context_lines,
+            settings,
             highlighter: Highlighter::new(),
             current_branch,
-        })
+            commit_changes: None,
+            cache: DiffCache::new(cache_budget),
+            active: None,
+            excludes: Excludes::default(),
+        }
     }
 
     pub fn get_current_branch(&self) -> Option<&str> {
         self.current_branch.as_deref()
     }
 
+    pub fn workdir(&self) -> Option<&std::path::Path> {
+        self.repo.workdir()
+    }
+
+    pub fn settings(&self) -> DiffSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: DiffSettings) {
+        self.settings = settings;
+    }
+
+    pub fn excludes(&self) -> &Excludes {
+        &self.excludes
+    }
+
+    pub fn set_excludes(&mut self, excludes: Excludes) {
+        self.excludes = excludes;
+    }
+
+    pub fn highlighter(&self) -> &Highlighter {
+        &self.highlighter
+    }
+
+    /// Cached spans were colored with the previous theme, so they are dropped
+    pub fn set_syntax_theme(&mut self, name: &str) -> bool {
+        if !self.highlighter.set_theme(name) {
+            return false;
+        }
+        self.cache.clear_highlights();
+        true
+    }
+
+    pub fn set_highlight_limit(&mut self, bytes: usize) {
+        self.highlighter.set_max_bytes(bytes);
+    }
+
+    /// git2 diff options matching the whitespace settings. libgit2 only decides which files
+    /// changed, which doesn't depend on the algorithm; the lines are diffed with `similar`,
+    /// whose LCS is a real longest-common-subsequence diff rather than git's `--minimal`.
+    fn diff_options(&self) -> DiffOptions {
+        let mut diff_opts = DiffOptions::new();
+        let ws = self.settings.whitespace;
+        diff_opts
+            .ignore_whitespace(ws == WhitespaceMode::IgnoreAll)
+            .ignore_whitespace_change(ws == WhitespaceMode::IgnoreAmount)
+            .ignore_whitespace_eol(ws == WhitespaceMode::IgnoreEol)
+            .ignore_blank_lines(ws == WhitespaceMode::IgnoreBlankLines);
+        diff_opts
+    }
+
     pub fn load_branches(&self) -> Result<Vec<BranchInfo>, git2::Error> {
         let mut branches = Vec::new();
         let current = self.current_branch.as_deref();
@@ -64,13 +171,7 @@ impl GitDiff {
 
         // Only show local changes if on current branch
         if Some(branch_name) == self.current_branch.as_deref() && self.has_local_changes()? {
-            commits.push(CommitInfo {
-                sha: String::new(),
-                short_sha: String::new(),
-                message: "Local Changes".to_string(),
-                author: String::new(),
-                is_local_changes: true,
-            });
+            commits.push(CommitInfo::local_changes("Local Changes"));
         }
 
         // Get commit history for the branch
@@ -122,9 +223,9 @@ impl GitDiff {
         Ok(false)
     }
 
-    pub fn load_files_for_commit(&self, commit_sha: &str) -> Result<Vec<FileChange>, git2::Error> {
+    pub fn load_files_for_commit(&mut self, commit_sha: &str) -> Result<Vec<FileChange>, git2::Error> {
         let mut files = Vec::new();
-        let mut diff_opts = DiffOptions::new();
+        let mut diff_opts = self.diff_options();
 
         let commit = self.repo.revparse_single(commit_sha)?.peel_to_commit()?;
         let tree = commit.tree()?;
@@ -135,33 +236,139 @@ impl GitDiff {
         let diff = self.repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), Some(&mut diff_opts))?;
         self.collect_files_from_diff(&diff, &mut files)?;
 
+        self.commit_changes = (files.len() <= MAX_MOVE_SCAN_FILES).then(|| {
+            let paths = files.iter().map(|file| file.path.clone()).collect();
+            (commit_sha.to_string(), CommitChanges::Pending(paths))
+        });
+
         Ok(files)
     }
 
-    pub fn load_diff_for_commit_file(&self, commit_sha: &str, file_path: &str) -> Result<Vec<DiffHunk>, git2::Error> {
+    /// Diff every file of the commit whose files were listed last, once, for moves between them
+    fn scan_commit_changes(&mut self) -> Result<(), git2::Error> {
+        let Some((ref commit_sha, CommitChanges::Pending(ref paths))) = self.commit_changes else {
+            return Ok(());
+        };
+        let mut changes = Vec::new();
+        for path in paths {
+            let (old, new) = self.commit_file_contents(commit_sha, path)?;
+            if Self::is_binary(path, &old.text, &new.text) {
+                continue;
+            }
+            let (old_content, new_content, _) = encoding::unify_line_endings(&old.text, &new.text);
+            let lines = self.diff_lines(&old_content, &new_content);
+            changes.push(ChangedLines::from_diff_lines(path, &lines));
+        }
+        if let Some((_, ref mut scanned)) = self.commit_changes {
+            *scanned = CommitChanges::Scanned(changes);
+        }
+        Ok(())
+    }
+
+    pub fn load_diff_for_commit_file(&mut self, commit_sha: &str, file_path: &str) -> Result<Vec<DiffHunk>, git2::Error> {
+        let (commit_id, old_id, new_id) = self.commit_file_ids(commit_sha, file_path)?;
+        let moves_scanned = matches!(self.commit_changes, Some((ref sha, _)) if sha == commit_sha);
+        let key = self.diff_key(file_path, old_id, new_id, if moves_scanned { commit_id } else { Oid::zero() });
+        if moves_scanned && self.cache.get_mut(&key).is_none() {
+            self.scan_commit_changes()?;
+        }
+
+        self.cached_hunks(Some(commit_sha), key, |git| {
+            let old = git.blob_content(old_id);
+            let new = git.blob_content(new_id);
+            let others = match git.commit_changes {
+                Some((ref sha, CommitChanges::Scanned(ref changes))) if sha == commit_sha => changes.as_slice(),
+                _ => &[],
+            };
+            git.compute_diff(file_path, &old, &new, others)
+        })
+    }
+
+    /// Hunks for `key` from the cache, computing them on a miss; the diff becomes the active one
+    fn cached_hunks(
+        &mut self,
+        commit_sha: Option<&str>,
+        key: DiffKey,
+        compute: impl FnOnce(&Self) -> Result<CachedDiff, git2::Error>,
+    ) -> Result<Vec<DiffHunk>, git2::Error> {
+        self.active = Some((commit_sha.map(str::to_string), key.clone()));
+        if let Some(diff) = self.cache.get_mut(&key) {
+            return Ok(diff.hunks.clone());
+        }
+
+        let diff = compute(self)?;
+        let hunks = diff.hunks.clone();
+        self.cache.insert(key, diff);
+        Ok(hunks)
+    }
+
+    /// Syntax highlighting for `lines` of a diff, parsing the file only as far as needed
+    pub fn highlight(&mut self, commit_sha: Option<&str>, file_path: &str, lines: &[LineRef]) -> Result<Highlights, git2::Error> {
+        let is_active = |active: &Option<(Option<String>, DiffKey)>| {
+            active.as_ref().is_some_and(|(sha, key)| sha.as_deref() == commit_sha && key.path == file_path)
+        };
+        if !is_active(&self.active) {
+            match commit_sha {
+                Some(sha) => self.load_diff_for_commit_file(sha, file_path)?,
+                None => self.load_diff_for_file(file_path)?,
+            };
+        }
+
+        let key = match self.active {
+            Some((_, ref key)) if is_active(&self.active) => key.clone(),
+            _ => return Ok(Highlights::Lines(vec![None; lines.len()])),
+        };
+        let Some(diff) = self.cache.get_mut(&key) else {
+            return Ok(Highlights::Lines(vec![None; lines.len()]));
+        };
+
+        let result = self.highlighter.highlight_diff(&mut diff.highlight, lines);
+        if let Highlights::Lines(ref spans) = result {
+            highlighter::apply_highlights(&mut diff.hunks, lines, spans.clone());
+            self.cache.update_size(&key);
+        }
+        Ok(result)
+    }
+
+    fn diff_key(&self, file_path: &str, old: Oid, new: Oid, move_scope: Oid) -> DiffKey {
+        DiffKey {
+            old,
+            new,
+            path: file_path.to_string(),
+            context_lines: self.context_lines,
+            settings: self.settings,
+            move_scope,
+        }
+    }
+
+    /// Commit id and the parent/commit blob ids of `file_path` (zero where the file doesn't exist)
+    fn commit_file_ids(&self, commit_sha: &str, file_path: &str) -> Result<(Oid, Oid, Oid), git2::Error> {
         let commit = self.repo.revparse_single(commit_sha)?.peel_to_commit()?;
         let tree = commit.tree()?;
         let parent_tree = commit.parent(0).ok().and_then(|p| p.tree().ok());
+        let path = std::path::Path::new(file_path);
 
-        let old_content = parent_tree
+        let old_id = parent_tree
             .as_ref()
-            .and_then(|t| t.get_path(std::path::Path::new(file_path)).ok())
-            .and_then(|entry| self.repo.find_blob(entry.id()).ok())
-            .map(|blob| String::from_utf8_lossy(blob.content()).to_string())
-            .unwrap_or_default();
+            .and_then(|t| t.get_path(path).ok())
+            .map_or(Oid::zero(), |entry| entry.id());
+        let new_id = tree.get_path(path).ok().map_or(Oid::zero(), |entry| entry.id());
 
-        let new_content = tree
-            .get_path(std::path::Path::new(file_path))
-            .ok()
-            .and_then(|entry| self.repo.find_blob(entry.id()).ok())
-            .map(|blob| String::from_utf8_lossy(blob.content()).to_string())
-            .unwrap_or_default();
+        Ok((commit.id(), old_id, new_id))
+    }
+
+    fn commit_file_contents(&self, commit_sha: &str, file_path: &str) -> Result<(Decoded, Decoded), git2::Error> {
+        let (_, old_id, new_id) = self.commit_file_ids(commit_sha, file_path)?;
+        Ok((self.blob_content(old_id), self.blob_content(new_id)))
+    }
 
-        self.compute_diff(file_path, &old_content, &new_content)
+    fn blob_content(&self, id: Oid) -> Decoded {
+        let blob = (!id.is_zero()).then(|| self.repo.find_blob(id).ok()).flatten();
+        encoding::decode(blob.as_ref().map_or(&[], |blob| blob.content()), None)
     }
 
     pub fn load_files(&self) -> Result<Vec<FileChange>, git2::Error> {
-        let mut diff_opts = DiffOptions::new();
+        let mut diff_opts = self.diff_options();
         diff_opts.include_untracked(true);
 
         let head_tree = self.repo.head().ok().and_then(|h| h.peel_to_tree().ok());
@@ -183,50 +390,102 @@ impl GitDiff {
             let unstaged = self.repo.diff_index_to_workdir(None, Some(&mut diff_opts))?;
 
             for diff in [&staged, &unstaged] {
-                diff.foreach(
-                    &mut |delta, _| {
-                        if let Some(path) = delta.new_file().path().or(delta.old_file().path()) {
-                            let path_str = path.to_string_lossy().to_string();
-                            if !path_str.starts_with("target/") && !files.iter().any(|f: &FileChange| f.path == path_str) {
-                                files.push(FileChange {
-                                    path: path_str,
-                                    status: Self::delta_to_status(delta.status()),
-                                });
-                            }
-                        }
-                        true
-                    },
-                    None,
-                    None,
-                    None,
-                )?;
+                self.collect_files_from_diff(diff, &mut files)?;
             }
         }
 
         Ok(files)
     }
 
+    /// Patch of `commit` (or of the local changes) as `git format-patch` would write it
+    pub fn format_patch(&self, commit: &CommitInfo) -> Result<String, git2::Error> {
+        let mut email_opts = EmailCreateOptions::new();
+
+        if !commit.is_local_changes {
+            let commit = self.repo.revparse_single(&commit.sha)?.peel_to_commit()?;
+            let email = if commit.parent_count() <= 1 {
+                Email::from_commit(&commit, &mut email_opts)?
+            } else {
+                // libgit2 refuses merges; use the diff against the first parent
+                let parent_tree = commit.parent(0)?.tree()?;
+                let diff = self.repo.diff_tree_to_tree(Some(&parent_tree), Some(&commit.tree()?), None)?;
+                Email::from_diff(
+                    &diff,
+                    1,
+                    1,
+                    &commit.id(),
+                    commit.summary().unwrap_or(""),
+                    commit.body().unwrap_or(""),
+                    &commit.author(),
+                    &mut email_opts,
+                )?
+            };
+            return Ok(String::from_utf8_lossy(email.as_slice()).to_string());
+        }
+
+        let mut diff_opts = DiffOptions::new();
+        diff_opts
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .show_untracked_content(true);
+        let head_tree = self.repo.head().ok().and_then(|h| h.peel_to_tree().ok());
+
+        let diff = if self.staged {
+            self.repo.diff_tree_to_index(head_tree.as_ref(), None, Some(&mut diff_opts))?
+        } else if let Some(ref commit_ref) = self.commit {
+            let tree = self.repo.revparse_single(commit_ref)?.peel_to_commit()?.tree()?;
+            self.repo.diff_tree_to_workdir_with_index(Some(&tree), Some(&mut diff_opts))?
+        } else {
+            self.repo.diff_tree_to_workdir_with_index(head_tree.as_ref(), Some(&mut diff_opts))?
+        };
+
+        let author = self
+            .repo
+            .signature()
+            .or_else(|_| git2::Signature::now("gitti", "gitti@localhost"))?;
+        let email = Email::from_diff(
+            &diff,
+            1,
+            1,
+            &git2::Oid::zero(),
+            commit.message.as_str(),
+            "",
+            &author,
+            &mut email_opts,
+        )?;
+        Ok(String::from_utf8_lossy(email.as_slice()).to_string())
+    }
+
     fn collect_files_from_diff(&self, diff: &git2::Diff, files: &mut Vec<FileChange>) -> Result<(), git2::Error> {
-        diff.foreach(
-            &mut |delta, _| {
-                if let Some(path) = delta.new_file().path().or(delta.old_file().path()) {
-                    let path_str = path.to_string_lossy().to_string();
-                    if !path_str.starts_with("target/") {
-                        files.push(FileChange {
-                            path: path_str,
-                            status: Self::delta_to_status(delta.status()),
-                        });
-                    }
+        for (idx, delta) in diff.deltas().enumerate() {
+            if let Some(path) = delta.new_file().path().or(delta.old_file().path()) {
+                let path_str = path.to_string_lossy().to_string();
+                if path_str.starts_with("target/") || files.iter().any(|f| f.path == path_str) {
+                    continue;
                 }
-                true
-            },
-            None,
-            None,
-            None,
-        )?;
+                if delta.status() == git2::Delta::Modified && self.is_ignored_change(diff, idx)? {
+                    continue;
+                }
+                files.push(FileChange {
+                    path: path_str,
+                    status: Self::delta_to_status(delta.status()),
+                });
+            }
+        }
         Ok(())
     }
 
+    /// True when the whitespace mode hides every change in the delta
+    fn is_ignored_change(&self, diff: &git2::Diff, idx: usize) -> Result<bool, git2::Error> {
+        if self.settings.whitespace == WhitespaceMode::None {
+            return Ok(false);
+        }
+        Ok(match git2::Patch::from_diff(diff, idx)? {
+            Some(patch) => patch.num_hunks() == 0,
+            None => false,
+        })
+    }
+
     fn delta_to_status(delta: git2::Delta) -> String {
         match delta {
             git2::Delta::Added => "added".to_string(),
@@ -236,10 +495,11 @@ impl GitDiff {
         }
     }
 
-    pub fn load_diff_for_file(&self, file_path: &str) -> Result<Vec<DiffHunk>, git2::Error> {
-        let (old_content, new_content) = match self.get_file_contents(file_path) {
+    pub fn load_diff_for_file(&mut self, file_path: &str) -> Result<Vec<DiffHunk>, git2::Error> {
+        let FileBytes { old: old_bytes, new: new_bytes, new_encoding } = match self.get_file_contents(file_path) {
             Ok(contents) => contents,
             Err(_) => {
+                self.active = None;
                 return Ok(vec![DiffHunk {
                     lines: vec![DiffLine {
                         old_num: None,
@@ -247,133 +507,308 @@ impl GitDiff {
                         tag: ChangeTag::Insert,
                         content: "[Unable to read file]".to_string(),
                         highlighted: None,
+                        moved: None,
+                        no_newline: false,
+                        old_content: None,
                     }],
                 }]);
             }
         };
 
-        self.compute_diff(file_path, &old_content, &new_content)
+        // Worktree content has no blob yet, so hash it the way git would
+        let old_id = Oid::hash_object(ObjectType::Blob, &old_bytes)?;
+        let new_id = Oid::hash_object(ObjectType::Blob, &new_bytes)?;
+        let key = self.diff_key(file_path, old_id, new_id, Oid::zero());
+
+        self.cached_hunks(None, key, |git| {
+            let mut old = encoding::decode(&old_bytes, None);
+            let new = encoding::decode(&new_bytes, new_encoding);
+            if let Some(declared) = new_encoding {
+                // The blob is git's UTF-8 copy of a file kept in the declared encoding
+                old.encoding = declared;
+            }
+            git.compute_diff(file_path, &old, &new, &[])
+        })
     }
 
-    fn compute_diff(&self, file_path: &str, old_content: &str, new_content: &str) -> Result<Vec<DiffHunk>, git2::Error> {
-        // Skip binary files
+    /// The diff lines numbered from `from` up to (not including) `to`, to widen the context
+    /// between two hunks. Taken from the full diff, since with blank lines ignored the gap
+    /// can still hold blank line changes; a side of `to` without a number is unbounded.
+    pub fn context(&self, commit_sha: Option<&str>, file_path: &str, from: LineRef, to: LineRef) -> Result<Vec<DiffLine>, git2::Error> {
+        let (Some(old_from), Some(new_from)) = from else {
+            return Ok(Vec::new());
+        };
+        let (old, new) = match commit_sha {
+            Some(sha) => self.commit_file_contents(sha, file_path)?,
+            None => {
+                let FileBytes { old, new, new_encoding } = self.get_file_contents(file_path)?;
+                (encoding::decode(&old, None), encoding::decode(&new, new_encoding))
+            }
+        };
+        let (old_content, new_content, _) = encoding::unify_line_endings(&old.text, &new.text);
+
+        let in_gap = |num: Option<u32>, from: u32, to: Option<u32>| num.is_none_or(|num| num >= from && to.is_none_or(|to| num < to));
+        let mut lines = self.diff_lines(&old_content, &new_content);
+        lines.retain(|line| in_gap(line.old_num, old_from, to.0) && in_gap(line.new_num, new_from, to.1));
+        Ok(lines)
+    }
+
+    /// Add the changes on `lines` of a local change to the index and leave the file's other
+    /// changes unstaged. Deleted lines are numbered on the HEAD side, added ones in the
+    /// worktree, as in the diff. Returns how many lines were staged.
+    pub fn stage_lines(&self, file_path: &str, lines: &[LineRef]) -> Result<usize, git2::Error> {
+        if self.staged || self.commit.is_some() {
+            return Err(git2::Error::from_str("only unstaged changes against HEAD can be staged"));
+        }
+        let FileBytes { old: head, new: worktree, new_encoding } = self.get_file_contents(file_path)?;
+        // The index keeps files with a `working-tree-encoding` as UTF-8
+        let worktree = match new_encoding {
+            Some(declared) => encoding::decode(&worktree, Some(declared)).text.into_bytes(),
+            None if [UTF_16LE, UTF_16BE].contains(&encoding::decode(&worktree, None).encoding) => {
+                return Err(git2::Error::from_str("lines of UTF-16 files can't be staged"));
+            }
+            None => worktree,
+        };
+
+        let mut index = self.repo.index()?;
+        let entry = index.get_path(std::path::Path::new(file_path), 0);
+        let staged = entry
+            .as_ref()
+            .and_then(|entry| self.repo.find_blob(entry.id).ok())
+            .map(|blob| blob.content().to_vec())
+            .unwrap_or_default();
+
+        let deleted: HashSet<u32> = lines.iter().filter(|(_, new)| new.is_none()).filter_map(|(old, _)| *old).collect();
+        let added: HashSet<u32> = lines.iter().filter(|(old, _)| old.is_none()).filter_map(|(_, new)| *new).collect();
+        let (head, staged, worktree) = (split_lines(&head), split_lines(&staged), split_lines(&worktree));
+        let algorithm = self.settings.algorithm.to_similar();
+
+        // Deleted HEAD lines the index still has, by their index in the staged file
+        let mut unstaged_deletions = HashSet::new();
+        for op in similar::capture_diff_slices(algorithm, &line_keys(&head), &line_keys(&staged)) {
+            if let DiffOp::Equal { old_index, new_index, len } = op {
+                unstaged_deletions.extend((0..len).filter(|k| deleted.contains(&((old_index + k + 1) as u32))).map(|k| new_index + k));
+            }
+        }
+
+        let mut content = Vec::new();
+        let mut push = |line: &[u8]| {
+            if !content.is_empty() && !content.ends_with(b"\n") {
+                content.push(b'\n');
+            }
+            content.extend_from_slice(line);
+        };
+        let mut count = 0;
+        for op in similar::capture_diff_slices(algorithm, &line_keys(&staged), &line_keys(&worktree)) {
+            let (tag, old_range, new_range) = op.as_tag_tuple();
+            for i in old_range {
+                if tag != DiffTag::Equal && unstaged_deletions.contains(&i) {
+                    count += 1;
+                } else {
+                    push(staged[i]);
+                }
+            }
+            for n in new_range.filter(|n| tag != DiffTag::Equal && added.contains(&(*n as u32 + 1))) {
+                push(worktree[n]);
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return Ok(0);
+        }
+
+        // A fresh entry: the old one's stat data describes a file that no longer matches the blob
+        let entry = IndexEntry {
+            ctime: IndexTime::new(0, 0),
+            mtime: IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            mode: entry.map_or(0o100644, |entry| entry.mode),
+            uid: 0,
+            gid: 0,
+            file_size: 0,
+            id: Oid::zero(),
+            flags: 0,
+            flags_extended: 0,
+            path: file_path.as_bytes().to_vec(),
+        };
+        index.add_frombuffer(&entry, &content)?;
+        index.write()?;
+        Ok(count)
+    }
+
+    fn is_binary(file_path: &str, old_content: &str, new_content: &str) -> bool {
         let binary_extensions = [
             "png", "jpg", "jpeg", "gif", "ico", "pdf", "zip", "tar", "gz", "bin", "exe", "dll",
             "so", "dylib", "o", "a", "class", "jar", "rlib", "rmeta", "d",
         ];
         if let Some(ext) = std::path::Path::new(file_path).extension() {
             if binary_extensions.contains(&ext.to_str().unwrap_or("").to_lowercase().as_str()) {
-                return Ok(vec![DiffHunk {
+                return true;
+            }
+        }
+
+        // Check if content looks binary
+        old_content.contains('\0') || new_content.contains('\0')
+    }
+
+    fn compute_diff(
+        &self,
+        file_path: &str,
+        old: &Decoded,
+        new: &Decoded,
+        other_changes: &[ChangedLines],
+    ) -> Result<CachedDiff, git2::Error> {
+        // Skip binary files
+        if Self::is_binary(file_path, &old.text, &new.text) {
+            return Ok(CachedDiff {
+                hunks: vec![DiffHunk {
                     lines: vec![DiffLine {
                         old_num: None,
                         new_num: Some(1),
                         tag: ChangeTag::Insert,
                         content: "[Binary file]".to_string(),
                         highlighted: None,
+                        moved: None,
+                        no_newline: false,
+                        old_content: None,
                     }],
-                }]);
-            }
+                }],
+                highlight: self.highlighter.prepare(file_path, Vec::new(), Vec::new()),
+            });
         }
 
-        // Check if content looks binary
-        if old_content.contains('\0') || new_content.contains('\0') {
-            return Ok(vec![DiffHunk {
-                lines: vec![DiffLine {
-                    old_num: None,
-                    new_num: Some(1),
-                    tag: ChangeTag::Insert,
-                    content: "[Binary file]".to_string(),
-                    highlighted: None,
-                }],
-            }]);
+        let (old_content, new_content, line_endings) = encoding::unify_line_endings(&old.text, &new.text);
+        let mut all_lines = self.diff_lines(&old_content, &new_content);
+        moves::mark_moves(file_path, &mut all_lines, other_changes);
+
+        let mut hunks = self.extract_hunks(&all_lines);
+        let notices: Vec<DiffLine> =
+            encoding::notices(old, new).into_iter().chain(line_endings).map(DiffLine::notice).collect();
+        if !notices.is_empty() {
+            hunks.insert(0, DiffHunk { lines: notices });
         }
 
-        let text_diff = TextDiff::from_lines(old_content, new_content);
+        // Highlighting happens later, for the lines that are actually looked at
+        Ok(CachedDiff {
+            hunks,
+            highlight: self.highlighter.prepare(file_path, Self::numbered_lines(&old_content), Self::numbered_lines(&new_content)),
+        })
+    }
+
+    /// Lines of a file with their 1-based numbers, split the same way `diff_lines` does
+    fn numbered_lines(content: &str) -> Vec<(u32, String)> {
+        content
+            .split_inclusive('\n')
+            .enumerate()
+            .map(|(idx, line)| (idx as u32 + 1, line.trim_end_matches('\n').to_string()))
+            .collect()
+    }
 
-        let line_contents: Vec<String> = text_diff
-            .iter_all_changes()
-            .map(|c| c.value().trim_end_matches('\n').to_string())
-            .collect();
+    /// Full change stream between two texts under the current algorithm/whitespace settings
+    fn diff_lines(&self, old_content: &str, new_content: &str) -> Vec<DiffLine> {
+        let old_lines: Vec<&str> = old_content.split_inclusive('\n').collect();
+        let new_lines: Vec<&str> = new_content.split_inclusive('\n').collect();
+        let ws = self.settings.whitespace;
+        let old_keys: Vec<String> = old_lines.iter().map(|l| ws.normalize(l)).collect();
+        let new_keys: Vec<String> = new_lines.iter().map(|l| ws.normalize(l)).collect();
 
-        let highlighted = self.highlighter.highlight_lines(file_path, &line_contents);
+        let ops = similar::capture_diff_slices(self.settings.algorithm.to_similar(), &old_keys, &new_keys);
 
         let mut all_lines: Vec<DiffLine> = Vec::new();
-        let mut old_line = 1u32;
-        let mut new_line = 1u32;
-
-        for (idx, change) in text_diff.iter_all_changes().enumerate() {
-            let (old_num, new_num) = match change.tag() {
-                ChangeTag::Delete => {
-                    let n = old_line;
-                    old_line += 1;
-                    (Some(n), None)
-                }
-                ChangeTag::Insert => {
-                    let n = new_line;
-                    new_line += 1;
-                    (None, Some(n))
-                }
-                ChangeTag::Equal => {
-                    let (o, n) = (old_line, new_line);
-                    old_line += 1;
-                    new_line += 1;
-                    (Some(o), Some(n))
+        for op in &ops {
+            let (tag, old_range, new_range) = op.as_tag_tuple();
+            if tag == DiffTag::Equal {
+                // Lines may differ in ignored whitespace; show the new side
+                for (o, n) in old_range.zip(new_range) {
+                    let mut line = Self::make_line(Some(o), Some(n), ChangeTag::Equal, new_lines[n]);
+                    if old_lines[o] != new_lines[n] {
+                        line.old_content = Some(old_lines[o].to_string());
+                    }
+                    all_lines.push(line);
                 }
-            };
+                continue;
+            }
+            for o in old_range {
+                all_lines.push(Self::make_line(Some(o), None, ChangeTag::Delete, old_lines[o]));
+            }
+            for n in new_range {
+                all_lines.push(Self::make_line(None, Some(n), ChangeTag::Insert, new_lines[n]));
+            }
+        }
 
-            all_lines.push(DiffLine {
-                old_num,
-                new_num,
-                tag: change.tag(),
-                content: change.value().trim_end_matches('\n').to_string(),
-                highlighted: highlighted.get(idx).cloned(),
-            });
+        all_lines
+    }
+
+    fn make_line(old_idx: Option<usize>, new_idx: Option<usize>, tag: ChangeTag, text: &str) -> DiffLine {
+        DiffLine {
+            old_num: old_idx.map(|i| i as u32 + 1),
+            new_num: new_idx.map(|i| i as u32 + 1),
+            tag,
+            content: text.trim_end_matches('\n').to_string(),
+            highlighted: None,
+            moved: None,
+            no_newline: !text.ends_with('\n'),
+            old_content: None,
         }
+    }
 
-        Ok(self.extract_hunks(&all_lines))
+    /// Which lines are changes that need a hunk. Under `IgnoreBlankLines` a run of changed
+    /// lines that are all blank doesn't; it still shows as changes (keeping both sides'
+    /// numbering intact) when it falls inside a hunk's context.
+    fn significant_changes(&self, lines: &[DiffLine]) -> Vec<bool> {
+        let mut significant: Vec<bool> = lines.iter().map(|l| l.tag != ChangeTag::Equal).collect();
+        if self.settings.whitespace != WhitespaceMode::IgnoreBlankLines {
+            return significant;
+        }
+        let mut i = 0;
+        while i < lines.len() {
+            if lines[i].tag == ChangeTag::Equal {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < lines.len() && lines[i].tag != ChangeTag::Equal {
+                i += 1;
+            }
+            if lines[start..i].iter().all(|l| l.content.trim().is_empty()) {
+                significant[start..i].fill(false);
+            }
+        }
+        significant
     }
 
     fn extract_hunks(&self, lines: &[DiffLine]) -> Vec<DiffHunk> {
+        let significant = self.significant_changes(lines);
         let mut hunks = Vec::new();
         let mut i = 0;
         let ctx = self.context_lines;
 
         while i < lines.len() {
-            if lines[i].tag != ChangeTag::Equal {
+            if significant[i] {
                 let mut hunk_lines = Vec::new();
 
                 let start = i.saturating_sub(ctx);
-                for j in start..i {
-                    hunk_lines.push(DiffLine {
-                        old_num: lines[j].old_num,
-                        new_num: lines[j].new_num,
-                        tag: lines[j].tag,
-                        content: lines[j].content.clone(),
-                        highlighted: lines[j].highlighted.clone(),
-                    });
-                }
+                hunk_lines.extend_from_slice(&lines[start..i]);
+
+                loop {
+                    while i < lines.len() && significant[i] {
+                        hunk_lines.push(lines[i].clone());
+                        i += 1;
+                    }
 
-                while i < lines.len() && lines[i].tag != ChangeTag::Equal {
-                    hunk_lines.push(DiffLine {
-                        old_num: lines[i].old_num,
-                        new_num: lines[i].new_num,
-                        tag: lines[i].tag,
-                        content: lines[i].content.clone(),
-                        highlighted: lines[i].highlighted.clone(),
-                    });
-                    i += 1;
+                    // Merge with the next change when the context between them would overlap
+                    match (i..lines.len()).find(|&j| significant[j]) {
+                        Some(next) if next - i <= 2 * ctx => {
+                            hunk_lines.extend_from_slice(&lines[i..next]);
+                            i = next;
+                        }
+                        _ => break,
+                    }
                 }
 
                 let end = (i + ctx).min(lines.len());
-                for j in i..end {
-                    hunk_lines.push(DiffLine {
-                        old_num: lines[j].old_num,
-                        new_num: lines[j].new_num,
-                        tag: lines[j].tag,
-                        content: lines[j].content.clone(),
-                        highlighted: lines[j].highlighted.clone(),
-                    });
-                }
+                hunk_lines.extend_from_slice(&lines[i..end]);
                 i = end;
 
                 hunks.push(DiffHunk { lines: hunk_lines });
@@ -385,19 +820,17 @@ impl GitDiff {
         hunks
     }
 
-    fn get_file_contents(&self, path: &str) -> Result<(String, String), git2::Error> {
+    fn get_file_contents(&self, path: &str) -> Result<FileBytes, git2::Error> {
         let workdir = self.repo.workdir().unwrap();
+        let blob_bytes = |id: Oid| self.repo.find_blob(id).ok().map(|blob| blob.content().to_vec());
 
         let old_content = if let Some(ref commit_ref) = self.commit {
             let obj = self.repo.revparse_single(commit_ref)?;
             let commit = obj.peel_to_commit()?;
             let tree = commit.tree()?;
             match tree.get_path(std::path::Path::new(path)) {
-                Ok(entry) => {
-                    let blob = self.repo.find_blob(entry.id())?;
-                    String::from_utf8_lossy(blob.content()).to_string()
-                }
-                Err(_) => String::new(),
+                Ok(entry) => self.repo.find_blob(entry.id())?.content().to_vec(),
+                Err(_) => Vec::new(),
             }
         } else {
             self.repo
@@ -405,32 +838,245 @@ impl GitDiff {
                 .ok()
                 .and_then(|h| h.peel_to_tree().ok())
                 .and_then(|tree| tree.get_path(std::path::Path::new(path)).ok())
-                .and_then(|entry| self.repo.find_blob(entry.id()).ok())
-                .map(|blob| String::from_utf8_lossy(blob.content()).to_string())
+                .and_then(|entry| blob_bytes(entry.id()))
                 .unwrap_or_default()
         };
 
-        let new_content = {
-            let index_content = self.repo.index().ok().and_then(|index| {
-                index
-                    .get_path(std::path::Path::new(path), 0)
-                    .and_then(|entry| {
-                        self.repo
-                            .find_blob(entry.id)
-                            .ok()
-                            .map(|blob| String::from_utf8_lossy(blob.content()).to_string())
-                    })
-            });
+        let index_content = self
+            .repo
+            .index()
+            .ok()
+            .and_then(|index| index.get_path(std::path::Path::new(path), 0))
+            .and_then(|entry| blob_bytes(entry.id));
 
-            if self.staged {
-                index_content.unwrap_or_default()
-            } else {
-                let file_path = workdir.join(path);
-                std::fs::read_to_string(&file_path)
-                    .unwrap_or_else(|_| index_content.unwrap_or_default())
+        if self.staged {
+            return Ok(FileBytes { old: old_content, new: index_content.unwrap_or_default(), new_encoding: None });
+        }
+        match std::fs::read(workdir.join(path)) {
+            Ok(bytes) => {
+                let declared = self
+                    .repo
+                    .get_attr(std::path::Path::new(path), "working-tree-encoding", AttrCheckFlags::default())
+                    .ok()
+                    .flatten()
+                    .and_then(|label| Encoding::for_label(label.as_bytes()));
+                Ok(FileBytes { old: old_content, new: bytes, new_encoding: declared })
             }
-        };
+            Err(_) => Ok(FileBytes { old: old_content, new: index_content.unwrap_or_default(), new_encoding: None }),
+        }
+    }
+}
+
+/// Lines of a file with their line endings
+fn split_lines(bytes: &[u8]) -> Vec<&[u8]> {
+    bytes.split_inclusive(|b| *b == b'\n').collect()
+}
+
+/// Lines to match up when staging: a changed line ending alone doesn't make a line different
+fn line_keys<'a>(lines: &[&'a [u8]]) -> Vec<&'a [u8]> {
+    lines
+        .iter()
+        .map(|line| {
+            let line = line.strip_suffix(b"\n").unwrap_or(line);
+            line.strip_suffix(b"\r").unwrap_or(line)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::types::DiffAlgorithm;
+    use std::path::PathBuf;
+
+    /// A throwaway repository in the temp directory
+    pub struct TestRepo {
+        pub dir: PathBuf,
+        pub repo: Repository,
+    }
+
+    impl TestRepo {
+        pub fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("gitti-test-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            let repo = Repository::init(&dir).unwrap();
+            Self { dir, repo }
+        }
+
+        pub fn write(&self, path: &str, content: &str) {
+            std::fs::write(self.dir.join(path), content).unwrap();
+        }
+
+        /// Commit every file in the worktree
+        pub fn commit(&self, message: &str) {
+            let mut index = self.repo.index().unwrap();
+            index.add_all(["*"], git2::IndexAddOption::DEFAULT, None).unwrap();
+            index.write().unwrap();
+            let tree = self.repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let signature = git2::Signature::now("test", "test@example.com").unwrap();
+            let parent = self.repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+            let parents: Vec<&git2::Commit> = parent.iter().collect();
+            self.repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents).unwrap();
+        }
+
+        /// Stage `path` as it is in the worktree
+        pub fn add(&self, path: &str) {
+            let mut index = self.repo.index().unwrap();
+            index.add_path(std::path::Path::new(path)).unwrap();
+            index.write().unwrap();
+        }
+
+        /// Content of the staged blob of `path`
+        pub fn staged(&self, path: &str) -> String {
+            let index = Repository::open(&self.dir).unwrap().index().unwrap();
+            let entry = index.get_path(std::path::Path::new(path), 0).unwrap();
+            String::from_utf8(self.repo.find_blob(entry.id).unwrap().content().to_vec()).unwrap()
+        }
+
+        /// Local changes against HEAD, diffed with `whitespace`
+        pub fn diff(&self, whitespace: WhitespaceMode) -> GitDiff {
+            let settings = DiffSettings { algorithm: DiffAlgorithm::Myers, whitespace };
+            GitDiff::with_repo(Repository::open(&self.dir).unwrap(), false, None, 3, settings, 1 << 20)
+        }
+    }
+
+    impl Drop for TestRepo {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn ignored_blank_lines_keep_both_numberings() {
+        let repo = TestRepo::new("blank-lines");
+        repo.write("a.txt", "a\n\nb\nc\nd\n");
+        repo.commit("init");
+        repo.write("a.txt", "a\nb\nC\nd\n");
+
+        let hunks = repo.diff(WhitespaceMode::IgnoreBlankLines).load_diff_for_file("a.txt").unwrap();
+        let lines: Vec<&DiffLine> = hunks.iter().flat_map(|h| &h.lines).collect();
+        assert!(lines.iter().filter(|l| l.tag == ChangeTag::Equal).all(|l| l.old_num.is_some() && l.new_num.is_some()));
+        let blank = lines.iter().find(|l| l.content.is_empty()).unwrap();
+        assert_eq!((blank.tag, blank.old_num, blank.new_num), (ChangeTag::Delete, Some(2), None));
+    }
+
+    #[test]
+    fn expanded_context_keeps_ignored_blank_line_changes() {
+        let repo = TestRepo::new("context-blank-lines");
+        let old: Vec<String> = (1..=20).map(|n| if n == 10 { String::new() } else { format!("line {}", n) }).collect();
+        let mut new = old.clone();
+        new[1] = "changed 2".to_string();
+        new[18] = "changed 19".to_string();
+        new.remove(9);
+        repo.write("a.txt", &(old.join("\n") + "\n"));
+        repo.commit("init");
+        repo.write("a.txt", &(new.join("\n") + "\n"));
+
+        let mut diff = repo.diff(WhitespaceMode::IgnoreBlankLines);
+        let hunks = diff.load_diff_for_file("a.txt").unwrap();
+        assert_eq!(hunks.len(), 2);
+        let last = |side: fn(&DiffLine) -> Option<u32>| hunks[0].lines.iter().filter_map(side).max().map(|n| n + 1);
+        let first = |side: fn(&DiffLine) -> Option<u32>| hunks[1].lines.iter().find_map(side);
+        let from = (last(|l| l.old_num), last(|l| l.new_num));
+        let to = (first(|l| l.old_num), first(|l| l.new_num));
+        let gap = diff.context(None, "a.txt", from, to).unwrap();
+
+        let blank: Vec<&DiffLine> = gap.iter().filter(|l| l.tag != ChangeTag::Equal).collect();
+        assert_eq!(blank.len(), 1);
+        assert_eq!((blank[0].tag, blank[0].old_num, blank[0].content.as_str()), (ChangeTag::Delete, Some(10), ""));
+        // Hunk above, gap and hunk below number both sides without holes or overlaps
+        let joined: Vec<&DiffLine> = hunks[0].lines.iter().chain(&gap).chain(&hunks[1].lines).collect();
+        let old_nums: Vec<u32> = joined.iter().filter_map(|l| l.old_num).collect();
+        let new_nums: Vec<u32> = joined.iter().filter_map(|l| l.new_num).collect();
+        assert_eq!(old_nums, (1..=20).collect::<Vec<_>>());
+        assert_eq!(new_nums, (1..=19).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn only_blank_line_changes_make_no_hunks() {
+        let repo = TestRepo::new("only-blank-lines");
+        repo.write("a.txt", "x\ny\n");
+        repo.commit("init");
+        repo.write("a.txt", "x\n\ny\n");
+
+        assert!(repo.diff(WhitespaceMode::IgnoreBlankLines).load_diff_for_file("a.txt").unwrap().is_empty());
+        assert_eq!(repo.diff(WhitespaceMode::None).load_diff_for_file("a.txt").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn stages_part_of_an_added_run() {
+        let repo = TestRepo::new("stage-added");
+        repo.write("a.txt", "a\nb\n");
+        repo.commit("init");
+        repo.write("a.txt", "a\nx\ny\nz\nb\n");
+
+        let staged = repo.diff(WhitespaceMode::None).stage_lines("a.txt", &[(None, Some(3))]).unwrap();
+        assert_eq!(staged, 1);
+        assert_eq!(repo.staged("a.txt"), "a\ny\nb\n");
+    }
+
+    #[test]
+    fn stages_part_of_a_deleted_run() {
+        let repo = TestRepo::new("stage-deleted");
+        repo.write("a.txt", "a\nx\ny\nz\nb\n");
+        repo.commit("init");
+        repo.write("a.txt", "a\nb\n");
+
+        let staged = repo.diff(WhitespaceMode::None).stage_lines("a.txt", &[(Some(2), None), (Some(4), None)]).unwrap();
+        assert_eq!(staged, 2);
+        assert_eq!(repo.staged("a.txt"), "a\ny\nb\n");
+    }
+
+    #[test]
+    fn stages_lines_of_an_untracked_file() {
+        let repo = TestRepo::new("stage-untracked");
+        repo.write("a.txt", "a\n");
+        repo.commit("init");
+        repo.write("new.txt", "one\ntwo\nthree\n");
+
+        let staged = repo.diff(WhitespaceMode::None).stage_lines("new.txt", &[(None, Some(1)), (None, Some(3))]).unwrap();
+        assert_eq!(staged, 2);
+        assert_eq!(repo.staged("new.txt"), "one\nthree\n");
+    }
+
+    #[test]
+    fn stages_lines_on_top_of_staged_changes() {
+        let repo = TestRepo::new("stage-on-staged");
+        repo.write("a.txt", "a\nb\nc\nd\n");
+        repo.commit("init");
+        repo.write("a.txt", "A\nb\nc\nd\n");
+        repo.add("a.txt");
+        repo.write("a.txt", "A\nb\nx\nd\ny\n");
+
+        // HEAD line 3 (`c`) deleted and worktree line 5 (`y`) added; `x` stays unstaged
+        let staged = repo.diff(WhitespaceMode::None).stage_lines("a.txt", &[(Some(3), None), (None, Some(5))]).unwrap();
+        assert_eq!(staged, 2);
+        assert_eq!(repo.staged("a.txt"), "A\nb\nd\ny\n");
+        let status = Repository::open(&repo.dir).unwrap().status_file(std::path::Path::new("a.txt")).unwrap();
+        assert_eq!(status, git2::Status::INDEX_MODIFIED | git2::Status::WT_MODIFIED);
+    }
+
+    #[test]
+    fn moves_between_files_of_a_commit() {
+        let repo = TestRepo::new("commit-moves");
+        let block = "fn moved() {\n    let first = 1;\n    let second = 2;\n}\n";
+        repo.write("a.rs", &format!("fn stays() {{}}\n{}", block));
+        repo.write("b.rs", "fn other() {}\n");
+        repo.commit("init");
+        repo.write("a.rs", "fn stays() {}\n");
+        repo.write("b.rs", &format!("fn other() {{}}\n{}", block));
+        repo.commit("move");
+
+        let sha = repo.repo.head().unwrap().target().unwrap().to_string();
+        let mut git = repo.diff(WhitespaceMode::None);
+        assert_eq!(git.load_files_for_commit(&sha).unwrap().len(), 2);
+        // Listing the files doesn't diff them yet
+        assert!(matches!(git.commit_changes, Some((_, CommitChanges::Pending(_)))));
 
-        Ok((old_content, new_content))
+        let hunks = git.load_diff_for_commit_file(&sha, "a.rs").unwrap();
+        let moved: Vec<(String, u32)> =
+            hunks.iter().flat_map(|h| &h.lines).filter_map(|l| l.moved.as_ref().map(|m| (m.path.clone(), m.line))).collect();
+        assert_eq!(moved, [("b.rs".to_string(), 2), ("b.rs".to_string(), 3), ("b.rs".to_string(), 4), ("b.rs".to_string(), 5)]);
     }
 }
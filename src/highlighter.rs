@@ -1,54 +1,220 @@
+use std::collections::HashMap;
 use std::path::Path;
-use syntect::easy::HighlightLines;
-use syntect::highlighting::{Style, ThemeSet};
-use syntect::parsing::SyntaxSet;
+use syntect::highlighting::{Color, HighlightIterator, HighlightState, Style, Theme, ThemeSet};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
 use two_face::syntax::extra_newlines;
 
+use crate::config;
+use crate::types::{DiffHunk, Highlights, LineRef};
+
+/// Lines between saved parser states; highlighting line n re-parses at most this many lines first
+const CHECKPOINT_INTERVAL: usize = 256;
+
+/// Files bigger than this (old and new together) are not highlighted at all, unless
+/// `--max-highlight-size` says otherwise
+pub const MAX_HIGHLIGHT_BYTES: usize = 4 * 1024 * 1024;
+
+const DEFAULT_THEME: &str = "base16-eighties.dark";
+
+/// Lines to highlight on demand, with parser checkpoints so any line can be reached
+/// without starting over from the top of the file.
+struct HighlightStream {
+    /// Newline-terminated, as the syntax definitions expect
+    lines: Vec<String>,
+    /// Line number of each stream line, ascending (with gaps when only patch hunks are known)
+    numbers: Vec<u32>,
+    /// Parser state before line `i * CHECKPOINT_INTERVAL`
+    checkpoints: Vec<(ParseState, ScopeStack)>,
+}
+
+impl HighlightStream {
+    fn size(&self) -> usize {
+        self.lines.iter().map(|l| l.len() + std::mem::size_of::<String>()).sum::<usize>()
+            + self.numbers.len() * std::mem::size_of::<u32>()
+            + self.checkpoints.len() * std::mem::size_of::<(ParseState, ScopeStack)>()
+    }
+}
+
+/// Old and new file of a diff, prepared for highlighting the lines that are actually shown.
+/// The sides are parsed independently, so a string or comment opened in deleted lines can't
+/// change the colors of inserted lines.
+pub struct DiffHighlight {
+    /// `None` when the files are over the highlighter's size limit
+    sides: Option<(HighlightStream, HighlightStream)>,
+}
+
+impl DiffHighlight {
+    /// Approximate heap footprint, for cache accounting
+    pub fn size(&self) -> usize {
+        self.sides.as_ref().map_or(0, |(old, new)| old.size() + new.size())
+    }
+}
+
+/// A syntax theme and the editor colors it was designed for
+#[derive(Clone)]
+pub struct SyntaxTheme {
+    pub name: String,
+    pub background: Option<Color>,
+    pub foreground: Option<Color>,
+}
+
 pub struct Highlighter {
     syntax_set: SyntaxSet,
     theme_set: ThemeSet,
+    theme_name: String,
+    max_bytes: usize,
 }
 
 impl Highlighter {
+    /// Built-in themes plus any `.tmTheme` files in the user's theme directory
     pub fn new() -> Self {
+        let mut theme_set = ThemeSet::load_defaults();
+        if let Some(dir) = config::config_dir() {
+            load_user_themes(&mut theme_set, &dir.join("themes"));
+        }
         Self {
             syntax_set: extra_newlines(),
-            theme_set: ThemeSet::load_defaults(),
+            theme_set,
+            theme_name: DEFAULT_THEME.to_string(),
+            max_bytes: MAX_HIGHLIGHT_BYTES,
+        }
+    }
+
+    pub fn theme_name(&self) -> &str {
+        &self.theme_name
+    }
+
+    /// Switch themes; returns false (keeping the current one) if `name` is unknown.
+    /// Spans highlighted before the switch keep their old colors.
+    pub fn set_theme(&mut self, name: &str) -> bool {
+        if !self.theme_set.themes.contains_key(name) {
+            return false;
         }
+        self.theme_name = name.to_string();
+        true
     }
 
-    pub fn highlight_lines(&self, path: &str, lines: &[String]) -> Vec<Vec<(Style, String)>> {
+    /// All available themes, sorted by name
+    pub fn themes(&self) -> Vec<SyntaxTheme> {
+        // BTreeMap, so already in name order
+        self.theme_set
+            .themes
+            .iter()
+            .map(|(name, theme)| SyntaxTheme {
+                name: name.clone(),
+                background: theme.settings.background,
+                foreground: theme.settings.foreground,
+            })
+            .collect()
+    }
+
+    /// Leave files bigger than `bytes`, old and new side together, unhighlighted
+    pub fn set_max_bytes(&mut self, bytes: usize) {
+        self.max_bytes = bytes;
+    }
+
+    fn find_syntax(&self, path: &str, first_line: &str) -> &SyntaxReference {
         let extension = Path::new(path)
             .extension()
             .and_then(|e| e.to_str())
             .unwrap_or("");
 
-        let syntax = self
-            .syntax_set
+        self.syntax_set
             .find_syntax_by_extension(extension)
-            .or_else(|| {
-                self.syntax_set
-                    .find_syntax_by_first_line(lines.first().map(|s| s.as_str()).unwrap_or(""))
-            })
-            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
+            .or_else(|| self.syntax_set.find_syntax_by_first_line(first_line))
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text())
+    }
+
+    fn theme(&self) -> &Theme {
+        &self.theme_set.themes[&self.theme_name]
+    }
+
+    /// Prepare the numbered lines of the old and new file for lazy highlighting; nothing is parsed yet
+    pub fn prepare(&self, path: &str, old: Vec<(u32, String)>, new: Vec<(u32, String)>) -> DiffHighlight {
+        let size: usize = old.iter().chain(new.iter()).map(|(_, line)| line.len()).sum();
+        if size > self.max_bytes {
+            return DiffHighlight { sides: None };
+        }
+
+        // Both sides use the same syntax, even if only one has a telling first line
+        let first_line = new.first().or(old.first()).map(|(_, line)| line.as_str()).unwrap_or("");
+        let syntax = self.find_syntax(path, first_line);
+        let stream = |lines: Vec<(u32, String)>| {
+            let (numbers, lines) = lines.into_iter().map(|(num, line)| (num, line + "\n")).unzip();
+            HighlightStream {
+                lines,
+                numbers,
+                checkpoints: vec![(ParseState::new(syntax), ScopeStack::new())],
+            }
+        };
+
+        DiffHighlight { sides: Some((stream(old), stream(new))) }
+    }
 
-        let theme = &self.theme_set.themes["base16-eighties.dark"];
-        let mut highlighter = HighlightLines::new(syntax, theme);
+    /// Spans for the diff lines `lines`: deleted lines from the old file, the rest from the new one
+    pub fn highlight_diff(&self, diff: &mut DiffHighlight, lines: &[LineRef]) -> Highlights {
+        let Some((ref mut old, ref mut new)) = diff.sides else {
+            return Highlights::TooLarge;
+        };
 
-        lines
+        let index = |stream: &HighlightStream, num: Option<u32>| {
+            num.and_then(|num| stream.numbers.binary_search(&num).ok()).unwrap_or(usize::MAX)
+        };
+        let old_indices: Vec<usize> = lines
             .iter()
-            .map(|line| {
-                highlighter
-                    .highlight_line(line, &self.syntax_set)
-                    .map(|ranges| {
-                        ranges
-                            .into_iter()
-                            .map(|(style, text)| (style, text.to_string()))
-                            .collect()
-                    })
-                    .unwrap_or_else(|_| vec![(Style::default(), line.clone())])
-            })
-            .collect()
+            .map(|&(old_num, new_num)| if new_num.is_none() { index(old, old_num) } else { usize::MAX })
+            .collect();
+        let new_indices: Vec<usize> = lines.iter().map(|&(_, new_num)| index(new, new_num)).collect();
+
+        let old_spans = self.highlight_indices(old, &old_indices);
+        let new_spans = self.highlight_indices(new, &new_indices);
+        Highlights::Lines(old_spans.into_iter().zip(new_spans).map(|(old, new)| new.or(old)).collect())
+    }
+
+    /// Spans for the stream lines at `indices`, resuming from the nearest checkpoint
+    fn highlight_indices(&self, stream: &mut HighlightStream, indices: &[usize]) -> Vec<Option<Vec<(Style, String)>>> {
+        let mut result = vec![None; indices.len()];
+        let theme_highlighter = syntect::highlighting::Highlighter::new(self.theme());
+
+        let mut order: Vec<usize> = (0..indices.len()).filter(|&i| indices[i] < stream.lines.len()).collect();
+        order.sort_by_key(|&i| indices[i]);
+
+        // Parser state before line `current`
+        let mut state: Option<(ParseState, ScopeStack)> = None;
+        let mut current = 0;
+        for i in order {
+            let target = indices[i];
+            let checkpoint = (target / CHECKPOINT_INTERVAL).min(stream.checkpoints.len() - 1);
+            if state.is_none() || current > target || checkpoint * CHECKPOINT_INTERVAL > current {
+                state = Some(stream.checkpoints[checkpoint].clone());
+                current = checkpoint * CHECKPOINT_INTERVAL;
+            }
+            let (parse_state, stack) = state.as_mut().expect("state was just set");
+
+            while current <= target {
+                let line = &stream.lines[current];
+                let ops = parse_state.parse_line(line, &self.syntax_set).unwrap_or_default();
+                if current == target {
+                    let mut highlight_state = HighlightState::new(&theme_highlighter, stack.clone());
+                    let spans = HighlightIterator::new(&mut highlight_state, &ops, line, &theme_highlighter)
+                        .map(|(style, text)| (style, text.trim_end_matches('\n').to_string()))
+                        .filter(|(_, text)| !text.is_empty())
+                        .collect();
+                    result[i] = Some(spans);
+                    *stack = highlight_state.path;
+                } else {
+                    for (_, op) in &ops {
+                        let _ = stack.apply(op);
+                    }
+                }
+                current += 1;
+
+                if current % CHECKPOINT_INTERVAL == 0 && current / CHECKPOINT_INTERVAL == stream.checkpoints.len() {
+                    stream.checkpoints.push((parse_state.clone(), stack.clone()));
+                }
+            }
+        }
+        result
     }
 }
 
@@ -57,3 +223,45 @@ impl Default for Highlighter {
         Self::new()
     }
 }
+
+/// Add every readable `.tmTheme` in `dir`, named after its file stem; broken files are skipped
+fn load_user_themes(theme_set: &mut ThemeSet, dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.extension().and_then(|e| e.to_str()) != Some("tmTheme") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        if let Ok(theme) = ThemeSet::get_theme(&path) {
+            theme_set.themes.insert(name.to_string(), theme);
+        }
+    }
+}
+
+/// Store spans returned for `lines` on the matching hunk lines
+pub fn apply_highlights(hunks: &mut [DiffHunk], lines: &[LineRef], spans: Vec<Option<Vec<(Style, String)>>>) {
+    let mut by_line: HashMap<LineRef, Vec<(Style, String)>> = lines
+        .iter()
+        .zip(spans)
+        .filter_map(|(line, spans)| Some((*line, spans?)))
+        .collect();
+    for line in hunks.iter_mut().flat_map(|h| h.lines.iter_mut()) {
+        if let Some(spans) = by_line.remove(&(line.old_num, line.new_num)) {
+            line.highlighted = Some(spans);
+        }
+    }
+}
+
+/// Lines of `hunks` that have no highlighting yet
+pub fn unhighlighted_lines(hunks: &[DiffHunk]) -> Vec<LineRef> {
+    hunks
+        .iter()
+        .flat_map(|h| h.lines.iter())
+        .filter(|line| line.highlighted.is_none())
+        .map(|line| (line.old_num, line.new_num))
+        .collect()
+}
@@ -0,0 +1,583 @@
+use clap::ValueEnum;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use crate::config::KeyList;
+
+/// Everything a key can be bound to
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    Quit,
+    Help,
+    Branches,
+    PrevCommit,
+    NextCommit,
+    /// Previous file, or previous branch in the branch list
+    SelectPrev,
+    SelectNext,
+    ScrollUp,
+    ScrollDown,
+    ScrollLeft,
+    ScrollRight,
+    HalfPageUp,
+    HalfPageDown,
+    PageUp,
+    PageDown,
+    /// With a count, go to the line with that number instead
+    GoToTop,
+    GoToBottom,
+    PrevHunk,
+    NextHunk,
+    JumpToMove,
+    ExportPatch,
+    ExportHtml,
+    /// Stage the changed lines selected with the mouse
+    StageLines,
+    /// Copy to the clipboard: the file's path, the commit's SHA, the hunk as a patch, the
+    /// selected lines without the gutter
+    CopyPath,
+    CopySha,
+    CopyHunk,
+    CopyLines,
+    /// Edit the file in $VISUAL or $EDITOR at the line in view
+    OpenInEditor,
+    CycleAlgorithm,
+    CycleWhitespace,
+    CycleSyntaxTheme,
+    /// Wrap long lines or cut them off
+    ToggleWrap,
+    /// Show tabs, trailing spaces, line endings and whitespace errors
+    ToggleWhitespace,
+    ToggleMouse,
+    /// Show or hide the commit and file lists on a narrow terminal
+    ToggleDrawer,
+    /// Diff on the whole screen
+    Zoom,
+    /// Lists above the diff instead of beside it
+    ToggleStacked,
+    ShrinkLists,
+    GrowLists,
+    ShrinkCommits,
+    GrowCommits,
+    /// Pick the selected branch
+    Confirm,
+    /// Leave the branch list, or drop the mouse selection
+    Cancel,
+}
+
+/// Action names as written in `[keys]`, with the description shown in the help overlay
+const ACTIONS: &[(Action, &str, &str)] = &[
+    (Action::Quit, "quit", "Quit"),
+    (Action::Help, "help", "Show this help"),
+    (Action::Branches, "branches", "Select branch"),
+    (Action::PrevCommit, "prev-commit", "Previous commit"),
+    (Action::NextCommit, "next-commit", "Next commit"),
+    (Action::SelectPrev, "select-prev", "Previous file"),
+    (Action::SelectNext, "select-next", "Next file"),
+    (Action::ScrollUp, "scroll-up", "Scroll diff up"),
+    (Action::ScrollDown, "scroll-down", "Scroll diff down"),
+    (Action::ScrollLeft, "scroll-left", "Scroll diff left"),
+    (Action::ScrollRight, "scroll-right", "Scroll diff right"),
+    (Action::HalfPageUp, "half-page-up", "Scroll diff half a page up"),
+    (Action::HalfPageDown, "half-page-down", "Scroll diff half a page down"),
+    (Action::PageUp, "page-up", "Scroll diff a page up"),
+    (Action::PageDown, "page-down", "Scroll diff a page down"),
+    (Action::GoToTop, "go-to-top", "Top of the diff (with a count: that line number)"),
+    (Action::GoToBottom, "go-to-bottom", "End of the diff (with a count: that line number)"),
+    (Action::PrevHunk, "prev-hunk", "Previous hunk"),
+    (Action::NextHunk, "next-hunk", "Next hunk"),
+    (Action::JumpToMove, "jump-to-move", "Jump between the sides of a moved block"),
+    (Action::ExportPatch, "export-patch", "Export commit as a .patch file"),
+    (Action::ExportHtml, "export-html", "Export commit as an HTML report"),
+    (Action::StageLines, "stage-lines", "Stage the lines selected with the mouse"),
+    (Action::CopyPath, "copy-path", "Copy the file's path"),
+    (Action::CopySha, "copy-sha", "Copy the commit's SHA"),
+    (Action::CopyHunk, "copy-hunk", "Copy the hunk as a patch"),
+    (Action::CopyLines, "copy-lines", "Copy the selected lines"),
+    (Action::OpenInEditor, "open-in-editor", "Edit the file at the selected line or the top one in view"),
+    (Action::CycleAlgorithm, "cycle-algorithm", "Cycle diff algorithm"),
+    (Action::CycleWhitespace, "cycle-whitespace", "Cycle whitespace mode"),
+    (Action::CycleSyntaxTheme, "cycle-syntax-theme", "Cycle syntax theme"),
+    (Action::ToggleWrap, "toggle-wrap", "Wrap long lines or cut them off"),
+    (Action::ToggleWhitespace, "toggle-whitespace", "Show tabs, trailing spaces and line endings"),
+    (Action::ToggleMouse, "toggle-mouse", "Toggle mouse/select mode"),
+    (Action::ToggleDrawer, "toggle-drawer", "Show or hide the lists on a narrow terminal"),
+    (Action::Zoom, "zoom", "Show the diff on the whole screen"),
+    (Action::ToggleStacked, "toggle-stacked", "Lists beside or above the diff"),
+    (Action::ShrinkLists, "shrink-lists", "Make the lists smaller"),
+    (Action::GrowLists, "grow-lists", "Make the lists larger"),
+    (Action::ShrinkCommits, "shrink-commits", "Move the commit/file boundary up"),
+    (Action::GrowCommits, "grow-commits", "Move the commit/file boundary down"),
+    (Action::Confirm, "confirm", "Pick the selected branch"),
+    (Action::Cancel, "cancel", "Leave the branch list or clear the selection"),
+];
+
+/// Largest count a key can be prefixed with
+const MAX_COUNT: usize = 9999;
+
+/// Starting point for the key bindings, before `[keys]` is applied
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeymapPreset {
+    #[default]
+    Default,
+    /// `hjkl`, `gg`/`G`, `Ctrl-d`/`Ctrl-u`, `{`/`}` and `[c`/`]c` for hunks
+    Vim,
+    /// `C-n`/`C-p`, `C-v`/`M-v`, `M-<`/`M->`, `C-x C-c`
+    Emacs,
+}
+
+impl KeymapPreset {
+    /// Keys per action; keys in a sequence are separated by spaces (`g g`, `ctrl-x ctrl-c`)
+    fn bindings(self) -> &'static [(Action, &'static [&'static str])] {
+        match self {
+            KeymapPreset::Default => &[
+                (Action::Quit, &["q", "ctrl-c"]),
+                (Action::Help, &["?"]),
+                (Action::Branches, &["b"]),
+                (Action::PrevCommit, &["left"]),
+                (Action::NextCommit, &["right"]),
+                (Action::SelectPrev, &["up"]),
+                (Action::SelectNext, &["down"]),
+                (Action::ScrollUp, &["k"]),
+                (Action::ScrollDown, &["j"]),
+                (Action::ScrollLeft, &["h", "shift-left"]),
+                (Action::ScrollRight, &["l", "shift-right"]),
+                (Action::HalfPageUp, &["ctrl-u"]),
+                (Action::HalfPageDown, &["ctrl-d"]),
+                (Action::PageUp, &["pageup"]),
+                (Action::PageDown, &["pagedown"]),
+                (Action::GoToTop, &["home"]),
+                (Action::GoToBottom, &["end"]),
+                (Action::PrevHunk, &["{"]),
+                (Action::NextHunk, &["}"]),
+                (Action::JumpToMove, &["M"]),
+                (Action::ExportPatch, &["e"]),
+                (Action::ExportHtml, &["E"]),
+                (Action::StageLines, &["S"]),
+                (Action::CopyPath, &["y p"]),
+                (Action::CopySha, &["y c"]),
+                (Action::CopyHunk, &["y h"]),
+                (Action::CopyLines, &["y y"]),
+                (Action::OpenInEditor, &["o"]),
+                (Action::CycleAlgorithm, &["a"]),
+                (Action::CycleWhitespace, &["w"]),
+                (Action::CycleSyntaxTheme, &["t"]),
+                (Action::ToggleWrap, &["W"]),
+                (Action::ToggleWhitespace, &["s"]),
+                (Action::ToggleMouse, &["m"]),
+                (Action::ToggleDrawer, &["tab"]),
+                (Action::Zoom, &["z"]),
+                (Action::ToggleStacked, &["L"]),
+                (Action::ShrinkLists, &["<"]),
+                (Action::GrowLists, &[">"]),
+                (Action::ShrinkCommits, &["-"]),
+                (Action::GrowCommits, &["+", "="]),
+                (Action::Confirm, &["enter"]),
+                (Action::Cancel, &["esc"]),
+            ],
+            KeymapPreset::Vim => &[
+                (Action::Quit, &["q", "ctrl-c"]),
+                (Action::Help, &["?"]),
+                (Action::Branches, &["b"]),
+                (Action::PrevCommit, &["h", "left"]),
+                (Action::NextCommit, &["l", "right"]),
+                (Action::SelectPrev, &["K", "up"]),
+                (Action::SelectNext, &["J", "down"]),
+                (Action::ScrollUp, &["k", "ctrl-y"]),
+                (Action::ScrollDown, &["j", "ctrl-e"]),
+                (Action::ScrollLeft, &["shift-left"]),
+                (Action::ScrollRight, &["shift-right"]),
+                (Action::HalfPageUp, &["ctrl-u"]),
+                (Action::HalfPageDown, &["ctrl-d"]),
+                (Action::PageUp, &["ctrl-b", "pageup"]),
+                (Action::PageDown, &["ctrl-f", "pagedown"]),
+                (Action::GoToTop, &["g g", "home"]),
+                (Action::GoToBottom, &["G", "end"]),
+                (Action::PrevHunk, &["{", "[ c"]),
+                (Action::NextHunk, &["}", "] c"]),
+                (Action::JumpToMove, &["M"]),
+                (Action::ExportPatch, &["e"]),
+                (Action::ExportHtml, &["E"]),
+                (Action::StageLines, &["S"]),
+                (Action::CopyPath, &["y p"]),
+                (Action::CopySha, &["y c"]),
+                (Action::CopyHunk, &["y h"]),
+                (Action::CopyLines, &["y y"]),
+                (Action::OpenInEditor, &["o"]),
+                (Action::CycleAlgorithm, &["a"]),
+                (Action::CycleWhitespace, &["w"]),
+                (Action::CycleSyntaxTheme, &["t"]),
+                (Action::ToggleWrap, &["W"]),
+                (Action::ToggleWhitespace, &["s"]),
+                (Action::ToggleMouse, &["m"]),
+                (Action::ToggleDrawer, &["tab"]),
+                (Action::Zoom, &["z"]),
+                (Action::ToggleStacked, &["L"]),
+                (Action::ShrinkLists, &["<"]),
+                (Action::GrowLists, &[">"]),
+                (Action::ShrinkCommits, &["-"]),
+                (Action::GrowCommits, &["+", "="]),
+                (Action::Confirm, &["enter"]),
+                (Action::Cancel, &["esc"]),
+            ],
+            KeymapPreset::Emacs => &[
+                (Action::Quit, &["ctrl-x ctrl-c", "q"]),
+                (Action::Help, &["?"]),
+                (Action::Branches, &["ctrl-x b", "b"]),
+                (Action::PrevCommit, &["ctrl-b", "left"]),
+                (Action::NextCommit, &["ctrl-f", "right"]),
+                (Action::SelectPrev, &["alt-p", "up"]),
+                (Action::SelectNext, &["alt-n", "down"]),
+                (Action::ScrollUp, &["ctrl-p"]),
+                (Action::ScrollDown, &["ctrl-n"]),
+                (Action::ScrollLeft, &["ctrl-x >", "shift-left"]),
+                (Action::ScrollRight, &["ctrl-x <", "shift-right"]),
+                (Action::HalfPageUp, &[]),
+                (Action::HalfPageDown, &[]),
+                (Action::PageUp, &["alt-v", "pageup"]),
+                (Action::PageDown, &["ctrl-v", "pagedown"]),
+                (Action::GoToTop, &["alt-<", "home"]),
+                (Action::GoToBottom, &["alt->", "end"]),
+                (Action::PrevHunk, &["alt-{"]),
+                (Action::NextHunk, &["alt-}"]),
+                (Action::JumpToMove, &["M"]),
+                (Action::ExportPatch, &["e"]),
+                (Action::ExportHtml, &["E"]),
+                (Action::StageLines, &["S"]),
+                (Action::CopyPath, &["y p"]),
+                (Action::CopySha, &["y c"]),
+                (Action::CopyHunk, &["y h"]),
+                (Action::CopyLines, &["alt-w", "y y"]),
+                (Action::OpenInEditor, &["ctrl-x ctrl-f", "o"]),
+                (Action::CycleAlgorithm, &["a"]),
+                (Action::CycleWhitespace, &["w"]),
+                (Action::CycleSyntaxTheme, &["t"]),
+                (Action::ToggleWrap, &["W"]),
+                (Action::ToggleWhitespace, &["s"]),
+                (Action::ToggleMouse, &["m"]),
+                (Action::ToggleDrawer, &["tab"]),
+                (Action::Zoom, &["z"]),
+                (Action::ToggleStacked, &["L"]),
+                (Action::ShrinkLists, &["<"]),
+                (Action::GrowLists, &[">"]),
+                (Action::ShrinkCommits, &["-"]),
+                (Action::GrowCommits, &["+", "="]),
+                (Action::Confirm, &["enter"]),
+                (Action::Cancel, &["esc", "ctrl-g"]),
+            ],
+        }
+    }
+}
+
+/// A key with its modifiers, as written in the config (`j`, `ctrl-d`, `alt-v`, `pagedown`)
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Key {
+    pub fn from_event(event: KeyEvent) -> Self {
+        let modifiers = event.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+        Self::normalized(event.code, modifiers)
+    }
+
+    /// Shift is part of the character for letters and symbols (`E`, `?`), and terminals
+    /// can't tell ctrl-D from ctrl-d
+    fn normalized(code: KeyCode, mut modifiers: KeyModifiers) -> Self {
+        let KeyCode::Char(c) = code else {
+            return Self { code, modifiers };
+        };
+        let c = if modifiers.contains(KeyModifiers::CONTROL) {
+            c.to_ascii_lowercase()
+        } else if modifiers.contains(KeyModifiers::SHIFT) {
+            c.to_ascii_uppercase()
+        } else {
+            c
+        };
+        modifiers.remove(KeyModifiers::SHIFT);
+        Self { code: KeyCode::Char(c), modifiers }
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = text;
+        loop {
+            let lower = rest.to_lowercase();
+            let (modifier, len) = if lower.starts_with("ctrl-") {
+                (KeyModifiers::CONTROL, 5)
+            } else if lower.starts_with("alt-") {
+                (KeyModifiers::ALT, 4)
+            } else if lower.starts_with("shift-") {
+                (KeyModifiers::SHIFT, 6)
+            } else {
+                break;
+            };
+            // `ctrl--` binds ctrl and the minus key
+            if rest.len() == len {
+                break;
+            }
+            modifiers |= modifier;
+            rest = &rest[len..];
+        }
+
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest.to_lowercase().as_str() {
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "pageup" | "pgup" => KeyCode::PageUp,
+                "pagedown" | "pgdn" => KeyCode::PageDown,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "enter" | "return" => KeyCode::Enter,
+                "esc" | "escape" => KeyCode::Esc,
+                "tab" => KeyCode::Tab,
+                "backtab" => KeyCode::BackTab,
+                "backspace" => KeyCode::Backspace,
+                "delete" | "del" => KeyCode::Delete,
+                "insert" | "ins" => KeyCode::Insert,
+                "space" => KeyCode::Char(' '),
+                name => KeyCode::F(name.strip_prefix('f')?.parse().ok()?),
+            },
+        };
+        Some(Self::normalized(code, modifiers))
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "ctrl-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "alt-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "shift-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "f{}", n),
+            KeyCode::PageUp => write!(f, "pageup"),
+            KeyCode::PageDown => write!(f, "pagedown"),
+            code => write!(f, "{}", format!("{:?}", code).to_lowercase()),
+        }
+    }
+}
+
+/// Keys pressed one after another, written with spaces in between (`g g`, `ctrl-x ctrl-c`)
+fn parse_sequence(text: &str) -> Option<Vec<Key>> {
+    let keys: Vec<Key> = text.split_whitespace().map(Key::parse).collect::<Option<_>>()?;
+    (!keys.is_empty()).then_some(keys)
+}
+
+/// A sequence as written in the config
+fn sequence_text(keys: &[Key]) -> String {
+    let names: Vec<String> = keys.iter().map(Key::to_string).collect();
+    names.join(" ")
+}
+
+/// How a sequence reads in the help: `gg` and `]c` like vim, otherwise spaced out
+fn describe_sequence(keys: &[Key]) -> String {
+    let plain = keys.len() > 1
+        && keys
+            .iter()
+            .all(|key| key.modifiers.is_empty() && matches!(key.code, KeyCode::Char(c) if c != ' '));
+    let names: Vec<String> = keys.iter().map(Key::to_string).collect();
+    names.join(if plain { "" } else { " " })
+}
+
+/// Which action each key sequence triggers, and the keys typed so far
+pub struct Keymap {
+    bindings: HashMap<Vec<Key>, Action>,
+    /// Unfinished beginnings of the bound sequences
+    prefixes: HashSet<Vec<Key>>,
+    pending: Vec<Key>,
+    count: Option<usize>,
+}
+
+impl Keymap {
+    /// The preset's bindings, with the actions listed in `[keys]` rebound to the given keys.
+    /// An empty list unbinds an action; a configured key wins over a preset one.
+    pub fn new(preset: KeymapPreset, overrides: &BTreeMap<String, KeyList>) -> Result<Self, String> {
+        let mut bindings = HashMap::new();
+        for (action, keys) in preset.bindings() {
+            if !overrides.contains_key(action_name(*action)) {
+                for text in *keys {
+                    bindings.insert(parse_sequence(text).expect("preset keys are valid"), *action);
+                }
+            }
+        }
+
+        for (name, list) in overrides {
+            let Some((action, _, _)) = ACTIONS.iter().find(|(_, action_name, _)| action_name == name) else {
+                return Err(format!("unknown action {}", name));
+            };
+            for text in list.keys() {
+                let keys = parse_sequence(text).ok_or_else(|| format!("invalid key '{}' for {}", text, name))?;
+                bindings.insert(keys, *action);
+            }
+        }
+
+        // A key that is also the start of a sequence would fire before the sequence could be typed
+        for (keys, action) in &bindings {
+            if let Some((len, other)) = (1..keys.len()).find_map(|len| Some((len, bindings.get(&keys[..len])?))) {
+                return Err(format!(
+                    "key '{}' for {} is the start of '{}' for {}",
+                    sequence_text(&keys[..len]),
+                    action_name(*other),
+                    sequence_text(keys),
+                    action_name(*action)
+                ));
+            }
+        }
+
+        let prefixes = bindings
+            .keys()
+            .flat_map(|keys| (1..keys.len()).map(|len| keys[..len].to_vec()))
+            .collect();
+        Ok(Self { bindings, prefixes, pending: Vec::new(), count: None })
+    }
+
+    /// Feed a key press; returns the action once a bound sequence is complete, with the
+    /// count typed before it (`5j`). Digits count unless they are bound themselves.
+    pub fn feed(&mut self, event: KeyEvent) -> Option<(Action, Option<usize>)> {
+        let key = Key::from_event(event);
+        if self.pending.is_empty() && key.modifiers.is_empty() && !self.bindings.contains_key(&vec![key]) {
+            if let KeyCode::Char(c @ '0'..='9') = key.code {
+                // A leading 0 isn't a count
+                if c != '0' || self.count.is_some() {
+                    let digit = c as usize - '0' as usize;
+                    self.count = Some((self.count.unwrap_or(0) * 10 + digit).min(MAX_COUNT));
+                    return None;
+                }
+            }
+        }
+
+        self.pending.push(key);
+        if let Some(action) = self.bindings.get(&self.pending).copied() {
+            self.pending.clear();
+            return Some((action, self.count.take()));
+        }
+        if self.prefixes.contains(&self.pending) {
+            return None;
+        }
+
+        // Not part of any sequence: drop what was typed and try the key on its own
+        let retry = self.pending.len() > 1;
+        self.pending.clear();
+        self.count = None;
+        if retry {
+            self.feed(event)
+        } else {
+            None
+        }
+    }
+
+    /// Count and keys of an unfinished sequence, for the status bar
+    pub fn pending(&self) -> String {
+        let count = self.count.map(|count| count.to_string()).unwrap_or_default();
+        format!("{}{}", count, describe_sequence(&self.pending))
+    }
+
+    /// Bound actions with their keys, in the order of the help overlay
+    pub fn help(&self) -> Vec<(String, &'static str)> {
+        ACTIONS
+            .iter()
+            .filter_map(|(action, _, description)| {
+                let mut keys: Vec<String> = self
+                    .bindings
+                    .iter()
+                    .filter(|(_, bound)| *bound == action)
+                    .map(|(keys, _)| describe_sequence(keys))
+                    .collect();
+                if keys.is_empty() {
+                    return None;
+                }
+                // Single keys first, then alphabetically so the order doesn't change between runs
+                keys.sort_by(|a, b| (a.chars().count() > 1, a).cmp(&(b.chars().count() > 1, b)));
+                Some((keys.join(", "), *description))
+            })
+            .collect()
+    }
+}
+
+fn action_name(action: Action) -> &'static str {
+    ACTIONS
+        .iter()
+        .find(|(bound, _, _)| *bound == action)
+        .map(|(_, name, _)| *name)
+        .expect("every action has a name")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(keymap: &mut Keymap, keys: &str) -> Vec<(Action, Option<usize>)> {
+        keys.chars()
+            .filter_map(|c| keymap.feed(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)))
+            .collect()
+    }
+
+    fn overrides(bindings: &[(&str, &str)]) -> BTreeMap<String, KeyList> {
+        bindings
+            .iter()
+            .map(|(action, key)| (action.to_string(), KeyList::One(key.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn counts_prefix_actions() {
+        let mut keymap = Keymap::new(KeymapPreset::Vim, &BTreeMap::new()).unwrap();
+        assert_eq!(press(&mut keymap, "5j"), [(Action::ScrollDown, Some(5))]);
+        assert_eq!(press(&mut keymap, "j"), [(Action::ScrollDown, None)]);
+        assert_eq!(press(&mut keymap, "120G"), [(Action::GoToBottom, Some(120))]);
+        assert_eq!(press(&mut keymap, "99999j"), [(Action::ScrollDown, Some(MAX_COUNT))]);
+    }
+
+    #[test]
+    fn sequences_complete_over_several_keys() {
+        let mut keymap = Keymap::new(KeymapPreset::Vim, &BTreeMap::new()).unwrap();
+        assert!(press(&mut keymap, "g").is_empty());
+        assert_eq!(keymap.pending(), "g");
+        assert_eq!(press(&mut keymap, "g"), [(Action::GoToTop, None)]);
+        assert_eq!(press(&mut keymap, "3]c"), [(Action::NextHunk, Some(3))]);
+        assert_eq!(keymap.pending(), "");
+    }
+
+    #[test]
+    fn unknown_key_resets_the_sequence() {
+        let mut keymap = Keymap::new(KeymapPreset::Vim, &BTreeMap::new()).unwrap();
+        // The count and the unfinished `g` are dropped, then `x` alone isn't bound either
+        assert!(press(&mut keymap, "4gx").is_empty());
+        assert_eq!(keymap.pending(), "");
+        assert_eq!(press(&mut keymap, "j"), [(Action::ScrollDown, None)]);
+        // A key that breaks a sequence still counts on its own
+        assert_eq!(press(&mut keymap, "yq"), [(Action::Quit, None)]);
+    }
+
+    #[test]
+    fn presets_have_no_prefix_conflicts() {
+        for preset in [KeymapPreset::Default, KeymapPreset::Vim, KeymapPreset::Emacs] {
+            assert!(Keymap::new(preset, &BTreeMap::new()).is_ok());
+        }
+    }
+
+    #[test]
+    fn bindings_shadowing_a_sequence_are_rejected() {
+        let error = Keymap::new(KeymapPreset::Vim, &overrides(&[("copy-path", "g")])).err().unwrap();
+        assert_eq!(error, "key 'g' for copy-path is the start of 'g g' for go-to-top");
+        assert!(Keymap::new(KeymapPreset::Default, &overrides(&[("next-hunk", "[")])).is_ok());
+        assert!(Keymap::new(KeymapPreset::Vim, &overrides(&[("copy-path", "[")])).is_err());
+        assert!(Keymap::new(KeymapPreset::Vim, &overrides(&[("go-to-top", "home"), ("copy-path", "g")])).is_ok());
+    }
+}
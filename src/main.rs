@@ -1,9 +1,22 @@
 mod app;
+mod cache;
+mod clipboard;
+mod config;
+mod encoding;
+mod export;
 mod git;
 mod highlighter;
+mod keymap;
+mod moves;
+mod patch;
+mod printer;
+mod source;
+mod text;
 mod theme;
 mod types;
 mod ui;
+mod watcher;
+mod worker;
 
 use clap::Parser;
 use crossterm::{
@@ -11,10 +24,22 @@ use crossterm::{
     execute,
     terminal::{self, LeaveAlternateScreen},
 };
-use std::io;
-use std::path::PathBuf;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 
-use app::App;
+use app::{App, AppOptions};
+use config::{Config, Excludes};
+use git::GitDiff;
+use highlighter::Highlighter;
+use keymap::{Keymap, KeymapPreset};
+use patch::{Patch, PatchDiff};
+use printer::ColorMode;
+use source::DiffSource;
+use theme::{ColorDepth, Theme, ThemeName};
+use watcher::RepoWatcher;
+use types::{DiffAlgorithm, DiffSettings, WhitespaceMode};
+use ui::LineFormat;
 
 #[derive(Parser)]
 #[command(name = "gitti")]
@@ -33,13 +58,190 @@ struct Cli {
     file: Option<PathBuf>,
 
     /// Context lines around changes (default 5)
-    #[arg(long, short = 'C', default_value = "5")]
-    context: usize,
+    #[arg(long, short = 'C')]
+    context: Option<usize>,
+
+    /// Line diff algorithm (default myers)
+    #[arg(long, value_enum)]
+    diff_algorithm: Option<DiffAlgorithm>,
+
+    /// Whitespace handling when comparing lines (default none)
+    #[arg(long, value_enum)]
+    whitespace: Option<WhitespaceMode>,
+
+    /// Memory for recently viewed diffs, in megabytes (default 64)
+    #[arg(long, value_name = "MB")]
+    diff_cache_size: Option<usize>,
+
+    /// Files bigger than this, in megabytes, are shown without syntax highlighting (default 4)
+    #[arg(long, value_name = "MB")]
+    max_highlight_size: Option<usize>,
+
+    /// Columns between tab stops (default 4)
+    #[arg(long, value_name = "COLUMNS")]
+    tab_width: Option<usize>,
+
+    /// Show tabs, trailing spaces and line endings, and mark whitespace errors in added lines (toggle with s)
+    #[arg(long)]
+    show_whitespace: bool,
+
+    /// Wrap long diff lines instead of cutting them off (toggle with W)
+    #[arg(long)]
+    wrap: bool,
+
+    /// UI color theme (default: from config.toml, otherwise dark)
+    #[arg(long, value_enum)]
+    theme: Option<ThemeName>,
+
+    /// Syntax highlighting theme (built-in or a .tmTheme in ~/.config/gitti/themes)
+    #[arg(long, value_name = "NAME")]
+    syntax_theme: Option<String>,
+
+    /// List the available syntax themes and exit
+    #[arg(long)]
+    list_syntax_themes: bool,
+
+    /// Use the syntax theme's background and text color for the diff panel
+    #[arg(long)]
+    match_syntax_theme: bool,
+
+    /// Key bindings (default: from config.toml, otherwise default)
+    #[arg(long, value_enum)]
+    keymap: Option<KeymapPreset>,
+
+    /// Print the diff to stdout instead of opening the viewer (default when stdout is not a terminal)
+    #[arg(long)]
+    print: bool,
+
+    /// Colorize printed output
+    #[arg(long, value_enum, default_value_t = ColorMode::Auto)]
+    color: ColorMode,
+
+    /// Colors the terminal can show (default: detected from COLORTERM and TERM, or GITTI_COLOR_DEPTH)
+    #[arg(long, value_enum, value_name = "DEPTH")]
+    color_depth: Option<ColorDepth>,
+
+    /// Write the changes as a `git format-patch` style patch ("-" for stdout) and exit
+    #[arg(long, value_name = "FILE")]
+    export_patch: Option<PathBuf>,
+
+    /// Write the changes as a self-contained HTML report ("-" for stdout) and exit
+    #[arg(long, value_name = "FILE")]
+    export_html: Option<PathBuf>,
 }
 
 fn main() {
     let cli = Cli::parse();
 
+    if cli.list_syntax_themes {
+        for theme in Highlighter::new().themes() {
+            println!("{}", theme.name);
+        }
+        return;
+    }
+
+    // Command line options win over the config file, which wins over the defaults
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let color_depth = cli.color_depth.or_else(ColorDepth::from_env).or(config.color_depth);
+    theme::set_color_depth(color_depth.unwrap_or_else(ColorDepth::detect));
+
+    let mut theme = Theme::named(cli.theme.or(config.theme).unwrap_or_default());
+    if let Err(e) = theme.override_colors(&config.colors) {
+        eprintln!("Error: config: {}", e);
+        std::process::exit(1);
+    }
+    let keymap = match Keymap::new(cli.keymap.or(config.keymap).unwrap_or_default(), &config.keys) {
+        Ok(keymap) => keymap,
+        Err(e) => {
+            eprintln!("Error: config: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let settings = DiffSettings {
+        algorithm: cli.diff_algorithm.or(config.diff_algorithm).unwrap_or_default(),
+        whitespace: cli.whitespace.or(config.whitespace).unwrap_or_default(),
+    };
+
+    let stdout_is_terminal = io::stdout().is_terminal();
+
+    // Piped input (`git diff | gitti`, core.pager): view the patch instead of a repository.
+    // Keyboard input then comes from /dev/tty, which crossterm opens when stdin is not a terminal.
+    let mut input = Vec::new();
+    if stdin_has_input() {
+        if let Err(e) = io::stdin().read_to_end(&mut input) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let patch = if !input.iter().all(u8::is_ascii_whitespace) {
+        let patch = Patch::parse(&String::from_utf8_lossy(&input));
+        if !patch.has_files() {
+            // Not a diff (e.g. `git log` through core.pager): page it like git would have
+            pass_through(&input, stdout_is_terminal);
+            return;
+        }
+        Some(patch)
+    } else {
+        None
+    };
+    let live = patch.is_none();
+
+    let cache_budget = cli.diff_cache_size.or(config.diff_cache_size).unwrap_or(64) * 1024 * 1024;
+    let highlight_limit = cli.max_highlight_size.or(config.max_highlight_size).map_or(highlighter::MAX_HIGHLIGHT_BYTES, |mb| mb * 1024 * 1024);
+    let context = cli.context.or(config.context).unwrap_or(5);
+    let line_format = LineFormat {
+        tab_width: cli.tab_width.or(config.tab_width).unwrap_or(4),
+        show_whitespace: cli.show_whitespace || config.show_whitespace.unwrap_or(false),
+    };
+    let (staged, commit) = (cli.staged, cli.commit.clone());
+    // Light UI themes come with a light syntax theme unless one is chosen
+    let syntax_theme = cli
+        .syntax_theme
+        .clone()
+        .or(config.syntax_theme)
+        .unwrap_or_else(|| theme.syntax_theme.to_string());
+    let exclude = config.exclude;
+    let open = move || -> Result<Box<dyn DiffSource>, git2::Error> {
+        let mut source: Box<dyn DiffSource> = match patch {
+            Some(patch) => Box::new(PatchDiff::new(patch)),
+            None => Box::new(GitDiff::new(staged, commit, context, settings, cache_budget)?),
+        };
+        source.set_excludes(Excludes::new(&exclude)?);
+        source.set_highlight_limit(highlight_limit);
+        if !source.set_syntax_theme(&syntax_theme) {
+            let message = format!("unknown syntax theme '{}' (see --list-syntax-themes)", syntax_theme);
+            return Err(git2::Error::from_str(&message));
+        }
+        Ok(source)
+    };
+
+    let export = cli.export_patch.is_some() || cli.export_html.is_some();
+    if export || cli.print || !stdout_is_terminal {
+        let source = match open() {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        };
+        if !export {
+            run_print(source, cli.color.enabled(stdout_is_terminal), &theme, line_format);
+        } else if let Err(e) = run_export(source, cli.export_patch.as_deref(), cli.export_html.as_deref(), &theme) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // Set up panic hook to show backtrace
     std::panic::set_hook(Box::new(|panic_info| {
         // Restore terminal first
@@ -68,8 +270,21 @@ fn main() {
         eprintln!("{}", std::backtrace::Backtrace::force_capture());
     }));
 
+    // Watch the repository for changes; fall back to polling if that is not possible
+    let watcher = if live { RepoWatcher::new().ok() } else { None };
+
     // Create and run app
-    let mut app = match App::new(cli.staged, cli.commit, cli.context) {
+    let options = AppOptions {
+        theme,
+        match_syntax_theme: cli.match_syntax_theme || config.match_syntax_theme.unwrap_or(false),
+        keymap,
+        layout: config.layout,
+        line_format,
+        wrap: cli.wrap || config.wrap.unwrap_or(false),
+        refresh_interval: std::time::Duration::from_millis(config.refresh_interval_ms),
+        max_commits: config.max_commits,
+    };
+    let mut app = match App::new(open, watcher, options) {
         Ok(app) => app,
         Err(e) => {
             eprintln!("Error: {}", e);
@@ -89,3 +304,99 @@ fn main() {
         std::process::exit(1);
     }
 }
+
+/// Show input that isn't a diff: in $PAGER (or `less -R`) on a terminal, otherwise written
+/// out untouched
+fn pass_through(input: &[u8], stdout_is_terminal: bool) {
+    if stdout_is_terminal {
+        let pager = std::env::var("PAGER").ok().filter(|value| !value.trim().is_empty());
+        // The variable can carry arguments, like `less -S`; never page through ourselves
+        let mut words: Vec<&str> = pager.as_deref().map_or_else(Vec::new, |pager| pager.split_whitespace().collect());
+        if words.first().is_none_or(|program| Path::new(program).file_stem().is_some_and(|stem| stem == "gitti")) {
+            words = vec!["less", "-R"];
+        }
+        if let Ok(mut child) = Command::new(words[0]).args(&words[1..]).stdin(Stdio::piped()).spawn() {
+            if let Some(mut stdin) = child.stdin.take() {
+                // The pager quitting early closes the pipe, which is fine
+                let _ = stdin.write_all(input);
+            }
+            let _ = child.wait();
+            return;
+        }
+    }
+    let _ = io::stdout().write_all(input);
+}
+
+/// Only pipes and redirected files count as input; an inherited non-terminal stdin
+/// (cron, some CI runners) would otherwise block forever.
+#[cfg(unix)]
+fn stdin_has_input() -> bool {
+    use std::os::fd::AsFd;
+    use std::os::unix::fs::FileTypeExt;
+
+    let stdin = io::stdin();
+    if stdin.is_terminal() {
+        return false;
+    }
+    stdin
+        .as_fd()
+        .try_clone_to_owned()
+        .map(std::fs::File::from)
+        .and_then(|f| f.metadata())
+        .map(|m| m.file_type().is_fifo() || m.file_type().is_file())
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn stdin_has_input() -> bool {
+    !io::stdin().is_terminal()
+}
+
+fn run_print(mut source: Box<dyn DiffSource>, color: bool, theme: &Theme, format: LineFormat) {
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    match printer::print_changes(source.as_mut(), color, theme, format, &mut out) {
+        Ok(()) => {}
+        // Reader went away (e.g. `gitti | head`)
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_export(
+    mut source: Box<dyn DiffSource>,
+    patch_path: Option<&Path>,
+    html_path: Option<&Path>,
+    theme: &Theme,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let commits = source.output_commits()?;
+
+    if let Some(path) = patch_path {
+        let mut patch = String::new();
+        for commit in &commits {
+            patch.push_str(&source.format_patch(commit)?);
+        }
+        write_output(path, &patch)?;
+    }
+
+    if let Some(path) = html_path {
+        let mut sections = Vec::new();
+        for commit in commits {
+            let files = export::collect_files(source.as_mut(), &commit)?;
+            sections.push((commit, files));
+        }
+        write_output(path, &export::to_html(&sections, theme))?;
+    }
+
+    Ok(())
+}
+
+fn write_output(path: &Path, content: &str) -> io::Result<()> {
+    if path == Path::new("-") {
+        io::stdout().write_all(content.as_bytes())
+    } else {
+        std::fs::write(path, content)
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use similar::ChangeTag;
+
+use crate::types::{DiffLine, MoveInfo};
+
+/// Minimum block size to count as a move, like git's `--color-moved`
+const MIN_MOVED_LINES: usize = 3;
+const MIN_MOVED_CHARS: usize = 20;
+/// Lines that appear more often than this among the candidates, like `}` or `end`, can't
+/// start a move; they'd otherwise be compared with each other pairwise
+const MAX_CANDIDATES: usize = 16;
+
+/// Deleted and inserted lines of one file, with their old/new line numbers
+pub struct ChangedLines {
+    pub path: String,
+    pub deleted: Vec<(u32, String)>,
+    pub inserted: Vec<(u32, String)>,
+}
+
+impl ChangedLines {
+    pub fn from_diff_lines(path: &str, lines: &[DiffLine]) -> Self {
+        let mut deleted = Vec::new();
+        let mut inserted = Vec::new();
+        for line in lines {
+            match (line.tag, line.old_num, line.new_num) {
+                (ChangeTag::Delete, Some(n), _) => deleted.push((n, line.content.clone())),
+                (ChangeTag::Insert, _, Some(n)) => inserted.push((n, line.content.clone())),
+                _ => {}
+            }
+        }
+        Self {
+            path: path.to_string(),
+            deleted,
+            inserted,
+        }
+    }
+}
+
+/// A run of `len` consecutive lines starting at `from[from_idx]` that reappears at `to[to_idx]`
+struct Block {
+    from_idx: usize,
+    to_idx: usize,
+    len: usize,
+}
+
+fn find_blocks(from: &[(u32, String)], to: &[(u32, String)]) -> Vec<Block> {
+    let from_hashes: Vec<u64> = from.iter().map(|(_, text)| line_hash(text)).collect();
+    let to_hashes: Vec<u64> = to.iter().map(|(_, text)| line_hash(text)).collect();
+    let mut by_hash: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (idx, (_, text)) in to.iter().enumerate() {
+        if !text.trim().is_empty() {
+            by_hash.entry(to_hashes[idx]).or_default().push(idx);
+        }
+    }
+
+    let mut used = vec![false; to.len()];
+    let mut blocks = Vec::new();
+    let mut i = 0;
+
+    while i < from.len() {
+        let candidates = by_hash.get(&from_hashes[i]).filter(|c| c.len() <= MAX_CANDIDATES);
+        let mut best: Option<Block> = None;
+        for &j in candidates.into_iter().flatten() {
+            let mut len = 0;
+            while i + len < from.len()
+                && j + len < to.len()
+                && !used[j + len]
+                && from[i + len].0 == from[i].0 + len as u32
+                && to[j + len].0 == to[j].0 + len as u32
+                && from_hashes[i + len] == to_hashes[j + len]
+                && from[i + len].1 == to[j + len].1
+            {
+                len += 1;
+            }
+            if len > 0 && best.as_ref().is_none_or(|b| len > b.len) {
+                best = Some(Block { from_idx: i, to_idx: j, len });
+            }
+        }
+
+        match best {
+            Some(block) if is_significant(&from[i..i + block.len]) => {
+                used[block.to_idx..block.to_idx + block.len].fill(true);
+                i += block.len;
+                blocks.push(block);
+            }
+            _ => i += 1,
+        }
+    }
+
+    blocks
+}
+
+fn line_hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn is_significant(lines: &[(u32, String)]) -> bool {
+    let chars: usize = lines
+        .iter()
+        .map(|(_, text)| text.chars().filter(|c| c.is_alphanumeric()).count())
+        .sum();
+    lines.len() >= MIN_MOVED_LINES && chars >= MIN_MOVED_CHARS
+}
+
+/// Mark deleted/inserted lines of `path` that reappear elsewhere in the same file
+/// or in one of `others` (the other files of the same commit).
+pub fn mark_moves(path: &str, lines: &mut [DiffLine], others: &[ChangedLines]) {
+    let own = ChangedLines::from_diff_lines(path, lines);
+    let mut moves: HashMap<(ChangeTag, u32), MoveInfo> = HashMap::new();
+    let mut alternate = false;
+
+    // Within the file: both sides point at each other
+    for block in find_blocks(&own.deleted, &own.inserted) {
+        alternate = !alternate;
+        for k in 0..block.len {
+            let (old_num, _) = own.deleted[block.from_idx + k];
+            let (new_num, _) = own.inserted[block.to_idx + k];
+            moves.insert((ChangeTag::Delete, old_num), MoveInfo { path: path.to_string(), line: new_num, alternate });
+            moves.insert((ChangeTag::Insert, new_num), MoveInfo { path: path.to_string(), line: old_num, alternate });
+        }
+    }
+
+    for other in others.iter().filter(|c| c.path != path) {
+        // Moved out of this file
+        let deleted = remaining(&own.deleted, &moves, ChangeTag::Delete);
+        for block in find_blocks(&deleted, &other.inserted) {
+            alternate = !alternate;
+            for k in 0..block.len {
+                let info = MoveInfo { path: other.path.clone(), line: other.inserted[block.to_idx + k].0, alternate };
+                moves.insert((ChangeTag::Delete, deleted[block.from_idx + k].0), info);
+            }
+        }
+
+        // Moved into this file
+        let inserted = remaining(&own.inserted, &moves, ChangeTag::Insert);
+        for block in find_blocks(&other.deleted, &inserted) {
+            alternate = !alternate;
+            for k in 0..block.len {
+                let info = MoveInfo { path: other.path.clone(), line: other.deleted[block.from_idx + k].0, alternate };
+                moves.insert((ChangeTag::Insert, inserted[block.to_idx + k].0), info);
+            }
+        }
+    }
+
+    for line in lines.iter_mut() {
+        let num = match line.tag {
+            ChangeTag::Delete => line.old_num,
+            ChangeTag::Insert => line.new_num,
+            ChangeTag::Equal => None,
+        };
+        if let Some(num) = num {
+            line.moved = moves.remove(&(line.tag, num));
+        }
+    }
+}
+
+fn remaining(
+    changed: &[(u32, String)],
+    moves: &HashMap<(ChangeTag, u32), MoveInfo>,
+    tag: ChangeTag,
+) -> Vec<(u32, String)> {
+    changed
+        .iter()
+        .filter(|(n, _)| !moves.contains_key(&(tag, *n)))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(lines: &[&str], first: u32) -> Vec<(u32, String)> {
+        lines.iter().zip(first..).map(|(text, n)| (n, text.to_string())).collect()
+    }
+
+    #[test]
+    fn repeated_lines_do_not_start_blocks() {
+        let block = ["}", "fn moved() {", "    let first = 1;", "    let second = 2;"];
+        let mut from = vec!["}"; 1000];
+        from.extend(block);
+        let mut to = vec!["}"; 1000];
+        to.extend(block);
+
+        let blocks = find_blocks(&numbered(&from, 1), &numbered(&to, 1));
+        let found: Vec<(usize, usize, usize)> = blocks.iter().map(|b| (b.from_idx, b.to_idx, b.len)).collect();
+        assert_eq!(found, [(1001, 1001, 3)]);
+    }
+}
@@ -0,0 +1,731 @@
+use similar::ChangeTag;
+
+use crate::config::Excludes;
+use crate::highlighter::{DiffHighlight, Highlighter};
+use crate::moves::{self, ChangedLines};
+use crate::source::DiffSource;
+use crate::types::{BranchInfo, CommitInfo, DiffHunk, DiffLine, DiffSettings, FileChange, Highlights, LineRef};
+
+/// Commits and files parsed from unified diff text
+pub struct Patch {
+    commits: Vec<PatchCommit>,
+}
+
+/// Diff read from unified diff text (`git diff`, `git log -p`, `diff -u`) instead of a repository
+pub struct PatchDiff {
+    commits: Vec<PatchCommit>,
+    settings: DiffSettings,
+    highlighter: Highlighter,
+    /// Commit, path and highlight state of the file highlighted last
+    active: Option<(Option<String>, String, DiffHighlight)>,
+    excludes: Excludes,
+}
+
+struct PatchCommit {
+    info: CommitInfo,
+    files: Vec<PatchFile>,
+}
+
+struct PatchFile {
+    change: FileChange,
+    hunks: Vec<DiffHunk>,
+}
+
+/// Remaining line counts and current line numbers of the hunk being read
+struct HunkState {
+    old_remaining: u32,
+    new_remaining: u32,
+    old_line: u32,
+    new_line: u32,
+}
+
+impl Patch {
+    pub fn parse(text: &str) -> Self {
+        let mut commits: Vec<PatchCommit> = Vec::new();
+        let mut file: Option<PatchFile> = None;
+        let mut hunk: Option<HunkState> = None;
+        let mut in_commit_header = false;
+
+        for raw in text.lines() {
+            let stripped = strip_ansi(raw);
+            let line = stripped.as_str();
+
+            if line.starts_with('\\') {
+                // "\ No newline at end of file", possibly in the middle of a hunk
+                if let Some(last) = file.as_mut().and_then(|f| f.hunks.last_mut()).and_then(|h| h.lines.last_mut()) {
+                    last.no_newline = true;
+                }
+                continue;
+            }
+
+            if let Some(state) = hunk.as_mut() {
+                if let Some(diff_line) = state.read_line(line) {
+                    if let Some(current) = file.as_mut().and_then(|f| f.hunks.last_mut()) {
+                        current.lines.push(diff_line);
+                    }
+                    if state.old_remaining == 0 && state.new_remaining == 0 {
+                        hunk = None;
+                    }
+                    continue;
+                }
+                hunk = None;
+            }
+
+            if let Some(sha) = line.strip_prefix("commit ").and_then(parse_commit_sha) {
+                Self::finish_file(&mut commits, &mut file);
+                let short_sha = sha[..7.min(sha.len())].to_string();
+                commits.push(PatchCommit {
+                    info: CommitInfo {
+                        sha,
+                        short_sha,
+                        message: String::new(),
+                        author: String::new(),
+                        is_local_changes: false,
+                    },
+                    files: Vec::new(),
+                });
+                in_commit_header = true;
+                continue;
+            }
+
+            if in_commit_header {
+                if let Some(commit) = commits.last_mut() {
+                    if let Some(author) = line.strip_prefix("Author:") {
+                        commit.info.author = author.trim().to_string();
+                    } else if let Some(message) = line.strip_prefix("    ") {
+                        if commit.info.message.is_empty() {
+                            commit.info.message = message.trim().to_string();
+                        }
+                    }
+                }
+            }
+
+            if let Some(rest) = line.strip_prefix("diff --git ") {
+                Self::finish_file(&mut commits, &mut file);
+                in_commit_header = false;
+                file = Some(PatchFile::new(parse_git_header_path(rest)));
+            } else if line.starts_with("new file mode") {
+                if let Some(f) = file.as_mut() {
+                    f.change.status = "added".to_string();
+                }
+            } else if line.starts_with("deleted file mode") {
+                if let Some(f) = file.as_mut() {
+                    f.change.status = "deleted".to_string();
+                }
+            } else if let Some(path) = line.strip_prefix("rename to ") {
+                if let Some(f) = file.as_mut() {
+                    f.change.path = unquote(path);
+                    f.change.status = "renamed".to_string();
+                }
+            } else if let Some(path) = line.strip_prefix("copy to ") {
+                if let Some(f) = file.as_mut() {
+                    f.change.path = unquote(path);
+                    f.change.status = "copied".to_string();
+                }
+            } else if line.starts_with("Binary files ") || line == "GIT binary patch" {
+                if let Some(f) = file.as_mut() {
+                    f.hunks = vec![DiffHunk {
+                        lines: vec![marker_line("[Binary file]")],
+                    }];
+                }
+            } else if let Some(path) = line.strip_prefix("--- ") {
+                // Plain `diff -u` output has no "diff --git" line to start the file
+                if file.as_ref().is_none_or(|f| !f.hunks.is_empty()) {
+                    Self::finish_file(&mut commits, &mut file);
+                    in_commit_header = false;
+                    file = Some(PatchFile::new(parse_marker_path(path).unwrap_or_default()));
+                }
+                if let Some(f) = file.as_mut() {
+                    if parse_marker_path(path).is_none() && f.change.status == "modified" {
+                        f.change.status = "added".to_string();
+                    }
+                }
+            } else if let Some(path) = line.strip_prefix("+++ ") {
+                if let Some(f) = file.as_mut() {
+                    match parse_marker_path(path) {
+                        Some(new_path) => f.change.path = new_path,
+                        None => f.change.status = "deleted".to_string(),
+                    }
+                }
+            } else if let Some(state) = line.strip_prefix("@@ ").and_then(HunkState::parse) {
+                let f = file.get_or_insert_with(|| PatchFile::new(String::new()));
+                f.hunks.push(DiffHunk { lines: Vec::new() });
+                hunk = Some(state);
+            }
+        }
+        Self::finish_file(&mut commits, &mut file);
+
+        Self { commits }
+    }
+
+    pub fn has_files(&self) -> bool {
+        self.commits.iter().any(|c| !c.files.is_empty())
+    }
+
+    fn finish_file(commits: &mut Vec<PatchCommit>, file: &mut Option<PatchFile>) {
+        let Some(file) = file.take() else {
+            return;
+        };
+        if commits.is_empty() {
+            // Diff without commit headers (e.g. `git diff`): one pseudo entry for everything
+            commits.push(PatchCommit {
+                info: CommitInfo::local_changes("Standard Input"),
+                files: Vec::new(),
+            });
+        }
+        if let Some(commit) = commits.last_mut() {
+            commit.files.push(file);
+        }
+    }
+
+}
+
+impl PatchDiff {
+    pub fn new(patch: Patch) -> Self {
+        Self {
+            commits: patch.commits,
+            settings: DiffSettings::default(),
+            highlighter: Highlighter::new(),
+            active: None,
+            excludes: Excludes::default(),
+        }
+    }
+
+    fn find_commit(&self, commit_sha: Option<&str>) -> Option<&PatchCommit> {
+        self.commits.iter().find(|c| match commit_sha {
+            Some(sha) => c.info.sha == sha,
+            None => c.info.is_local_changes,
+        })
+    }
+
+    fn diff_for(&self, commit_sha: Option<&str>, file_path: &str) -> Vec<DiffHunk> {
+        let Some(commit) = self.find_commit(commit_sha) else {
+            return Vec::new();
+        };
+        let Some(file) = commit.files.iter().find(|f| f.change.path == file_path) else {
+            return Vec::new();
+        };
+
+        let mut lines: Vec<DiffLine> = file.hunks.iter().flat_map(|h| h.lines.iter().cloned()).collect();
+
+        let others: Vec<ChangedLines> = commit
+            .files
+            .iter()
+            .map(|f| {
+                let lines: Vec<DiffLine> = f.hunks.iter().flat_map(|h| h.lines.iter().cloned()).collect();
+                ChangedLines::from_diff_lines(&f.change.path, &lines)
+            })
+            .collect();
+        moves::mark_moves(file_path, &mut lines, &others);
+
+        let mut lines = lines.into_iter();
+        file.hunks
+            .iter()
+            .map(|h| DiffHunk {
+                lines: lines.by_ref().take(h.lines.len()).collect(),
+            })
+            .collect()
+    }
+}
+
+impl PatchFile {
+    fn new(path: String) -> Self {
+        Self {
+            change: FileChange {
+                path,
+                status: "modified".to_string(),
+            },
+            hunks: Vec::new(),
+        }
+    }
+}
+
+impl HunkState {
+    /// Parse the part of "@@ -1,5 +1,6 @@ fn context" after the leading "@@ "
+    fn parse(header: &str) -> Option<Self> {
+        let mut parts = header.split_whitespace();
+        let (old_line, old_remaining) = parse_range(parts.next()?.strip_prefix('-')?)?;
+        let (new_line, new_remaining) = parse_range(parts.next()?.strip_prefix('+')?)?;
+        Some(Self {
+            old_remaining,
+            new_remaining,
+            old_line,
+            new_line,
+        })
+    }
+
+    /// Consume one body line of the hunk, or None if the line does not belong to it
+    fn read_line(&mut self, line: &str) -> Option<DiffLine> {
+        let (tag, content) = match line.chars().next() {
+            Some('+') if self.new_remaining > 0 => (ChangeTag::Insert, &line[1..]),
+            Some('-') if self.old_remaining > 0 => (ChangeTag::Delete, &line[1..]),
+            Some(' ') if self.old_remaining > 0 && self.new_remaining > 0 => (ChangeTag::Equal, &line[1..]),
+            // Some tools strip the space from empty context lines
+            None if self.old_remaining > 0 && self.new_remaining > 0 => (ChangeTag::Equal, ""),
+            _ => return None,
+        };
+
+        let (old_num, new_num) = match tag {
+            ChangeTag::Delete => (Some(self.old_line), None),
+            ChangeTag::Insert => (None, Some(self.new_line)),
+            ChangeTag::Equal => (Some(self.old_line), Some(self.new_line)),
+        };
+        if old_num.is_some() {
+            self.old_line += 1;
+            self.old_remaining -= 1;
+        }
+        if new_num.is_some() {
+            self.new_line += 1;
+            self.new_remaining -= 1;
+        }
+
+        Some(DiffLine {
+            old_num,
+            new_num,
+            tag,
+            content: content.to_string(),
+            highlighted: None,
+            moved: None,
+            no_newline: false,
+            old_content: None,
+        })
+    }
+}
+
+fn parse_range(range: &str) -> Option<(u32, u32)> {
+    match range.split_once(',') {
+        Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+        None => Some((range.parse().ok()?, 1)),
+    }
+}
+
+fn parse_commit_sha(rest: &str) -> Option<String> {
+    let sha = rest.split_whitespace().next()?;
+    (sha.len() >= 7 && sha.chars().all(|c| c.is_ascii_hexdigit())).then(|| sha.to_string())
+}
+
+/// New-side path from "a/old b/new". Renames and copies have their paths on lines of their
+/// own, which replace this guess.
+fn parse_git_header_path(rest: &str) -> String {
+    let rest = rest.trim();
+    if let Some(idx) = rest.rfind(" \"b/") {
+        return unquote(&rest[idx + 1..]).trim_start_matches("b/").to_string();
+    }
+    // "a/<path> b/<path>" splits in the middle, even when the path has " b/" in it
+    if let Some(both) = rest.strip_prefix("a/").filter(|both| both.len() % 2 == 1) {
+        let half = both.len() / 2 - 1;
+        if let (Some(old), Some(new)) = (both.get(..half), both.get(half + 3..)) {
+            if old == new && both[half..].starts_with(" b/") {
+                return new.to_string();
+            }
+        }
+    }
+    if let Some(idx) = rest.rfind(" b/") {
+        return rest[idx + 3..].to_string();
+    }
+    rest.to_string()
+}
+
+/// Path from a "--- a/path" / "+++ b/path" line; None for /dev/null
+fn parse_marker_path(path: &str) -> Option<String> {
+    // `diff -u` appends a tab and timestamp
+    let path = unquote(path.split('\t').next().unwrap_or(path).trim_end());
+    if path == "/dev/null" {
+        return None;
+    }
+    Some(
+        path.strip_prefix("a/")
+            .or_else(|| path.strip_prefix("b/"))
+            .unwrap_or(&path)
+            .to_string(),
+    )
+}
+
+/// Path as git writes it, in double quotes with C escapes and octal bytes when it has
+/// special or (with `core.quotePath`) non-ASCII characters
+fn unquote(path: &str) -> String {
+    let path = path.trim();
+    let Some(quoted) = path.strip_prefix('"').and_then(|p| p.strip_suffix('"')) else {
+        return path.to_string();
+    };
+    let mut bytes = Vec::with_capacity(quoted.len());
+    let mut rest = quoted.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        let Some((&escaped, tail)) = rest.split_first() else {
+            break;
+        };
+        rest = tail;
+        match escaped {
+            b'a' => bytes.push(0x07),
+            b'b' => bytes.push(0x08),
+            b't' => bytes.push(b'\t'),
+            b'n' => bytes.push(b'\n'),
+            b'v' => bytes.push(0x0b),
+            b'f' => bytes.push(0x0c),
+            b'r' => bytes.push(b'\r'),
+            b'0'..=b'7' => {
+                let mut value = u32::from(escaped - b'0');
+                for _ in 0..2 {
+                    let Some((&digit @ b'0'..=b'7', tail)) = rest.split_first() else {
+                        break;
+                    };
+                    value = value * 8 + u32::from(digit - b'0');
+                    rest = tail;
+                }
+                bytes.push(value as u8);
+            }
+            other => bytes.push(other),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn marker_line(text: &str) -> DiffLine {
+    DiffLine {
+        old_num: None,
+        new_num: Some(1),
+        tag: ChangeTag::Insert,
+        content: text.to_string(),
+        highlighted: None,
+        moved: None,
+        no_newline: false,
+        old_content: None,
+    }
+}
+
+/// Remove SGR color sequences, as git colors its output when a pager is attached
+fn strip_ansi(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' && chars.peek() == Some(&'[') {
+            chars.next();
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+impl DiffSource for PatchDiff {
+    fn current_branch(&self) -> Option<&str> {
+        Some("stdin")
+    }
+
+    fn load_branches(&self) -> Result<Vec<BranchInfo>, git2::Error> {
+        Ok(Vec::new())
+    }
+
+    fn load_commits_for_branch(&self, _branch_name: &str, limit: usize) -> Result<Vec<CommitInfo>, git2::Error> {
+        Ok(self
+            .commits
+            .iter()
+            .filter(|c| !c.files.is_empty())
+            .take(limit)
+            .map(|c| c.info.clone())
+            .collect())
+    }
+
+    fn load_files(&self) -> Result<Vec<FileChange>, git2::Error> {
+        Ok(self
+            .find_commit(None)
+            .map(|c| c.files.iter().map(|f| f.change.clone()).collect())
+            .unwrap_or_default())
+    }
+
+    fn load_files_for_commit(&mut self, commit_sha: &str) -> Result<Vec<FileChange>, git2::Error> {
+        Ok(self
+            .find_commit(Some(commit_sha))
+            .map(|c| c.files.iter().map(|f| f.change.clone()).collect())
+            .unwrap_or_default())
+    }
+
+    fn load_diff_for_file(&mut self, file_path: &str) -> Result<Vec<DiffHunk>, git2::Error> {
+        Ok(self.diff_for(None, file_path))
+    }
+
+    fn load_diff_for_commit_file(&mut self, commit_sha: &str, file_path: &str) -> Result<Vec<DiffHunk>, git2::Error> {
+        Ok(self.diff_for(Some(commit_sha), file_path))
+    }
+
+    fn highlight(&mut self, commit_sha: Option<&str>, file_path: &str, lines: &[LineRef]) -> Result<Highlights, git2::Error> {
+        let is_active = matches!(self.active, Some((ref sha, ref path, _)) if sha.as_deref() == commit_sha && path == file_path);
+        if !is_active {
+            // Hunk lines only: both sides have gaps where the patch has no context
+            let Some(file) = self
+                .find_commit(commit_sha)
+                .and_then(|c| c.files.iter().find(|f| f.change.path == file_path))
+            else {
+                return Ok(Highlights::Lines(vec![None; lines.len()]));
+            };
+            let side = |num: fn(&DiffLine) -> Option<u32>| -> Vec<(u32, String)> {
+                file.hunks
+                    .iter()
+                    .flat_map(|h| h.lines.iter())
+                    .filter_map(|line| Some((num(line)?, line.content.clone())))
+                    .collect()
+            };
+            let highlight = self.highlighter.prepare(file_path, side(|l| l.old_num), side(|l| l.new_num));
+            self.active = Some((commit_sha.map(str::to_string), file_path.to_string(), highlight));
+        }
+
+        let Some((_, _, ref mut highlight)) = self.active else {
+            return Ok(Highlights::Lines(vec![None; lines.len()]));
+        };
+        Ok(self.highlighter.highlight_diff(highlight, lines))
+    }
+
+    fn settings(&self) -> DiffSettings {
+        self.settings
+    }
+
+    fn set_settings(&mut self, settings: DiffSettings) {
+        // Hunks come precomputed from the input; only remember the choice
+        self.settings = settings;
+    }
+
+    fn highlighter(&self) -> &Highlighter {
+        &self.highlighter
+    }
+
+    fn set_syntax_theme(&mut self, name: &str) -> bool {
+        // Parser checkpoints don't depend on the theme, so the active file can stay
+        self.highlighter.set_theme(name)
+    }
+
+    fn set_highlight_limit(&mut self, bytes: usize) {
+        self.highlighter.set_max_bytes(bytes);
+    }
+
+    fn excludes(&self) -> &Excludes {
+        &self.excludes
+    }
+
+    fn set_excludes(&mut self, excludes: Excludes) {
+        self.excludes = excludes;
+    }
+
+    fn is_live(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Path and status of each file of commit `index`
+    fn files(patch: &Patch, index: usize) -> Vec<(&str, &str)> {
+        patch.commits[index].files.iter().map(|f| (f.change.path.as_str(), f.change.status.as_str())).collect()
+    }
+
+    /// Sign, old and new number and content of each line of a file
+    fn lines(patch: &Patch, commit: usize, file: usize) -> Vec<(ChangeTag, Option<u32>, Option<u32>, &str)> {
+        patch.commits[commit].files[file]
+            .hunks
+            .iter()
+            .flat_map(|h| &h.lines)
+            .map(|l| (l.tag, l.old_num, l.new_num, l.content.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn renames_and_copies() {
+        let patch = Patch::parse(
+            "diff --git a/old.txt b/new.txt\n\
+             similarity index 67%\n\
+             rename from old.txt\n\
+             rename to new.txt\n\
+             index 1111111..2222222 100644\n\
+             --- a/old.txt\n\
+             +++ b/new.txt\n\
+             @@ -1,2 +1,2 @@\n\
+             \x20a\n\
+             -b\n\
+             +c\n\
+             diff --git a/src.txt b/copy.txt\n\
+             similarity index 100%\n\
+             copy from src.txt\n\
+             copy to copy.txt\n",
+        );
+        assert_eq!(files(&patch, 0), [("new.txt", "renamed"), ("copy.txt", "copied")]);
+        assert_eq!(lines(&patch, 0, 0).len(), 3);
+        assert!(patch.commits[0].files[1].hunks.is_empty());
+    }
+
+    #[test]
+    fn binary_files() {
+        let patch = Patch::parse(
+            "diff --git a/a.png b/a.png\n\
+             index 1111111..2222222 100644\n\
+             Binary files a/a.png and b/a.png differ\n\
+             diff --git a/b.bin b/b.bin\n\
+             new file mode 100644\n\
+             index 0000000..3333333\n\
+             GIT binary patch\n\
+             literal 4\n\
+             LcmZQzWMT#Y01f~L\n\
+             \n\
+             literal 0\n\
+             HcmV?d00001\n",
+        );
+        assert_eq!(files(&patch, 0), [("a.png", "modified"), ("b.bin", "added")]);
+        for file in 0..2 {
+            assert_eq!(lines(&patch, 0, file), [(ChangeTag::Insert, None, Some(1), "[Binary file]")]);
+        }
+    }
+
+    #[test]
+    fn mode_changes() {
+        let patch = Patch::parse(
+            "diff --git a/run.sh b/run.sh\n\
+             old mode 100644\n\
+             new mode 100755\n\
+             diff --git a/b.txt b/b.txt\n\
+             --- a/b.txt\n\
+             +++ b/b.txt\n\
+             @@ -1 +1 @@\n\
+             -x\n\
+             +y\n",
+        );
+        assert_eq!(files(&patch, 0), [("run.sh", "modified"), ("b.txt", "modified")]);
+        assert!(patch.commits[0].files[0].hunks.is_empty());
+        assert_eq!(lines(&patch, 0, 1).len(), 2);
+    }
+
+    #[test]
+    fn missing_newlines() {
+        let patch = Patch::parse(
+            "--- a/f\n\
+             +++ b/f\n\
+             @@ -1,2 +1,2 @@\n\
+             \x20a\n\
+             -b\n\
+             \\ No newline at end of file\n\
+             +c\n\
+             \\ No newline at end of file\n",
+        );
+        let hunk = &patch.commits[0].files[0].hunks[0];
+        let no_newline: Vec<bool> = hunk.lines.iter().map(|l| l.no_newline).collect();
+        assert_eq!(no_newline, [false, true, true]);
+        assert_eq!(lines(&patch, 0, 0)[2], (ChangeTag::Insert, None, Some(2), "c"));
+    }
+
+    #[test]
+    fn plain_diff_with_timestamps() {
+        let patch = Patch::parse(
+            "--- old/f.txt\t2024-01-01 10:00:00.000000000 +0100\n\
+             +++ new/f.txt\t2024-01-02 10:00:00.000000000 +0100\n\
+             @@ -3,2 +3,3 @@\n\
+             \x20a\n\
+             +b\n\
+             \x20c\n\
+             --- /dev/null\t1970-01-01 01:00:00.000000000 +0100\n\
+             +++ new/g.txt\t2024-01-02 10:00:00.000000000 +0100\n\
+             @@ -0,0 +1 @@\n\
+             +g\n",
+        );
+        assert_eq!(files(&patch, 0), [("new/f.txt", "modified"), ("new/g.txt", "added")]);
+        assert_eq!(
+            lines(&patch, 0, 0),
+            [
+                (ChangeTag::Equal, Some(3), Some(3), "a"),
+                (ChangeTag::Insert, None, Some(4), "b"),
+                (ChangeTag::Equal, Some(4), Some(5), "c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn colored_input() {
+        let plain = Patch::parse("diff --git a/f b/f\n--- a/f\n+++ b/f\n@@ -1 +1 @@\n-a\n+b\n");
+        let colored = Patch::parse(
+            "\x1b[1mdiff --git a/f b/f\x1b[m\n\
+             \x1b[1m--- a/f\x1b[m\n\
+             \x1b[1m+++ b/f\x1b[m\n\
+             \x1b[36m@@ -1 +1 @@\x1b[m\n\
+             \x1b[31m-a\x1b[m\n\
+             \x1b[32m+b\x1b[m\n",
+        );
+        assert_eq!(files(&colored, 0), files(&plain, 0));
+        assert_eq!(lines(&colored, 0, 0), lines(&plain, 0, 0));
+    }
+
+    #[test]
+    fn log_with_several_commits() {
+        let patch = Patch::parse(
+            "commit 1111111111111111111111111111111111111111\n\
+             Author: Ann <ann@example.com>\n\
+             Date:   Mon Jan 1 10:00:00 2024 +0100\n\
+             \n\
+             \x20   Second change\n\
+             \n\
+             \x20   With a body\n\
+             \n\
+             diff --git a/f b/f\n\
+             --- a/f\n\
+             +++ b/f\n\
+             @@ -1 +1 @@\n\
+             -b\n\
+             +c\n\
+             commit 2222222222222222222222222222222222222222\n\
+             Author: Bob <bob@example.com>\n\
+             Date:   Sun Dec 31 10:00:00 2023 +0100\n\
+             \n\
+             \x20   First change\n\
+             \n\
+             diff --git a/f b/f\n\
+             new file mode 100644\n\
+             --- /dev/null\n\
+             +++ b/f\n\
+             @@ -0,0 +1 @@\n\
+             +b\n\
+             diff --git a/g b/g\n\
+             new file mode 100644\n\
+             --- /dev/null\n\
+             +++ b/g\n\
+             @@ -0,0 +1 @@\n\
+             +g\n",
+        );
+        let commits: Vec<(&str, &str, &str)> =
+            patch.commits.iter().map(|c| (c.info.short_sha.as_str(), c.info.author.as_str(), c.info.message.as_str())).collect();
+        assert_eq!(
+            commits,
+            [("1111111", "Ann <ann@example.com>", "Second change"), ("2222222", "Bob <bob@example.com>", "First change")]
+        );
+        assert_eq!(files(&patch, 0), [("f", "modified")]);
+        assert_eq!(files(&patch, 1), [("f", "added"), ("g", "added")]);
+    }
+
+    #[test]
+    fn paths_with_spaces() {
+        let patch = Patch::parse(
+            "diff --git a/my file.txt b/my file.txt\n\
+             --- a/my file.txt\n\
+             +++ b/my file.txt\n\
+             @@ -1 +1 @@\n\
+             -a\n\
+             +b\n\
+             diff --git a/x b/y.sh b/x b/y.sh\n\
+             old mode 100644\n\
+             new mode 100755\n\
+             diff --git \"a/tab\\there\" \"b/tab\\there\"\n\
+             old mode 100644\n\
+             new mode 100755\n\
+             diff --git \"a/caf\\303\\251 b/x\" \"b/caf\\303\\251 b/x\"\n\
+             deleted file mode 100644\n",
+        );
+        let expected = [("my file.txt", "modified"), ("x b/y.sh", "modified"), ("tab\there", "modified"), ("café b/x", "deleted")];
+        assert_eq!(files(&patch, 0), expected);
+    }
+}
@@ -0,0 +1,101 @@
+use clap::ValueEnum;
+use std::io::{self, Write};
+
+use crate::export;
+use crate::source::DiffSource;
+use crate::text;
+use crate::theme::{self, Theme};
+use crate::types::{DiffHunk, FileChange};
+use crate::ui::{LineFormat, Ui};
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum ColorMode {
+    Never,
+    Always,
+    Auto,
+}
+
+impl ColorMode {
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        match self {
+            ColorMode::Never => false,
+            ColorMode::Always => true,
+            ColorMode::Auto => is_terminal,
+        }
+    }
+}
+
+/// Write every changed file with its hunks as plain text, for pipes and CI logs.
+/// Repositories print their local changes; patches read from stdin print every commit.
+pub fn print_changes(
+    source: &mut dyn DiffSource,
+    color: bool,
+    theme: &Theme,
+    format: LineFormat,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let width = crossterm::terminal::size().map(|(w, _)| w as usize).unwrap_or(80);
+    let mut first = true;
+
+    for commit in source.output_commits().map_err(io::Error::other)? {
+        let files = export::collect_files(source, &commit).map_err(io::Error::other)?;
+
+        if !commit.is_local_changes {
+            print_separated(out, &mut first)?;
+            let title = format!("commit {} {}", commit.short_sha, text::printable(&commit.message));
+            if color {
+                writeln!(out, "{}{}{}", theme.fg_added, title, theme::RESET)?;
+            } else {
+                writeln!(out, "{}", title)?;
+            }
+        }
+
+        for file in &files {
+            print_separated(out, &mut first)?;
+            print_file(out, &file.change, &file.hunks, width, color, theme, format)?;
+        }
+    }
+
+    out.flush()
+}
+
+fn print_separated(out: &mut impl Write, first: &mut bool) -> io::Result<()> {
+    if !*first {
+        writeln!(out)?;
+    }
+    *first = false;
+    Ok(())
+}
+
+pub fn print_file(
+    out: &mut impl Write,
+    file: &FileChange,
+    hunks: &[DiffHunk],
+    width: usize,
+    color: bool,
+    theme: &Theme,
+    format: LineFormat,
+) -> io::Result<()> {
+    let header = format!(" {} ({}) ", text::printable(&file.path), file.status);
+    if color {
+        writeln!(out, "{}{}{}{}", theme.bg_header, theme.fg_header, header, theme::RESET)?;
+    } else {
+        writeln!(out, "{}", header)?;
+    }
+
+    for (hunk_idx, hunk) in hunks.iter().enumerate() {
+        if hunk_idx > 0 {
+            let sep = "─".repeat(width);
+            if color {
+                writeln!(out, "{}{}{}{}", theme.bg_hunk, theme.fg_separator, sep, theme::RESET)?;
+            } else {
+                writeln!(out, "{}", sep)?;
+            }
+        }
+        for line in &hunk.lines {
+            writeln!(out, "{}", Ui::format_diff_line(line, None, color, theme, format))?;
+        }
+    }
+
+    Ok(())
+}
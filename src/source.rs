@@ -0,0 +1,162 @@
+use std::path::Path;
+
+use crate::config::Excludes;
+use crate::export;
+use crate::git::GitDiff;
+use crate::highlighter::{Highlighter, SyntaxTheme};
+use crate::types::{BranchInfo, CommitInfo, DiffHunk, DiffLine, DiffSettings, FileChange, Highlights, LineRef};
+
+/// Where the viewer gets commits, files and hunks from: a repository or a parsed patch
+pub trait DiffSource {
+    fn current_branch(&self) -> Option<&str>;
+    fn load_branches(&self) -> Result<Vec<BranchInfo>, git2::Error>;
+    fn load_commits_for_branch(&self, branch_name: &str, limit: usize) -> Result<Vec<CommitInfo>, git2::Error>;
+    fn load_files(&self) -> Result<Vec<FileChange>, git2::Error>;
+    fn load_files_for_commit(&mut self, commit_sha: &str) -> Result<Vec<FileChange>, git2::Error>;
+    fn load_diff_for_file(&mut self, file_path: &str) -> Result<Vec<DiffHunk>, git2::Error>;
+    fn load_diff_for_commit_file(&mut self, commit_sha: &str, file_path: &str) -> Result<Vec<DiffHunk>, git2::Error>;
+    /// Syntax highlighting for `lines` of a file's diff (commit `None` for local changes), computed on demand
+    fn highlight(&mut self, commit_sha: Option<&str>, file_path: &str, lines: &[LineRef]) -> Result<Highlights, git2::Error>;
+    fn settings(&self) -> DiffSettings;
+    fn set_settings(&mut self, settings: DiffSettings);
+    fn highlighter(&self) -> &Highlighter;
+    /// Highlight with the named theme from now on; false if there is no such theme
+    fn set_syntax_theme(&mut self, name: &str) -> bool;
+    /// Leave files bigger than `bytes` unhighlighted in diffs loaded from now on
+    fn set_highlight_limit(&mut self, bytes: usize);
+    fn excludes(&self) -> &Excludes;
+    /// Leave files matching `excludes` out of `files_of`
+    fn set_excludes(&mut self, excludes: Excludes);
+
+    fn syntax_theme(&self) -> &str {
+        self.highlighter().theme_name()
+    }
+
+    fn syntax_themes(&self) -> Vec<SyntaxTheme> {
+        self.highlighter().themes()
+    }
+
+    /// Changed files of `commit` (or of the local changes), without excluded ones
+    fn files_of(&mut self, commit: &CommitInfo) -> Result<Vec<FileChange>, git2::Error> {
+        let files = if commit.is_local_changes {
+            self.load_files()?
+        } else {
+            self.load_files_for_commit(&commit.sha)?
+        };
+        Ok(files.into_iter().filter(|f| !self.excludes().matches(&f.path)).collect())
+    }
+
+    /// Whether the content can change while the viewer is open (and is worth polling)
+    fn is_live(&self) -> bool {
+        true
+    }
+
+    /// Directory the file paths are relative to, when it isn't the current one
+    fn workdir(&self) -> Option<&Path> {
+        None
+    }
+
+    /// The diff lines from `from` up to `to`, to widen the context between two hunks;
+    /// none when the source only has the hunks themselves
+    fn context(&mut self, _commit_sha: Option<&str>, _file_path: &str, _from: LineRef, _to: LineRef) -> Result<Vec<DiffLine>, git2::Error> {
+        Ok(Vec::new())
+    }
+
+    /// Stage the changes on `lines` of a file's local changes; returns how many were staged
+    fn stage_lines(&mut self, _file_path: &str, _lines: &[LineRef]) -> Result<usize, git2::Error> {
+        Err(git2::Error::from_str("a patch has no index to stage into"))
+    }
+
+    /// What print and export modes write: the local changes of a repository, every commit of a patch
+    fn output_commits(&self) -> Result<Vec<CommitInfo>, git2::Error> {
+        if self.is_live() {
+            return Ok(vec![CommitInfo::local_changes("Local Changes")]);
+        }
+        let branch = self.current_branch().unwrap_or_default().to_string();
+        self.load_commits_for_branch(&branch, usize::MAX)
+    }
+
+    /// Changes of `commit` as a `git format-patch` style mail
+    fn format_patch(&mut self, commit: &CommitInfo) -> Result<String, git2::Error> {
+        let files = export::collect_files(self, commit)?;
+        Ok(export::to_mbox_patch(commit, &files))
+    }
+}
+
+impl DiffSource for GitDiff {
+    fn current_branch(&self) -> Option<&str> {
+        self.get_current_branch()
+    }
+
+    fn load_branches(&self) -> Result<Vec<BranchInfo>, git2::Error> {
+        GitDiff::load_branches(self)
+    }
+
+    fn load_commits_for_branch(&self, branch_name: &str, limit: usize) -> Result<Vec<CommitInfo>, git2::Error> {
+        GitDiff::load_commits_for_branch(self, branch_name, limit)
+    }
+
+    fn load_files(&self) -> Result<Vec<FileChange>, git2::Error> {
+        GitDiff::load_files(self)
+    }
+
+    fn load_files_for_commit(&mut self, commit_sha: &str) -> Result<Vec<FileChange>, git2::Error> {
+        GitDiff::load_files_for_commit(self, commit_sha)
+    }
+
+    fn load_diff_for_file(&mut self, file_path: &str) -> Result<Vec<DiffHunk>, git2::Error> {
+        GitDiff::load_diff_for_file(self, file_path)
+    }
+
+    fn load_diff_for_commit_file(&mut self, commit_sha: &str, file_path: &str) -> Result<Vec<DiffHunk>, git2::Error> {
+        GitDiff::load_diff_for_commit_file(self, commit_sha, file_path)
+    }
+
+    fn highlight(&mut self, commit_sha: Option<&str>, file_path: &str, lines: &[LineRef]) -> Result<Highlights, git2::Error> {
+        GitDiff::highlight(self, commit_sha, file_path, lines)
+    }
+
+    fn settings(&self) -> DiffSettings {
+        GitDiff::settings(self)
+    }
+
+    fn set_settings(&mut self, settings: DiffSettings) {
+        GitDiff::set_settings(self, settings)
+    }
+
+    fn highlighter(&self) -> &Highlighter {
+        GitDiff::highlighter(self)
+    }
+
+    fn set_syntax_theme(&mut self, name: &str) -> bool {
+        GitDiff::set_syntax_theme(self, name)
+    }
+
+    fn set_highlight_limit(&mut self, bytes: usize) {
+        GitDiff::set_highlight_limit(self, bytes)
+    }
+
+    fn excludes(&self) -> &Excludes {
+        GitDiff::excludes(self)
+    }
+
+    fn set_excludes(&mut self, excludes: Excludes) {
+        GitDiff::set_excludes(self, excludes)
+    }
+
+    fn format_patch(&mut self, commit: &CommitInfo) -> Result<String, git2::Error> {
+        GitDiff::format_patch(self, commit)
+    }
+
+    fn workdir(&self) -> Option<&Path> {
+        GitDiff::workdir(self)
+    }
+
+    fn context(&mut self, commit_sha: Option<&str>, file_path: &str, from: LineRef, to: LineRef) -> Result<Vec<DiffLine>, git2::Error> {
+        GitDiff::context(self, commit_sha, file_path, from, to)
+    }
+
+    fn stage_lines(&mut self, file_path: &str, lines: &[LineRef]) -> Result<usize, git2::Error> {
+        GitDiff::stage_lines(self, file_path, lines)
+    }
+}
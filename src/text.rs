@@ -0,0 +1,191 @@
+use std::borrow::Cow;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Columns `text` takes on screen: wide characters count two, combining marks none
+pub fn width(text: &str) -> usize {
+    text.graphemes(true).map(UnicodeWidthStr::width).sum()
+}
+
+/// The longest start of `text` that fits in `max` columns
+pub fn take_width(text: &str, max: usize) -> &str {
+    let mut used = 0;
+    for (index, grapheme) in text.grapheme_indices(true) {
+        used += grapheme.width();
+        if used > max {
+            return &text[..index];
+        }
+    }
+    text
+}
+
+/// `text` cut to `max` columns, ending in `…` when something was cut
+pub fn truncate(text: &str, max: usize) -> String {
+    if width(text) <= max {
+        return text.to_string();
+    }
+    if max == 0 {
+        return String::new();
+    }
+    format!("{}…", take_width(text, max - 1))
+}
+
+/// `text` cut to `max` columns from the front, starting with `…` when something was cut
+pub fn truncate_start(text: &str, max: usize) -> String {
+    if width(text) <= max {
+        return text.to_string();
+    }
+    if max == 0 {
+        return String::new();
+    }
+    let mut used = 1;
+    let mut start = text.len();
+    for (index, grapheme) in text.grapheme_indices(true).rev() {
+        used += grapheme.width();
+        if used > max {
+            break;
+        }
+        start = index;
+    }
+    format!("…{}", &text[start..])
+}
+
+/// `text` followed by spaces up to `columns`
+pub fn pad(text: &str, columns: usize) -> String {
+    let padding = columns.saturating_sub(width(text));
+    format!("{}{}", text, " ".repeat(padding))
+}
+
+/// `text` truncated with `…` or padded to exactly `columns`
+pub fn fit(text: &str, columns: usize) -> String {
+    pad(&truncate(text, columns), columns)
+}
+
+/// `text` safe to send to a terminal: control characters other than tabs show as their
+/// Control Pictures (`␛` for ESC) and C1 controls as `�`, so file content can't move the
+/// cursor or change colors
+pub fn printable(text: &str) -> Cow<'_, str> {
+    let control = |c: char| c != '\t' && c.is_control();
+    if !text.contains(control) {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(
+        text.chars()
+            .map(|c| match c {
+                c if !control(c) => c,
+                '\x7f' => '\u{2421}',
+                c if c < ' ' => char::from_u32(0x2400 + c as u32).unwrap_or('\u{fffd}'),
+                _ => '\u{fffd}',
+            })
+            .collect(),
+    )
+}
+
+/// `text` with tabs replaced by spaces up to the next multiple of `tab_width`, for text
+/// that starts at `column`; returns the column after it too
+pub fn expand_tabs(text: &str, tab_width: usize, column: usize) -> (String, usize) {
+    let tab_width = tab_width.max(1);
+    let mut expanded = String::with_capacity(text.len());
+    let mut column = column;
+    for grapheme in text.graphemes(true) {
+        if grapheme == "\t" {
+            let spaces = tab_width - column % tab_width;
+            expanded.push_str(&" ".repeat(spaces));
+            column += spaces;
+        } else {
+            expanded.push_str(grapheme);
+            column += grapheme.width();
+        }
+    }
+    (expanded, column)
+}
+
+/// Columns at which `text` breaks into rows of at most `columns`, without splitting a character
+pub fn wrap(text: &str, columns: usize) -> Vec<usize> {
+    let mut starts = vec![0];
+    let mut row_start = 0;
+    let mut column = 0;
+    for grapheme in text.graphemes(true) {
+        let width = grapheme.width();
+        // A character wider than a whole row still gets one to itself
+        if column + width - row_start > columns && column > row_start {
+            starts.push(column);
+            row_start = column;
+        }
+        column += width;
+    }
+    starts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Woman technologist, one grapheme of three characters joined by ZWJ
+    const TECHNOLOGIST: &str = "\u{1f469}\u{200d}\u{1f4bb}";
+
+    #[test]
+    fn wide_characters_count_two_columns() {
+        assert_eq!(width("漢字"), 4);
+        assert_eq!(width(TECHNOLOGIST), 2);
+        assert_eq!(width("e\u{301}"), 1);
+    }
+
+    #[test]
+    fn take_width_keeps_graphemes_whole() {
+        // The wide character straddling the cut is left out rather than halved
+        assert_eq!(take_width("a漢字", 2), "a");
+        assert_eq!(take_width("a漢字", 3), "a漢");
+        assert_eq!(take_width(&format!("{}x", TECHNOLOGIST), 1), "");
+        assert_eq!(take_width(&format!("{}x", TECHNOLOGIST), 2), TECHNOLOGIST);
+        // A combining mark stays with its base at the boundary
+        assert_eq!(take_width("ae\u{301}b", 2), "ae\u{301}");
+    }
+
+    #[test]
+    fn truncate_marks_the_cut() {
+        assert_eq!(truncate("漢字漢字", 8), "漢字漢字");
+        assert_eq!(truncate("漢字漢字", 5), "漢字…");
+        assert_eq!(truncate("漢字漢字", 4), "漢…");
+        assert_eq!(truncate(&format!("{}{}", TECHNOLOGIST, TECHNOLOGIST), 3), format!("{}…", TECHNOLOGIST));
+        assert_eq!(truncate("cafe\u{301}s", 4), "caf…");
+        assert_eq!(truncate("abc", 0), "");
+    }
+
+    #[test]
+    fn truncate_start_keeps_the_end() {
+        assert_eq!(truncate_start("src/漢字.rs", 6), "…字.rs");
+        assert_eq!(truncate_start("src/漢字.rs", 5), "….rs");
+        assert_eq!(truncate_start(&format!("ab{}", TECHNOLOGIST), 3), format!("…{}", TECHNOLOGIST));
+        assert_eq!(truncate_start("abe\u{301}", 2), "…e\u{301}");
+    }
+
+    #[test]
+    fn fit_pads_to_exact_width() {
+        assert_eq!(fit("漢字", 6), "漢字  ");
+        assert_eq!(fit("漢字漢", 5), "漢字…");
+        // No room for the wide character after the cut, so a space fills its column
+        assert_eq!(fit("a漢字", 3), "a… ");
+        assert_eq!(width(&fit(&format!("{}{}", TECHNOLOGIST, TECHNOLOGIST), 3)), 3);
+    }
+
+    #[test]
+    fn tabs_stop_at_display_columns() {
+        assert_eq!(expand_tabs("漢\tx", 4, 0), ("漢  x".to_string(), 5));
+        assert_eq!(expand_tabs("漢字a\tx", 4, 0), ("漢字a   x".to_string(), 9));
+        assert_eq!(expand_tabs(&format!("{}\t", TECHNOLOGIST), 8, 1), (format!("{}     ", TECHNOLOGIST), 8));
+        assert_eq!(expand_tabs("e\u{301}\t", 4, 0), ("e\u{301}   ".to_string(), 4));
+    }
+
+    #[test]
+    fn wrap_never_splits_a_character() {
+        assert_eq!(wrap("abcdef", 4), [0, 4]);
+        // The wide character that would straddle the row end starts the next row
+        assert_eq!(wrap("a漢字", 2), [0, 1, 3]);
+        assert_eq!(wrap("ab漢", 3), [0, 2]);
+        assert_eq!(wrap(&format!("a{}b", TECHNOLOGIST), 2), [0, 1, 3]);
+        assert_eq!(wrap("ae\u{301}b", 2), [0, 2]);
+        // Too wide for any row, it gets one of its own
+        assert_eq!(wrap("漢a", 1), [0, 2]);
+    }
+}
@@ -1,22 +1,425 @@
-// ANSI escape codes for IntelliJ Darcula-like theme
-// Using 256-color palette for better terminal compatibility
+// UI color themes: the IntelliJ Darcula-like default plus light, high-contrast and colorblind variants.
+// Defined on the 256-color palette and written at the depth the terminal supports
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::config::ColorValue;
+use crate::highlighter::SyntaxTheme;
 
 pub const RESET: &str = "\x1b[0m";
 
-// Backgrounds - using 256-color for compatibility
-pub const BG_DARK: &str = "\x1b[48;5;236m";
-pub const BG_HEADER: &str = "\x1b[48;5;238m";
-pub const BG_SELECTED: &str = "\x1b[48;5;24m";
-pub const BG_PANEL: &str = "\x1b[48;5;235m";
-pub const BG_HUNK: &str = "\x1b[48;5;239m";
-
-// Foregrounds - 256-color palette
-pub const FG_DEFAULT: &str = "\x1b[38;5;252m";
-pub const FG_ADDED: &str = "\x1b[38;5;114m";
-pub const FG_REMOVED: &str = "\x1b[38;5;210m";
-pub const FG_HEADER: &str = "\x1b[38;5;75m";
-pub const FG_SEPARATOR: &str = "\x1b[38;5;240m";
-pub const FG_DIM: &str = "\x1b[38;5;245m";
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThemeName {
+    #[default]
+    Dark,
+    Light,
+    HighContrast,
+    /// Blue/orange instead of green/red for added and removed lines
+    Colorblind,
+}
+
+/// Every color the UI draws with
+#[derive(Clone)]
+pub struct Theme {
+    pub bg_dark: Paint,
+    pub bg_header: Paint,
+    pub bg_selected: Paint,
+    pub bg_panel: Paint,
+    pub bg_hunk: Paint,
+    /// Diff panel, gutter and unchanged lines
+    pub bg_diff: Paint,
+    pub bg_added: Paint,
+    pub bg_removed: Paint,
+    pub bg_moved_from: Paint,
+    pub bg_moved_from_alt: Paint,
+    pub bg_moved_to: Paint,
+    pub bg_moved_to_alt: Paint,
+    /// Trailing whitespace and spaces before tabs in added lines, when whitespace is shown
+    pub bg_whitespace_error: Paint,
+
+    pub fg_default: Paint,
+    pub fg_context: Paint,
+    pub fg_line_number: Paint,
+    pub fg_added: Paint,
+    pub fg_removed: Paint,
+    pub fg_header: Paint,
+    pub fg_separator: Paint,
+    pub fg_dim: Paint,
+    pub fg_moved_from: Paint,
+    pub fg_moved_to: Paint,
+
+    /// Syntax theme that reads well on `bg_diff`, unless one is chosen explicitly
+    pub syntax_theme: &'static str,
+}
+
+const fn bg(index: u8) -> Paint {
+    Paint::bg(Color::Fixed(index))
+}
+
+const fn fg(index: u8) -> Paint {
+    Paint::fg(Color::Fixed(index))
+}
+
+impl Theme {
+    pub fn named(name: ThemeName) -> Self {
+        match name {
+            ThemeName::Dark => Self::dark(),
+            ThemeName::Light => Self::light(),
+            ThemeName::HighContrast => Self::high_contrast(),
+            ThemeName::Colorblind => Self::colorblind(),
+        }
+    }
+
+    pub fn dark() -> Self {
+        Self {
+            bg_dark: bg(236),
+            bg_header: bg(238),
+            bg_selected: bg(24),
+            bg_panel: bg(235),
+            bg_hunk: bg(239),
+            bg_diff: bg(236),
+            bg_added: bg(22),
+            bg_removed: bg(52),
+            bg_moved_from: bg(53),
+            bg_moved_from_alt: bg(54),
+            bg_moved_to: bg(23),
+            bg_moved_to_alt: bg(24),
+            bg_whitespace_error: bg(124),
+
+            fg_default: fg(252),
+            fg_context: fg(250),
+            fg_line_number: fg(243),
+            fg_added: fg(114),
+            fg_removed: fg(210),
+            fg_header: fg(75),
+            fg_separator: fg(240),
+            fg_dim: fg(245),
+            fg_moved_from: fg(177),
+            fg_moved_to: fg(80),
+
+            syntax_theme: "base16-eighties.dark",
+        }
+    }
+
+    pub fn light() -> Self {
+        Self {
+            bg_dark: bg(254),
+            bg_header: bg(252),
+            bg_selected: bg(153),
+            bg_panel: bg(255),
+            bg_hunk: bg(251),
+            bg_diff: bg(231),
+            bg_added: bg(194),
+            bg_removed: bg(224),
+            bg_moved_from: bg(225),
+            bg_moved_from_alt: bg(219),
+            bg_moved_to: bg(195),
+            bg_moved_to_alt: bg(159),
+            bg_whitespace_error: bg(210),
+
+            fg_default: fg(235),
+            fg_context: fg(237),
+            fg_line_number: fg(245),
+            fg_added: fg(28),
+            fg_removed: fg(124),
+            fg_header: fg(25),
+            fg_separator: fg(249),
+            fg_dim: fg(242),
+            fg_moved_from: fg(90),
+            fg_moved_to: fg(30),
+
+            syntax_theme: "InspiredGitHub",
+        }
+    }
+
+    pub fn high_contrast() -> Self {
+        Self {
+            bg_dark: bg(16),
+            bg_header: bg(236),
+            bg_selected: bg(21),
+            bg_panel: bg(16),
+            bg_hunk: bg(238),
+            bg_diff: bg(16),
+            bg_added: bg(22),
+            bg_removed: bg(88),
+            bg_moved_from: bg(54),
+            bg_moved_from_alt: bg(90),
+            bg_moved_to: bg(23),
+            bg_moved_to_alt: bg(30),
+            bg_whitespace_error: bg(160),
+
+            fg_default: fg(231),
+            fg_context: fg(231),
+            fg_line_number: fg(250),
+            fg_added: fg(46),
+            fg_removed: fg(203),
+            fg_header: fg(51),
+            fg_separator: fg(250),
+            fg_dim: fg(252),
+            fg_moved_from: fg(213),
+            fg_moved_to: fg(87),
+
+            syntax_theme: "base16-eighties.dark",
+        }
+    }
+
+    /// Dark, with added lines in blue and removed lines in orange; moves use purple and olive
+    pub fn colorblind() -> Self {
+        Self {
+            bg_added: bg(18),
+            bg_removed: bg(94),
+            bg_moved_from: bg(53),
+            bg_moved_from_alt: bg(54),
+            bg_moved_to: bg(58),
+            bg_moved_to_alt: bg(100),
+            bg_whitespace_error: bg(125),
+            fg_added: fg(75),
+            fg_removed: fg(214),
+            fg_header: fg(153),
+            fg_moved_from: fg(177),
+            fg_moved_to: fg(186),
+            ..Self::dark()
+        }
+    }
+
+    fn color_mut(&mut self, name: &str) -> Option<&mut Paint> {
+        Some(match name {
+            "bg_dark" => &mut self.bg_dark,
+            "bg_header" => &mut self.bg_header,
+            "bg_selected" => &mut self.bg_selected,
+            "bg_panel" => &mut self.bg_panel,
+            "bg_hunk" => &mut self.bg_hunk,
+            "bg_diff" => &mut self.bg_diff,
+            "bg_added" => &mut self.bg_added,
+            "bg_removed" => &mut self.bg_removed,
+            "bg_moved_from" => &mut self.bg_moved_from,
+            "bg_moved_from_alt" => &mut self.bg_moved_from_alt,
+            "bg_moved_to" => &mut self.bg_moved_to,
+            "bg_moved_to_alt" => &mut self.bg_moved_to_alt,
+            "bg_whitespace_error" => &mut self.bg_whitespace_error,
+            "fg_default" => &mut self.fg_default,
+            "fg_context" => &mut self.fg_context,
+            "fg_line_number" => &mut self.fg_line_number,
+            "fg_added" => &mut self.fg_added,
+            "fg_removed" => &mut self.fg_removed,
+            "fg_header" => &mut self.fg_header,
+            "fg_separator" => &mut self.fg_separator,
+            "fg_dim" => &mut self.fg_dim,
+            "fg_moved_from" => &mut self.fg_moved_from,
+            "fg_moved_to" => &mut self.fg_moved_to,
+            _ => return None,
+        })
+    }
+
+    /// Apply the `[colors]` overrides from the config file
+    pub fn override_colors(&mut self, colors: &BTreeMap<String, ColorValue>) -> Result<(), String> {
+        for (name, value) in colors {
+            let color = Color::parse(value).ok_or_else(|| format!("invalid color for {}", name))?;
+            let paint = self.color_mut(name).ok_or_else(|| format!("unknown theme color {}", name))?;
+            paint.color = color;
+        }
+        Ok(())
+    }
+
+    /// Take the diff background and text color from the syntax theme, if it specifies both
+    pub fn match_syntax_theme(&mut self, syntax: &SyntaxTheme) {
+        if let (Some(bg), Some(fg)) = (syntax.background, syntax.foreground) {
+            self.bg_diff = Paint::bg(Color::Rgb(bg.r, bg.g, bg.b));
+            self.fg_context = Paint::fg(Color::Rgb(fg.r, fg.g, fg.b));
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+/// How many colors the terminal can show
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum, Deserialize)]
+pub enum ColorDepth {
+    #[value(name = "16")]
+    #[serde(rename = "16")]
+    Ansi16,
+    #[value(name = "256")]
+    #[serde(rename = "256")]
+    Ansi256,
+    #[value(name = "truecolor", alias = "24bit")]
+    #[serde(rename = "truecolor", alias = "24bit")]
+    TrueColor,
+}
+
+impl ColorDepth {
+    /// `GITTI_COLOR_DEPTH`, if set to a valid depth
+    pub fn from_env() -> Option<Self> {
+        let value = std::env::var("GITTI_COLOR_DEPTH").ok()?;
+        ColorDepth::from_str(&value, true).ok()
+    }
+
+    /// Guessed from `COLORTERM` and `TERM`
+    pub fn detect() -> Self {
+        let var = |name| std::env::var(name).unwrap_or_default().to_lowercase();
+        let colorterm = var("COLORTERM");
+        let term = var("TERM");
+        if colorterm == "truecolor" || colorterm == "24bit" || term.contains("direct") || term.contains("truecolor") {
+            ColorDepth::TrueColor
+        } else if term.contains("256") || !colorterm.is_empty() {
+            ColorDepth::Ansi256
+        } else if term == "linux" || term == "ansi" || term.starts_with("vt") || term.starts_with("cons") {
+            ColorDepth::Ansi16
+        } else {
+            ColorDepth::Ansi256
+        }
+    }
+}
+
+static COLOR_DEPTH: AtomicU8 = AtomicU8::new(ColorDepth::Ansi256 as u8);
+
+/// Set once at startup; every `Paint` is written at this depth
+pub fn set_color_depth(depth: ColorDepth) {
+    COLOR_DEPTH.store(depth as u8, Ordering::Relaxed);
+}
+
+fn color_depth() -> ColorDepth {
+    match COLOR_DEPTH.load(Ordering::Relaxed) {
+        d if d == ColorDepth::Ansi16 as u8 => ColorDepth::Ansi16,
+        d if d == ColorDepth::TrueColor as u8 => ColorDepth::TrueColor,
+        _ => ColorDepth::Ansi256,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Color {
+    /// Index into the 256-color palette
+    Fixed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    fn parse(value: &ColorValue) -> Option<Self> {
+        match value {
+            ColorValue::Index(index) => Some(Color::Fixed(*index)),
+            ColorValue::Hex(hex) => {
+                let hex = hex.strip_prefix('#')?;
+                if hex.len() != 6 {
+                    return None;
+                }
+                let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+                Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?))
+            }
+        }
+    }
+
+    fn to_rgb(self) -> (u8, u8, u8) {
+        match self {
+            Color::Rgb(r, g, b) => (r, g, b),
+            Color::Fixed(index) => fixed_to_rgb(index),
+        }
+    }
+}
+
+/// A foreground or background color; displays as the escape sequence for the current color depth
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Paint {
+    color: Color,
+    background: bool,
+}
+
+impl Paint {
+    pub const fn fg(color: Color) -> Self {
+        Self { color, background: false }
+    }
+
+    pub const fn bg(color: Color) -> Self {
+        Self { color, background: true }
+    }
+}
+
+impl Paint {
+    /// The color as CSS `#rrggbb`
+    pub fn css(&self) -> String {
+        let (r, g, b) = self.color.to_rgb();
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+impl fmt::Display for Paint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let layer = if self.background { 48 } else { 38 };
+        match (color_depth(), self.color) {
+            (ColorDepth::TrueColor, color) => {
+                let (r, g, b) = color.to_rgb();
+                write!(f, "\x1b[{};2;{};{};{}m", layer, r, g, b)
+            }
+            (ColorDepth::Ansi256, Color::Fixed(index)) => write!(f, "\x1b[{};5;{}m", layer, index),
+            (ColorDepth::Ansi256, Color::Rgb(r, g, b)) => write!(f, "\x1b[{};5;{}m", layer, rgb_to_256(r, g, b)),
+            (ColorDepth::Ansi16, color) => {
+                let index = match color {
+                    Color::Fixed(index) if index < 16 => index,
+                    color => {
+                        let (r, g, b) = color.to_rgb();
+                        rgb_to_16(r, g, b)
+                    }
+                };
+                // 30-37 / 90-97 for foregrounds, 40-47 / 100-107 for backgrounds
+                let base = if index < 8 { 30 } else { 90 - 8 };
+                let offset = if self.background { 10 } else { 0 };
+                write!(f, "\x1b[{}m", base + offset + index)
+            }
+        }
+    }
+}
+
+/// The 16 basic colors, as VGA shows them; terminals vary but roughly agree
+const ANSI_16: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (128, 0, 0),
+    (0, 128, 0),
+    (128, 128, 0),
+    (0, 0, 128),
+    (128, 0, 128),
+    (0, 128, 128),
+    (192, 192, 192),
+    (128, 128, 128),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (0, 0, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// RGB value of a 256-color palette index (xterm defaults)
+fn fixed_to_rgb(index: u8) -> (u8, u8, u8) {
+    const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    match index {
+        0..=15 => ANSI_16[index as usize],
+        16..=231 => {
+            let i = index - 16;
+            (CUBE[(i / 36) as usize], CUBE[(i / 6 % 6) as usize], CUBE[(i % 6) as usize])
+        }
+        _ => {
+            let level = 8 + (index - 232) * 10;
+            (level, level, level)
+        }
+    }
+}
+
+/// Closest of the 16 basic colors
+fn rgb_to_16(r: u8, g: u8, b: u8) -> u8 {
+    let distance = |(cr, cg, cb): (u8, u8, u8)| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, cr) + d(g, cg) + d(b, cb)
+    };
+    (0..16u8).min_by_key(|&i| distance(ANSI_16[i as usize])).unwrap_or(7)
+}
 
 /// Convert RGB to closest 256-color palette index
 pub fn rgb_to_256(r: u8, g: u8, b: u8) -> u8 {
@@ -28,13 +431,28 @@ pub fn rgb_to_256(r: u8, g: u8, b: u8) -> u8 {
         if r > 248 {
             return 231; // white
         }
-        return (((r as u16 - 8) / 10) as u8) + 232; // grayscale 232-255
+        return ((r - 8) / 10).min(23) + 232; // grayscale 232-255
     }
-    
+
     // Convert to 6x6x6 color cube (indices 16-231)
     let r_idx = if r < 48 { 0 } else { ((r as u16 - 35) / 40).min(5) as u8 };
     let g_idx = if g < 48 { 0 } else { ((g as u16 - 35) / 40).min(5) as u8 };
     let b_idx = if b < 48 { 0 } else { ((b as u16 - 35) / 40).min(5) as u8 };
-    
+
     16 + 36 * r_idx + 6 * g_idx + b_idx
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn greys_map_to_greys() {
+        for level in 0..=255u8 {
+            let index = rgb_to_256(level, level, level);
+            let (r, g, b) = fixed_to_rgb(index);
+            assert!(r == g && g == b, "grey {} became {}", level, index);
+            assert!(r.abs_diff(level) <= 10, "grey {} became {} ({})", level, index, r);
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use similar::ChangeTag;
+use clap::ValueEnum;
+use serde::Deserialize;
+use similar::{Algorithm, ChangeTag};
 use syntect::highlighting::Style;
 
 #[derive(Clone, PartialEq)]
@@ -7,20 +9,67 @@ pub struct FileChange {
     pub status: String,
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub struct DiffLine {
     pub old_num: Option<u32>,
     pub new_num: Option<u32>,
     pub tag: ChangeTag,
     pub content: String,
     pub highlighted: Option<Vec<(Style, String)>>,
+    pub moved: Option<MoveInfo>,
+    /// Last line of a file that doesn't end in a newline
+    pub no_newline: bool,
+    /// Old side of a context line that differs from `content` in ignored whitespace,
+    /// with its line ending
+    pub old_content: Option<String>,
+}
+
+impl DiffLine {
+    /// A note about the whole file rather than one of its lines, like a changed encoding
+    pub fn notice(text: String) -> Self {
+        Self {
+            old_num: None,
+            new_num: None,
+            tag: ChangeTag::Equal,
+            content: text,
+            highlighted: None,
+            moved: None,
+            no_newline: false,
+            old_content: None,
+        }
+    }
+
+    pub fn is_notice(&self) -> bool {
+        self.old_num.is_none() && self.new_num.is_none()
+    }
+}
+
+/// Other side of a line that was moved rather than deleted/inserted
+#[derive(Clone, PartialEq)]
+pub struct MoveInfo {
+    pub path: String,
+    /// `new_num` of the destination for deleted lines, `old_num` of the source for inserted lines
+    pub line: u32,
+    /// Flips between adjacent moved blocks so their boundaries stay visible
+    pub alternate: bool,
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub struct DiffHunk {
     pub lines: Vec<DiffLine>,
 }
 
+/// A line of a file's diff, identified by its (old, new) line numbers
+pub type LineRef = (Option<u32>, Option<u32>);
+
+/// Result of highlighting lines on demand
+pub enum Highlights {
+    /// Spans for each requested line, `None` where there was nothing to highlight
+    Lines(Vec<Option<Vec<(Style, String)>>>),
+    /// The file is too large to be highlighted
+    TooLarge,
+}
+
 #[derive(Clone, PartialEq)]
 pub struct CommitInfo {
     pub sha: String,
@@ -30,9 +79,132 @@ pub struct CommitInfo {
     pub is_local_changes: bool,
 }
 
+impl CommitInfo {
+    /// Pseudo commit standing for uncommitted (or otherwise unnamed) changes
+    pub fn local_changes(message: &str) -> Self {
+        Self {
+            sha: String::new(),
+            short_sha: String::new(),
+            message: message.to_string(),
+            author: String::new(),
+            is_local_changes: true,
+        }
+    }
+}
+
 #[derive(Clone, PartialEq)]
 pub struct BranchInfo {
     pub name: String,
     pub is_current: bool,
     pub is_remote: bool,
 }
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiffAlgorithm {
+    #[default]
+    Myers,
+    Patience,
+    Lcs,
+}
+
+impl DiffAlgorithm {
+    pub fn next(self) -> Self {
+        match self {
+            DiffAlgorithm::Myers => DiffAlgorithm::Patience,
+            DiffAlgorithm::Patience => DiffAlgorithm::Lcs,
+            DiffAlgorithm::Lcs => DiffAlgorithm::Myers,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            DiffAlgorithm::Myers => "myers",
+            DiffAlgorithm::Patience => "patience",
+            DiffAlgorithm::Lcs => "lcs",
+        }
+    }
+
+    pub fn to_similar(self) -> Algorithm {
+        match self {
+            DiffAlgorithm::Myers => Algorithm::Myers,
+            DiffAlgorithm::Patience => Algorithm::Patience,
+            DiffAlgorithm::Lcs => Algorithm::Lcs,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WhitespaceMode {
+    /// Compare whitespace literally
+    #[default]
+    None,
+    /// Ignore all whitespace (like `git diff -w`)
+    IgnoreAll,
+    /// Ignore changes in the amount of whitespace (like `git diff -b`)
+    IgnoreAmount,
+    /// Ignore whitespace at end of line
+    IgnoreEol,
+    /// Ignore changes whose lines are all blank
+    IgnoreBlankLines,
+}
+
+impl WhitespaceMode {
+    pub fn next(self) -> Self {
+        match self {
+            WhitespaceMode::None => WhitespaceMode::IgnoreAll,
+            WhitespaceMode::IgnoreAll => WhitespaceMode::IgnoreAmount,
+            WhitespaceMode::IgnoreAmount => WhitespaceMode::IgnoreEol,
+            WhitespaceMode::IgnoreEol => WhitespaceMode::IgnoreBlankLines,
+            WhitespaceMode::IgnoreBlankLines => WhitespaceMode::None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            WhitespaceMode::None => "ws:exact",
+            WhitespaceMode::IgnoreAll => "ws:ignore-all",
+            WhitespaceMode::IgnoreAmount => "ws:ignore-amount",
+            WhitespaceMode::IgnoreEol => "ws:ignore-eol",
+            WhitespaceMode::IgnoreBlankLines => "ws:ignore-blank",
+        }
+    }
+
+    /// Key used to compare a line under this mode
+    pub fn normalize(self, line: &str) -> String {
+        match self {
+            WhitespaceMode::None | WhitespaceMode::IgnoreBlankLines => line.to_string(),
+            WhitespaceMode::IgnoreAll => line.chars().filter(|c| !c.is_whitespace()).collect(),
+            WhitespaceMode::IgnoreAmount => {
+                let mut key = String::with_capacity(line.len());
+                let mut in_whitespace = false;
+                for c in line.trim_end().chars() {
+                    if c.is_whitespace() {
+                        if !in_whitespace {
+                            key.push(' ');
+                        }
+                        in_whitespace = true;
+                    } else {
+                        key.push(c);
+                        in_whitespace = false;
+                    }
+                }
+                key
+            }
+            WhitespaceMode::IgnoreEol => line.trim_end().to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct DiffSettings {
+    pub algorithm: DiffAlgorithm,
+    pub whitespace: WhitespaceMode,
+}
+
+impl DiffSettings {
+    pub fn label(&self) -> String {
+        format!("{} {}", self.algorithm.label(), self.whitespace.label())
+    }
+}
@@ -1,51 +1,697 @@
-use crossterm::{cursor::MoveTo, execute};
+use crossterm::{
+    cursor::MoveTo,
+    queue,
+    terminal::{BeginSynchronizedUpdate, EndSynchronizedUpdate},
+};
 use similar::ChangeTag;
+use std::collections::HashMap;
 use std::io::{self, Write};
+use std::ops::{Range, RangeInclusive};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-use crate::theme;
+use crate::config::Layout;
+use crate::text;
+use crate::theme::{self, Color, Paint, Theme};
 use crate::types::{BranchInfo, CommitInfo, DiffHunk, DiffLine, FileChange};
 
+/// Below this many columns the commit and file lists fold away into a drawer
+const NARROW_WIDTH: u16 = 80;
+/// Smallest terminal laid out; anything smaller shows the top-left part of this size
+const MIN_WIDTH: u16 = 20;
+const MIN_HEIGHT: u16 = 8;
+/// Rows the diff keeps when the lists are stacked above it: header and three lines
+const MIN_DIFF_HEIGHT: u16 = 4;
+/// Columns of a diff line before its content: line numbers, border and sign
+const GUTTER_WIDTH: usize = 14;
+
+/// How the content of diff lines is turned into text
+#[derive(Clone, Copy)]
+pub struct LineFormat {
+    /// Columns between tab stops
+    pub tab_width: usize,
+    /// Draw tabs, trailing spaces and carriage returns as glyphs, mark missing newlines at
+    /// the end of files and whitespace errors in added lines
+    pub show_whitespace: bool,
+}
+
+impl Default for LineFormat {
+    fn default() -> Self {
+        Self { tab_width: 4, show_whitespace: false }
+    }
+}
+
+/// Part of the screen a panel is drawn in
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Rect {
+    pub fn contains(&self, column: u16, row: u16) -> bool {
+        (self.x..self.x + self.width).contains(&column) && (self.y..self.y + self.height).contains(&row)
+    }
+}
+
+/// Panel border that can be dragged or moved with keys
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Boundary {
+    /// Between the lists and the diff
+    Lists,
+    /// Between the commit list and the file list
+    Commits,
+}
+
+/// One character cell of a frame
+#[derive(Clone, Copy, PartialEq)]
+struct Cell {
+    symbol: char,
+    /// Index into `Screen::clusters` when combining marks or joined characters follow
+    /// `symbol`; 0 for none
+    cluster: usize,
+    /// Index into `Screen::styles`
+    style: usize,
+}
+
+const BLANK: Cell = Cell { symbol: ' ', cluster: 0, style: 0 };
+/// Right half of a double-width character, which the cell before it draws
+const WIDE_TAIL: char = '\0';
+const ZERO_WIDTH_JOINER: char = '\u{200d}';
+
+/// Where the escape sequence parser is between writes
+#[derive(Clone, Copy, PartialEq)]
+enum ParseState {
+    Ground,
+    Escape,
+    Csi,
+}
+
+/// Colors and attributes in effect: what the SGR sequences since the last reset add up to
+#[derive(Clone, Default, PartialEq)]
+struct Sgr {
+    /// Parameters selecting the foreground, e.g. `31` or `38;5;196`
+    fg: Option<String>,
+    bg: Option<String>,
+    /// Bit n set for attribute n: bold, dim, italic, underline, blink, (unused), reverse,
+    /// hidden, strikethrough
+    attributes: u16,
+}
+
+impl Sgr {
+    /// Apply the parameters of one SGR sequence
+    fn apply(&mut self, sequence: &str) {
+        let mut params = sequence.split(';');
+        while let Some(param) = params.next() {
+            let mut parts = param.split(':');
+            let code = parts.next().and_then(|code| code.parse::<u16>().ok()).unwrap_or(0);
+            match code {
+                0 => *self = Self::default(),
+                // `4:0` turns underlines off, `4:3` and the like pick a style
+                4 if parts.next() == Some("0") => self.attributes &= !(1 << 4),
+                1..=9 => self.attributes |= 1 << code,
+                22 => self.attributes &= !(1 << 1 | 1 << 2),
+                23..=29 => self.attributes &= !(1 << (code - 20)),
+                30..=37 | 90..=97 => self.fg = Some(code.to_string()),
+                40..=47 | 100..=107 => self.bg = Some(code.to_string()),
+                39 => self.fg = None,
+                49 => self.bg = None,
+                38 | 48 => {
+                    // `38:5:n` carries its arguments along; `38;5;n` takes the next parameters
+                    let color = if param.contains(':') {
+                        param.to_string()
+                    } else {
+                        let count = match params.next() {
+                            Some("5") => 1,
+                            Some("2") => 3,
+                            _ => continue,
+                        };
+                        let arguments: Vec<&str> = params.by_ref().take(count).collect();
+                        format!("{};{};{}", code, if count == 1 { 5 } else { 2 }, arguments.join(";"))
+                    };
+                    if code == 38 {
+                        self.fg = Some(color);
+                    } else {
+                        self.bg = Some(color);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// One sequence setting this state after a reset; empty for the terminal default
+    fn sequence(&self) -> String {
+        let attributes = (1..=9).filter(|n| self.attributes & (1 << n) != 0).map(|n: u16| n.to_string());
+        let params: Vec<String> = attributes.chain(self.fg.clone()).chain(self.bg.clone()).collect();
+        if params.is_empty() {
+            String::new()
+        } else {
+            format!("\x1b[{}m", params.join(";"))
+        }
+    }
+}
+
+/// Back buffer the panels draw into. It takes the same cursor moves, colors and text that
+/// would go to the terminal, keeps the frame in memory and writes only the cells that
+/// changed since the previous frame, in a single write.
+pub struct Screen {
+    width: u16,
+    height: u16,
+    cells: Vec<Cell>,
+    /// What the terminal shows; `None` when it is unknown and everything has to be written
+    shown: Option<Vec<Cell>>,
+    /// SGR sequence of each distinct style, by style index; 0 is the terminal default
+    styles: Vec<String>,
+    style_ids: HashMap<String, usize>,
+    /// Grapheme clusters of more than one character, by cluster index; 0 is unused
+    clusters: Vec<String>,
+    cluster_ids: HashMap<String, usize>,
+    /// The last character written was a zero-width joiner, so the next one joins its cell
+    joining: bool,
+    cursor: (u16, u16),
+    style: Sgr,
+    style_id: usize,
+    state: ParseState,
+    /// Parameters of the escape sequence being read
+    sequence: String,
+    /// Start of a UTF-8 character split across writes
+    partial: Vec<u8>,
+}
+
+impl Screen {
+    pub fn new(width: u16, height: u16) -> Self {
+        let mut screen = Self {
+            width: 0,
+            height: 0,
+            cells: Vec::new(),
+            shown: None,
+            styles: vec![String::new()],
+            style_ids: HashMap::from([(String::new(), 0)]),
+            clusters: vec![String::new()],
+            cluster_ids: HashMap::new(),
+            joining: false,
+            cursor: (0, 0),
+            style: Sgr::default(),
+            style_id: 0,
+            state: ParseState::Ground,
+            sequence: String::new(),
+            partial: Vec::new(),
+        };
+        screen.resize(width, height);
+        screen
+    }
+
+    /// Start over at a new size; the next frame is written in full
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.width = width;
+        self.height = height;
+        self.cells = vec![BLANK; width as usize * height as usize];
+        self.shown = None;
+    }
+
+    /// Something else wrote to the terminal: write the next frame in full
+    pub fn invalidate(&mut self) {
+        self.shown = None;
+    }
+
+    /// Blank the frame before drawing the next one
+    pub fn clear(&mut self) {
+        self.cells.fill(BLANK);
+        self.cursor = (0, 0);
+        self.style = Sgr::default();
+        self.style_id = 0;
+        self.state = ParseState::Ground;
+        self.partial.clear();
+        self.joining = false;
+    }
+
+    /// Write the cells that differ from the previous frame to `out`
+    pub fn render(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let mut buffer = Vec::new();
+        let mut cursor = None;
+        let mut style = None;
+        for (index, cell) in self.cells.iter().enumerate() {
+            // Tails change only with the character before them, which draws both cells
+            if cell.symbol == WIDE_TAIL || self.shown.as_ref().is_some_and(|shown| shown[index] == *cell) {
+                continue;
+            }
+            let position = ((index % self.width as usize) as u16, (index / self.width as usize) as u16);
+            if cursor != Some(position) {
+                queue!(buffer, MoveTo(position.0, position.1))?;
+            }
+            if style != Some(cell.style) {
+                write!(buffer, "{}{}", theme::RESET, self.styles[cell.style])?;
+                style = Some(cell.style);
+            }
+            write!(buffer, "{}{}", cell.symbol, self.clusters[cell.cluster])?;
+            let wide = self.cells.get(index + 1).is_some_and(|next| next.symbol == WIDE_TAIL);
+            cursor = Some((position.0 + if wide { 2 } else { 1 }, position.1));
+        }
+        self.shown = Some(self.cells.clone());
+        if buffer.is_empty() {
+            return Ok(());
+        }
+
+        // Terminals that don't know synchronized output ignore the mode
+        queue!(out, BeginSynchronizedUpdate)?;
+        out.write_all(&buffer)?;
+        write!(out, "{}", theme::RESET)?;
+        queue!(out, EndSynchronizedUpdate)?;
+        out.flush()
+    }
+
+    fn put(&mut self, symbol: char) {
+        let joining = std::mem::replace(&mut self.joining, symbol == ZERO_WIDTH_JOINER);
+        let columns = match symbol.width() {
+            Some(columns) => columns,
+            // Control characters take no room
+            None => return,
+        };
+        if columns == 0 || joining {
+            self.combine(symbol);
+            return;
+        }
+
+        let (x, y) = self.cursor;
+        self.cursor.0 = x.saturating_add(columns as u16);
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let index = y as usize * self.width as usize + x as usize;
+        if columns == 2 && x + 1 >= self.width {
+            // Half a wide character can't be shown; the terminal would wrap it
+            self.overwrite(index, Cell { symbol: ' ', ..BLANK });
+            return;
+        }
+        self.overwrite(index, Cell { symbol, cluster: 0, style: self.style_id });
+        if columns == 2 {
+            self.overwrite(index + 1, Cell { symbol: WIDE_TAIL, cluster: 0, style: self.style_id });
+        }
+    }
+
+    /// Set a cell, blanking what is left of a wide character it covers half of
+    fn overwrite(&mut self, index: usize, cell: Cell) {
+        let row_start = index - index % self.width as usize;
+        if self.cells[index].symbol == WIDE_TAIL && index > row_start && cell.symbol != WIDE_TAIL {
+            self.cells[index - 1].symbol = ' ';
+            self.cells[index - 1].cluster = 0;
+        }
+        if self.cells.get(index + 1).is_some_and(|next| next.symbol == WIDE_TAIL) {
+            self.cells[index + 1].symbol = ' ';
+        }
+        self.cells[index] = cell;
+    }
+
+    /// Add a combining mark or joined character to the cell written last
+    fn combine(&mut self, symbol: char) {
+        let (x, y) = self.cursor;
+        if x == 0 || x > self.width || y >= self.height {
+            return;
+        }
+        let mut index = y as usize * self.width as usize + x as usize - 1;
+        if self.cells[index].symbol == WIDE_TAIL && x > 1 {
+            index -= 1;
+        }
+        let cell = &mut self.cells[index];
+        if cell.symbol == WIDE_TAIL {
+            return;
+        }
+        let mut cluster = self.clusters[cell.cluster].clone();
+        cluster.push(symbol);
+        cell.cluster = match self.cluster_ids.get(&cluster) {
+            Some(id) => *id,
+            None => {
+                self.clusters.push(cluster.clone());
+                self.cluster_ids.insert(cluster, self.clusters.len() - 1);
+                self.clusters.len() - 1
+            }
+        };
+    }
+
+    /// Act on a complete escape sequence; only cursor moves and colors are drawn with
+    fn apply_sequence(&mut self, command: u8) {
+        match command {
+            b'm' => {
+                self.style.apply(&self.sequence);
+                let sequence = self.style.sequence();
+                self.style_id = match self.style_ids.get(&sequence) {
+                    Some(id) => *id,
+                    None => {
+                        self.styles.push(sequence.clone());
+                        self.style_ids.insert(sequence, self.styles.len() - 1);
+                        self.styles.len() - 1
+                    }
+                };
+            }
+            b'H' => {
+                let mut numbers = self.sequence.split(';').map(|n| n.parse::<u16>().unwrap_or(1).max(1));
+                let row = numbers.next().unwrap_or(1);
+                let column = numbers.next().unwrap_or(1);
+                self.cursor = (column - 1, row - 1);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Write for Screen {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        for &byte in bytes {
+            match self.state {
+                ParseState::Ground if byte == 0x1b => self.state = ParseState::Escape,
+                ParseState::Ground if byte < 0x20 => {}
+                ParseState::Ground => {
+                    self.partial.push(byte);
+                    match std::str::from_utf8(&self.partial) {
+                        Ok(text) => {
+                            let symbol = text.chars().next().unwrap_or(' ');
+                            self.partial.clear();
+                            self.put(symbol);
+                        }
+                        // More bytes of this character to come
+                        Err(e) if e.error_len().is_none() => {}
+                        Err(_) => {
+                            self.partial.clear();
+                            self.put('\u{fffd}');
+                        }
+                    }
+                }
+                ParseState::Escape if byte == b'[' => {
+                    self.sequence.clear();
+                    self.state = ParseState::Csi;
+                }
+                ParseState::Escape => self.state = ParseState::Ground,
+                ParseState::Csi if (0x40..=0x7e).contains(&byte) => {
+                    self.apply_sequence(byte);
+                    self.state = ParseState::Ground;
+                }
+                ParseState::Csi => self.sequence.push(byte as char),
+            }
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 pub struct Ui {
     pub term_width: u16,
     pub term_height: u16,
-    pub left_panel_width: u16,
-    pub commit_panel_height: u16,
+    /// Empty while the lists are hidden
+    pub commits_area: Rect,
+    pub files_area: Rect,
+    pub diff_area: Rect,
+    /// The line between the lists and the diff: a column beside it, or a row when stacked
+    pub separator: Rect,
+    pub theme: Theme,
+    layout: Layout,
+    /// Whether the lists are shown on a narrow terminal
+    drawer_open: bool,
+    /// Diff on the whole screen
+    zoomed: bool,
+    line_format: LineFormat,
+    /// Long diff lines continue on the next row instead of being cut off
+    wrap: bool,
 }
 
 impl Ui {
-    pub fn new() -> Self {
+    pub fn new(theme: Theme, layout: Layout, line_format: LineFormat) -> Self {
         let (width, height) = crossterm::terminal::size().unwrap_or((120, 40));
-        let left_panel_width = (width / 4).max(25).min(50);
-        let commit_panel_height = (height / 4).max(6).min(12);
-        Self {
-            term_width: width,
-            term_height: height,
-            left_panel_width,
-            commit_panel_height,
+        let mut ui = Self {
+            term_width: 0,
+            term_height: 0,
+            commits_area: Rect::default(),
+            files_area: Rect::default(),
+            diff_area: Rect::default(),
+            separator: Rect::default(),
+            theme,
+            layout,
+            drawer_open: false,
+            zoomed: false,
+            line_format,
+            wrap: false,
+        };
+        ui.resize(width, height);
+        ui
+    }
+
+    /// Lay the panels out for a terminal of `width` x `height`
+    pub fn resize(&mut self, width: u16, height: u16) {
+        let width = width.max(MIN_WIDTH);
+        let height = height.max(MIN_HEIGHT);
+        self.term_width = width;
+        self.term_height = height;
+        // Everything above the status bar
+        let body = height - 1;
+
+        self.commits_area = Rect::default();
+        self.files_area = Rect::default();
+        self.separator = Rect::default();
+        self.diff_area = Rect { x: 0, y: 0, width, height: body };
+        if self.zoomed || self.uses_drawer() && !self.drawer_open {
+            return;
+        }
+
+        if self.layout.stacked {
+            let lists_max = body.saturating_sub(MIN_DIFF_HEIGHT + 1);
+            if lists_max < 4 {
+                // Too short for anything but the diff
+                self.diff_area = Rect { x: 0, y: 0, width, height: body };
+                return;
+            }
+            let commits = self.layout.commit_panel_height.unwrap_or((height / 5).clamp(4, 8)).clamp(2, lists_max - 2);
+            let files = self.layout.file_panel_height.unwrap_or((height / 5).clamp(4, 8)).clamp(2, lists_max - commits);
+            self.commits_area = Rect { x: 0, y: 0, width, height: commits };
+            self.files_area = Rect { x: 0, y: commits, width, height: files };
+            self.separator = Rect { x: 0, y: commits + files, width, height: 1 };
+            let diff_y = commits + files + 1;
+            self.diff_area = Rect { x: 0, y: diff_y, width, height: body - diff_y };
+        } else {
+            // The drawer takes half of a narrow terminal, leaving the rest of the diff in view
+            let default_width = if self.uses_drawer() { (width / 2).max(25) } else { (width / 4).clamp(25, 50) };
+            let lists = self
+                .layout
+                .left_panel_width
+                .unwrap_or(default_width)
+                .clamp(10, width.saturating_sub(20).max(10));
+            let commits = self
+                .layout
+                .commit_panel_height
+                .unwrap_or((height / 4).clamp(6, 12))
+                .clamp(2, height.saturating_sub(4).max(2));
+            self.commits_area = Rect { x: 0, y: 0, width: lists, height: commits };
+            self.files_area = Rect { x: 0, y: commits, width: lists, height: body - commits };
+            self.separator = Rect { x: lists, y: 0, width: 1, height: body };
+            self.diff_area = Rect { x: lists + 1, y: 0, width: width - lists - 1, height: body };
+        }
+    }
+
+    /// Whether the commit and file lists live in a drawer instead of beside the diff
+    pub fn uses_drawer(&self) -> bool {
+        self.term_width < NARROW_WIDTH && !self.layout.stacked
+    }
+
+    pub fn is_drawer_open(&self) -> bool {
+        self.uses_drawer() && self.drawer_open
+    }
+
+    pub fn set_drawer_open(&mut self, open: bool) {
+        self.drawer_open = open;
+        self.resize(self.term_width, self.term_height);
+    }
+
+    pub fn is_zoomed(&self) -> bool {
+        self.zoomed
+    }
+
+    pub fn set_zoomed(&mut self, zoomed: bool) {
+        self.zoomed = zoomed;
+        self.resize(self.term_width, self.term_height);
+    }
+
+    pub fn is_wrapping(&self) -> bool {
+        self.wrap
+    }
+
+    pub fn set_wrap(&mut self, wrap: bool) {
+        self.wrap = wrap;
+    }
+
+    /// Columns of diff line content that fit beside the gutter
+    pub fn diff_content_width(&self) -> usize {
+        (self.diff_area.width as usize).saturating_sub(GUTTER_WIDTH).max(1)
+    }
+
+    pub fn is_showing_whitespace(&self) -> bool {
+        self.line_format.show_whitespace
+    }
+
+    pub fn set_show_whitespace(&mut self, show: bool) {
+        self.line_format.show_whitespace = show;
+    }
+
+    /// Columns diff lines wrap at, `None` when they are cut off instead
+    pub fn wrap_width(&self) -> Option<usize> {
+        self.wrap.then(|| self.diff_content_width())
+    }
+
+    /// Columns `line` takes with its tabs expanded
+    pub fn line_width(&self, line: &DiffLine) -> usize {
+        text::width(&self.line_text(line))
+    }
+
+    /// Content of `line` as it is drawn, without colors
+    fn line_text(&self, line: &DiffLine) -> String {
+        content_spans(line, self.line_format, None).into_iter().map(|(_, text)| text).collect()
+    }
+
+    /// Rows `line` takes when wrapped at `wrap_width` columns
+    pub fn line_rows(&self, line: &DiffLine, wrap_width: Option<usize>) -> usize {
+        match wrap_width {
+            Some(_) => self.row_starts(line, wrap_width, 0).len(),
+            None => 1,
+        }
+    }
+
+    /// Content column each row of `line` starts at: one row from `h_scroll`, or one
+    /// per `wrap_width` columns when wrapping
+    fn row_starts(&self, line: &DiffLine, wrap_width: Option<usize>, h_scroll: usize) -> Vec<usize> {
+        match wrap_width {
+            Some(columns) => text::wrap(&self.line_text(line), columns),
+            None => vec![h_scroll],
         }
     }
 
+    /// Panel sizes and arrangement as arranged so far, to be saved
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Switch between lists beside the diff and lists above it
+    pub fn toggle_stacked(&mut self) {
+        self.layout.stacked = !self.layout.stacked;
+        // Sizes along the other axis don't carry over
+        self.layout.commit_panel_height = None;
+        self.resize(self.term_width, self.term_height);
+    }
+
+    /// Move `boundary` by `delta` cells: right or down grows the panel before it
+    pub fn move_boundary(&mut self, boundary: Boundary, delta: i32) {
+        if !self.shows_lists() {
+            return;
+        }
+        match (boundary, self.layout.stacked) {
+            (Boundary::Lists, false) => {
+                self.layout.left_panel_width = Some(offset(self.commits_area.width, delta));
+            }
+            (Boundary::Lists, true) => {
+                self.layout.file_panel_height = Some(offset(self.files_area.height, delta));
+            }
+            (Boundary::Commits, _) => {
+                let commits = offset(self.commits_area.height, delta);
+                if self.layout.stacked {
+                    // The file list gives up or takes the rows, keeping the diff where it is
+                    let files = self.files_area.height as i32 - (commits as i32 - self.commits_area.height as i32);
+                    if files < 2 {
+                        return;
+                    }
+                    self.layout.file_panel_height = Some(files as u16);
+                }
+                self.layout.commit_panel_height = Some(commits);
+            }
+        }
+        self.resize(self.term_width, self.term_height);
+        // Keep what is on screen, so the next move starts from the clamped size
+        self.layout.commit_panel_height = self.layout.commit_panel_height.map(|_| self.commits_area.height);
+        if self.layout.stacked {
+            self.layout.file_panel_height = self.layout.file_panel_height.map(|_| self.files_area.height);
+        } else {
+            self.layout.left_panel_width = self.layout.left_panel_width.map(|_| self.commits_area.width);
+        }
+    }
+
+    /// Move `boundary` so it lies at the given screen position, as when dragged there
+    pub fn drag_boundary(&mut self, boundary: Boundary, column: u16, row: u16) {
+        let delta = match (boundary, self.layout.stacked) {
+            (Boundary::Lists, false) => column as i32 - self.separator.x as i32,
+            (Boundary::Lists, true) => row as i32 - self.separator.y as i32,
+            (Boundary::Commits, _) => row as i32 - self.files_area.y as i32,
+        };
+        if delta != 0 {
+            self.move_boundary(boundary, delta);
+        }
+    }
+
+    /// The boundary at a screen position, for starting a drag
+    pub fn boundary_at(&self, column: u16, row: u16) -> Option<Boundary> {
+        if self.separator.contains(column, row) {
+            Some(Boundary::Lists)
+        } else if self.shows_lists() && row == self.files_area.y && self.files_area.contains(column, row) {
+            // The file list's header row
+            Some(Boundary::Commits)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the commit and file lists are on screen
+    pub fn shows_lists(&self) -> bool {
+        self.commits_area.height > 0
+    }
+
+    /// Commits that fit below the commit list's header
+    pub fn commit_rows(&self) -> usize {
+        self.commits_area.height.saturating_sub(1) as usize
+    }
+
+    /// Files that fit below the file list's header
+    pub fn file_rows(&self) -> usize {
+        self.files_area.height.saturating_sub(1) as usize
+    }
+
+    /// Diff lines that fit below the diff header
+    pub fn diff_rows(&self) -> usize {
+        self.diff_area.height.saturating_sub(2) as usize
+    }
+
+    /// Where the branch list is drawn, header included
+    fn branch_panel(&self) -> Rect {
+        let width = self.term_width / 2;
+        Rect { x: (self.term_width - width) / 2, y: 1, width, height: self.term_height - 2 }
+    }
+
+    /// Index of the branch shown at `column`, `row` of the branch list
+    pub fn branch_at(&self, column: u16, row: u16, scroll_offset: usize) -> Option<usize> {
+        let panel = self.branch_panel();
+        (panel.contains(column, row) && row > panel.y).then(|| (row - panel.y - 1) as usize + scroll_offset)
+    }
+
     pub fn draw_branch_panel(
         &self,
-        stdout: &mut io::Stdout,
+        screen: &mut Screen,
         branches: &[BranchInfo],
         selected: usize,
         scroll_offset: usize,
     ) -> io::Result<()> {
-        let panel_width = (self.term_width / 2) as usize;
-        let panel_height = (self.term_height - 2) as usize;
-        let start_x = (self.term_width - panel_width as u16) / 2;
-        let start_y = 1u16;
+        let panel = self.branch_panel();
+        let panel_width = panel.width as usize;
+        let panel_height = panel.height as usize;
+        let (start_x, start_y) = (panel.x, panel.y);
 
         // Header
-        execute!(stdout, MoveTo(start_x, start_y))?;
+        queue!(screen, MoveTo(start_x, start_y))?;
         let header = " Select Branch (↑↓ navigate, Enter select, Esc cancel) ";
-        let header_padded = format!("{:<width$}", header, width = panel_width);
+        let header_padded = text::fit(header, panel_width);
         write!(
-            stdout,
+            screen,
             "{}{}{}{}",
-            theme::BG_HEADER,
-            theme::FG_DEFAULT,
+            self.theme.bg_header,
+            self.theme.fg_default,
             header_padded,
             theme::RESET
         )?;
@@ -58,39 +704,33 @@ impl Ui {
             }
 
             let branch = &branches[branch_idx];
-            execute!(stdout, MoveTo(start_x, start_y + (row + 1) as u16))?;
+            queue!(screen, MoveTo(start_x, start_y + (row + 1) as u16))?;
 
             let bg = if branch_idx == selected {
-                theme::BG_SELECTED
+                self.theme.bg_selected
             } else {
-                theme::BG_PANEL
+                self.theme.bg_panel
             };
 
             let (icon, color) = if branch.is_current {
-                ("●", theme::FG_ADDED)
+                ("●", self.theme.fg_added)
             } else {
-                ("○", theme::FG_DIM)
+                ("○", self.theme.fg_dim)
             };
 
             let max_name_len = panel_width.saturating_sub(4);
-            let display_name = if branch.name.len() > max_name_len {
-                format!("{}…", &branch.name[..max_name_len.saturating_sub(1)])
-            } else {
-                branch.name.clone()
-            };
-
-            let line = format!(" {} {:<width$}", icon, display_name, width = max_name_len);
-            write!(stdout, "{}{}{}{}", bg, color, line, theme::RESET)?;
+            let line = format!(" {} {}", icon, text::fit(&branch.name, max_name_len));
+            write!(screen, "{}{}{}{}", bg, color, line, theme::RESET)?;
         }
 
         // Fill remaining space
-        let displayed = (branches.len() - scroll_offset).min(visible_count);
+        let displayed = branches.len().saturating_sub(scroll_offset).min(visible_count);
         for i in displayed + 1..panel_height {
-            execute!(stdout, MoveTo(start_x, start_y + i as u16))?;
+            queue!(screen, MoveTo(start_x, start_y + i as u16))?;
             write!(
-                stdout,
+                screen,
                 "{}{:width$}{}",
-                theme::BG_PANEL,
+                self.theme.bg_panel,
                 "",
                 theme::RESET,
                 width = panel_width
@@ -100,33 +740,56 @@ impl Ui {
         Ok(())
     }
 
+    /// Key bindings of the active keymap, one action per row
+    pub fn draw_help(&self, screen: &mut Screen, entries: &[(String, &str)]) -> io::Result<()> {
+        let keys_width = entries.iter().map(|(keys, _)| text::width(keys)).max().unwrap_or(0).min(24);
+        let content_width = entries
+            .iter()
+            .map(|(_, description)| keys_width + text::width(description) + 5)
+            .max()
+            .unwrap_or(0);
+        let panel_width = content_width.max(30).min(self.term_width as usize);
+        let panel_height = (entries.len() + 1).min(self.term_height.saturating_sub(2) as usize);
+        let start_x = (self.term_width - panel_width as u16) / 2;
+        let start_y = 1u16;
+
+        queue!(screen, MoveTo(start_x, start_y))?;
+        let header = " Keys (any key to close) ";
+        let header = text::fit(header, panel_width);
+        write!(screen, "{}{}{}{}", self.theme.bg_header, self.theme.fg_default, header, theme::RESET)?;
+
+        for (row, (keys, description)) in entries.iter().take(panel_height.saturating_sub(1)).enumerate() {
+            queue!(screen, MoveTo(start_x, start_y + (row + 1) as u16))?;
+            let line = format!("  {}   {}", text::fit(keys, keys_width), description);
+            let line = text::fit(&line, panel_width);
+            write!(screen, "{}{}{}{}", self.theme.bg_panel, self.theme.fg_default, line, theme::RESET)?;
+        }
+        Ok(())
+    }
+
     pub fn draw_commit_panel(
         &self,
-        stdout: &mut io::Stdout,
+        screen: &mut Screen,
         commits: &[CommitInfo],
         selected: usize,
         scroll_offset: usize,
         current_branch: &str,
     ) -> io::Result<()> {
-        let panel_width = self.left_panel_width as usize;
-        let panel_height = self.commit_panel_height as usize;
+        let area = self.commits_area;
+        let panel_width = area.width as usize;
+        let panel_height = area.height as usize;
         let visible_count = panel_height - 1;
 
         // Header with branch name
-        execute!(stdout, MoveTo(0, 0))?;
+        queue!(screen, MoveTo(area.x, area.y))?;
         let max_branch_len = panel_width.saturating_sub(4);
-        let branch_display = if current_branch.len() > max_branch_len {
-            format!("{}…", &current_branch[..max_branch_len.saturating_sub(1)])
-        } else {
-            current_branch.to_string()
-        };
-        let header = format!(" {} ", branch_display);
-        let header_padded = format!("{:<width$}", header, width = panel_width);
+        let header = format!(" {} ", text::truncate(current_branch, max_branch_len));
+        let header_padded = text::fit(&header, panel_width);
         write!(
-            stdout,
+            screen,
             "{}{}{}{}",
-            theme::BG_HEADER,
-            theme::FG_ADDED,
+            self.theme.bg_header,
+            self.theme.fg_added,
             header_padded,
             theme::RESET
         )?;
@@ -138,39 +801,33 @@ impl Ui {
             }
 
             let commit = &commits[commit_idx];
-            execute!(stdout, MoveTo(0, (row + 1) as u16))?;
+            queue!(screen, MoveTo(area.x, area.y + (row + 1) as u16))?;
 
             let bg = if commit_idx == selected {
-                theme::BG_SELECTED
+                self.theme.bg_selected
             } else {
-                theme::BG_PANEL
+                self.theme.bg_panel
             };
 
             let (icon, color) = if commit.is_local_changes {
-                ("●", theme::FG_ADDED)
+                ("●", self.theme.fg_added)
             } else {
-                ("○", theme::FG_DIM)
+                ("○", self.theme.fg_dim)
             };
 
             let max_msg_len = panel_width.saturating_sub(4);
-            let display_msg = if commit.message.len() > max_msg_len {
-                format!("{}…", &commit.message[..max_msg_len.saturating_sub(1)])
-            } else {
-                commit.message.clone()
-            };
-
-            let line = format!(" {} {:<width$}", icon, display_msg, width = max_msg_len);
-            write!(stdout, "{}{}{}{}", bg, color, line, theme::RESET)?;
+            let line = format!(" {} {}", icon, text::fit(&text::printable(&commit.message), max_msg_len));
+            write!(screen, "{}{}{}{}", bg, color, line, theme::RESET)?;
         }
 
         // Fill remaining space
-        let displayed = (commits.len() - scroll_offset).min(visible_count);
+        let displayed = commits.len().saturating_sub(scroll_offset).min(visible_count);
         for i in displayed + 1..panel_height {
-            execute!(stdout, MoveTo(0, i as u16))?;
+            queue!(screen, MoveTo(area.x, area.y + i as u16))?;
             write!(
-                stdout,
+                screen,
                 "{}{:width$}{}",
-                theme::BG_PANEL,
+                self.theme.bg_panel,
                 "",
                 theme::RESET,
                 width = panel_width
@@ -182,24 +839,25 @@ impl Ui {
 
     pub fn draw_file_panel(
         &self,
-        stdout: &mut io::Stdout,
+        screen: &mut Screen,
         files: &[FileChange],
         selected: usize,
         scroll_offset: usize,
     ) -> io::Result<()> {
-        let panel_width = self.left_panel_width as usize;
-        let start_row = self.commit_panel_height;
-        let available_height = (self.term_height - start_row - 1) as usize;
+        let area = self.files_area;
+        let panel_width = area.width as usize;
+        let start_row = area.y;
+        let available_height = area.height as usize;
 
         // Header
-        execute!(stdout, MoveTo(0, start_row))?;
+        queue!(screen, MoveTo(area.x, start_row))?;
         let header = format!(" Files ({}) ", files.len());
-        let header_padded = format!("{:<width$}", header, width = panel_width);
+        let header_padded = text::fit(&header, panel_width);
         write!(
-            stdout,
+            screen,
             "{}{}{}{}",
-            theme::BG_HEADER,
-            theme::FG_DEFAULT,
+            self.theme.bg_header,
+            self.theme.fg_default,
             header_padded,
             theme::RESET
         )?;
@@ -211,39 +869,33 @@ impl Ui {
             }
 
             let file = &files[file_idx];
-            execute!(stdout, MoveTo(0, start_row + (row + 1) as u16))?;
+            queue!(screen, MoveTo(area.x, start_row + (row + 1) as u16))?;
 
             let (icon, color) = match file.status.as_str() {
-                "added" => ("+", theme::FG_ADDED),
-                "deleted" => ("-", theme::FG_REMOVED),
-                _ => ("~", theme::FG_HEADER),
+                "added" => ("+", self.theme.fg_added),
+                "deleted" => ("-", self.theme.fg_removed),
+                _ => ("~", self.theme.fg_header),
             };
 
             let bg = if file_idx == selected {
-                theme::BG_SELECTED
+                self.theme.bg_selected
             } else {
-                theme::BG_PANEL
+                self.theme.bg_panel
             };
 
             let max_name_len = panel_width.saturating_sub(4);
-            let display_name = if file.path.len() > max_name_len {
-                format!("…{}", &file.path[file.path.len() - max_name_len + 1..])
-            } else {
-                file.path.clone()
-            };
-
-            let line = format!(" {} {:<width$}", icon, display_name, width = max_name_len);
-            write!(stdout, "{}{}{}{}", bg, color, line, theme::RESET)?;
+            let line = format!(" {} {}", icon, text::pad(&text::truncate_start(&text::printable(&file.path), max_name_len), max_name_len));
+            write!(screen, "{}{}{}{}", bg, color, line, theme::RESET)?;
         }
 
         // Fill remaining space
-        let displayed = (files.len() - scroll_offset).min(available_height - 1);
+        let displayed = files.len().saturating_sub(scroll_offset).min(available_height - 1);
         for i in displayed + 1..available_height {
-            execute!(stdout, MoveTo(0, start_row + i as u16))?;
+            queue!(screen, MoveTo(area.x, start_row + i as u16))?;
             write!(
-                stdout,
+                screen,
                 "{}{:width$}{}",
-                theme::BG_PANEL,
+                self.theme.bg_panel,
                 "",
                 theme::RESET,
                 width = panel_width
@@ -253,111 +905,133 @@ impl Ui {
         Ok(())
     }
 
-    pub fn draw_separator(&self, stdout: &mut io::Stdout) -> io::Result<()> {
-        let x = self.left_panel_width;
-        for y in 0..self.term_height - 1 {
-            execute!(stdout, MoveTo(x, y))?;
+    pub fn draw_separator(&self, screen: &mut Screen) -> io::Result<()> {
+        let area = self.separator;
+        if area.height == 1 {
+            // Stacked: a row between the lists and the diff
+            queue!(screen, MoveTo(area.x, area.y))?;
+            let line = "─".repeat(area.width as usize);
+            return write!(screen, "{}{}{}{}", self.theme.bg_dark, self.theme.fg_separator, line, theme::RESET);
+        }
+        for y in area.y..area.y + area.height {
+            queue!(screen, MoveTo(area.x, y))?;
             write!(
-                stdout,
+                screen,
                 "{}{}│{}",
-                theme::BG_DARK,
-                theme::FG_SEPARATOR,
+                self.theme.bg_dark,
+                self.theme.fg_separator,
                 theme::RESET
             )?;
         }
         Ok(())
     }
 
+    /// `selection` holds the indices of the selected separators and lines, counted
+    /// through the hunks in order with each hunk's separator before its lines
+    #[allow(clippy::too_many_arguments)]
     pub fn draw_diff_panel(
         &self,
-        stdout: &mut io::Stdout,
+        screen: &mut Screen,
         file_name: &str,
         hunks: &[DiffHunk],
         scroll_offset: usize,
+        h_scroll: usize,
+        selection: Option<RangeInclusive<usize>>,
+        loading: bool,
     ) -> io::Result<()> {
-        let start_x = self.left_panel_width + 1;
-        let diff_width = (self.term_width - start_x) as usize;
+        let area = self.diff_area;
+        let start_x = area.x;
+        let diff_width = area.width as usize;
 
         // Header
-        execute!(stdout, MoveTo(start_x, 0))?;
-        let header = format!(" {} ", file_name);
-        let header_padded = format!("{:<width$}", header, width = diff_width);
+        queue!(screen, MoveTo(start_x, area.y))?;
+        let header = format!(" {} ", text::printable(file_name));
+        let header_padded = text::fit(&header, diff_width);
         write!(
-            stdout,
+            screen,
             "{}{}{}{}",
-            theme::BG_HEADER,
-            theme::FG_HEADER,
+            self.theme.bg_header,
+            self.theme.fg_header,
             header_padded,
             theme::RESET
         )?;
 
         if hunks.is_empty() {
-            execute!(stdout, MoveTo(start_x, 2))?;
+            queue!(screen, MoveTo(start_x, area.y + 2))?;
             write!(
-                stdout,
-                "{}{}  No changes{}",
-                theme::BG_DARK,
-                theme::FG_DIM,
+                screen,
+                "{}{}  {}{}",
+                self.theme.bg_diff,
+                self.theme.fg_dim,
+                if loading { "Loading…" } else { "No changes" },
                 theme::RESET
             )?;
             return Ok(());
         }
 
-        let mut row = 1u16;
-        let max_rows = self.term_height - 2;
-        let mut line_idx = 0usize;
+        let mut row = area.y + 1;
+        let max_rows = area.y + area.height - 1;
+        let wrap_width = self.wrap_width();
+        // Rows of the diff before the current one, counted like `scroll_offset`
+        let mut position = 0usize;
+        // Separators and lines before the current one, counted like `selection`
+        let mut item = 0usize;
 
-        for (hunk_idx, hunk) in hunks.iter().enumerate() {
+        'hunks: for (hunk_idx, hunk) in hunks.iter().enumerate() {
             if row >= max_rows {
                 break;
             }
 
-            if line_idx + hunk.lines.len() <= scroll_offset {
-                line_idx += hunk.lines.len() + 1;
+            // Lines are one row each unless wrapping, so whole hunks above can be skipped
+            if wrap_width.is_none() && position + hunk.lines.len() < scroll_offset {
+                position += hunk.lines.len() + 1;
+                item += hunk.lines.len() + 1;
                 continue;
             }
 
-            if hunk_idx > 0 && line_idx >= scroll_offset {
-                execute!(stdout, MoveTo(start_x, row))?;
+            if hunk_idx > 0 && position >= scroll_offset {
+                queue!(screen, MoveTo(start_x, row))?;
                 let sep = format!("{:─<width$}", "─", width = diff_width);
                 write!(
-                    stdout,
+                    screen,
                     "{}{}{}{}",
-                    theme::BG_HUNK,
-                    theme::FG_SEPARATOR,
+                    self.theme.bg_hunk,
+                    self.theme.fg_separator,
                     sep,
                     theme::RESET
                 )?;
                 row += 1;
-                if row >= max_rows {
-                    break;
-                }
             }
-            line_idx += 1;
+            position += 1;
+            item += 1;
 
             for line in &hunk.lines {
-                if line_idx < scroll_offset {
-                    line_idx += 1;
-                    continue;
-                }
+                let selected = selection.as_ref().is_some_and(|selection| selection.contains(&item));
+                item += 1;
+                let starts = self.row_starts(line, wrap_width, h_scroll);
+                for (part, start) in starts.iter().enumerate() {
+                    if position + part < scroll_offset {
+                        continue;
+                    }
+                    if row >= max_rows {
+                        break 'hunks;
+                    }
 
-                if row >= max_rows {
-                    break;
+                    queue!(screen, MoveTo(start_x, row))?;
+                    let text = Self::format_diff_row(line, Some(diff_width), *start, part > 0, selected, true, &self.theme, self.line_format);
+                    write!(screen, "{}", text)?;
+                    row += 1;
                 }
-
-                execute!(stdout, MoveTo(start_x, row))?;
-                self.draw_diff_line(stdout, line, diff_width)?;
-                row += 1;
-                line_idx += 1;
+                position += starts.len();
             }
         }
 
         while row < max_rows {
-            execute!(stdout, MoveTo(start_x, row))?;
+            queue!(screen, MoveTo(start_x, row))?;
             write!(
-                stdout,
+                screen,
                 "{}{:width$}{}",
-                theme::BG_DARK,
+                self.theme.bg_diff,
                 "",
                 theme::RESET,
                 width = diff_width
@@ -368,101 +1042,108 @@ impl Ui {
         Ok(())
     }
 
-    fn draw_diff_line(
-        &self,
-        stdout: &mut io::Stdout,
+    /// Render one diff line with the line-number gutter. Without a width the
+    /// content is neither truncated nor padded; without color it is plain text.
+    pub fn format_diff_line(line: &DiffLine, width: Option<usize>, color: bool, theme: &Theme, format: LineFormat) -> String {
+        Self::format_diff_row(line, width, 0, false, false, color, theme, format)
+    }
+
+    /// One row of a diff line, showing its content from column `start`. Rows that
+    /// continue a wrapped line leave the line numbers out and mark the gutter with `↪`;
+    /// selected lines have their gutter highlighted.
+    #[allow(clippy::too_many_arguments)]
+    fn format_diff_row(
         line: &DiffLine,
-        width: usize,
-    ) -> io::Result<()> {
+        width: Option<usize>,
+        start: usize,
+        continued: bool,
+        selected: bool,
+        color: bool,
+        theme: &Theme,
+        format: LineFormat,
+    ) -> String {
         let old_str = line
             .old_num
+            .filter(|_| !continued)
             .map(|n| format!("{:>4}", n))
             .unwrap_or_else(|| "    ".to_string());
-        let new_str = line
-            .new_num
-            .map(|n| format!("{:>4}", n))
-            .unwrap_or_else(|| "    ".to_string());
+        let new_str = match line.new_num {
+            _ if continued => "   ↪".to_string(),
+            Some(n) => format!("{:>4}", n),
+            None => "    ".to_string(),
+        };
 
-        let content_width = width.saturating_sub(14);
+        let sign = match line.tag {
+            ChangeTag::Insert => '+',
+            ChangeTag::Delete => '-',
+            ChangeTag::Equal => ' ',
+        };
 
-        let mut content = String::new();
-        if let Some(ref highlighted) = line.highlighted {
-            let mut chars_written = 0;
-            for (style, text) in highlighted {
-                if chars_written >= content_width {
-                    break;
-                }
-                let remaining = content_width - chars_written;
-                let display_text = if text.len() > remaining {
-                    &text[..remaining]
-                } else {
-                    text.as_str()
-                };
-                let color_code =
-                    theme::rgb_to_256(style.foreground.r, style.foreground.g, style.foreground.b);
-                content.push_str(&format!("\x1b[38;5;{}m{}", color_code, display_text));
-                chars_written += display_text.len();
-            }
-            if chars_written < content_width {
-                content.push_str(&" ".repeat(content_width - chars_written));
-            }
-        } else if line.content.len() > content_width {
-            content = format!("{}…", &line.content[..content_width.saturating_sub(1)]);
-        } else {
-            content = format!("{:<width$}", line.content, width = content_width);
+        let (bg, fg) = match (&line.moved, line.tag) {
+            _ if line.is_notice() => (theme.bg_diff, theme.fg_header),
+            (Some(moved), ChangeTag::Delete) if moved.alternate => (theme.bg_moved_from_alt, theme.fg_moved_from),
+            (Some(_), ChangeTag::Delete) => (theme.bg_moved_from, theme.fg_moved_from),
+            (Some(moved), _) if moved.alternate => (theme.bg_moved_to_alt, theme.fg_moved_to),
+            (Some(_), _) => (theme.bg_moved_to, theme.fg_moved_to),
+            (None, ChangeTag::Insert) => (theme.bg_added, theme.fg_added),
+            (None, ChangeTag::Delete) => (theme.bg_removed, theme.fg_removed),
+            (None, ChangeTag::Equal) => (theme.bg_diff, theme.fg_context),
+        };
+
+        let spans = content_spans(line, format, color.then_some((theme, bg, fg)));
+        let content_width = width.map(|w| w.saturating_sub(GUTTER_WIDTH));
+        let (mut content, used) = visible_content(&spans, start, content_width.unwrap_or(usize::MAX));
+        if let Some(w) = content_width {
+            content.push_str(&" ".repeat(w.saturating_sub(used)));
         }
 
-        match line.tag {
-            ChangeTag::Insert => {
-                write!(
-                    stdout,
-                    "\x1b[48;5;236m\x1b[38;5;243m{} {}\x1b[38;5;240m│\x1b[48;5;22m\x1b[38;5;114m+ {}\x1b[0m",
-                    old_str, new_str, content
-                )?;
-            }
-            ChangeTag::Delete => {
-                write!(
-                    stdout,
-                    "\x1b[48;5;236m\x1b[38;5;243m{} {}\x1b[38;5;240m│\x1b[48;5;52m\x1b[38;5;210m- {}\x1b[0m",
-                    old_str, new_str, content
-                )?;
-            }
-            ChangeTag::Equal => {
-                write!(
-                    stdout,
-                    "\x1b[48;5;236m\x1b[38;5;243m{} {}\x1b[38;5;240m│\x1b[48;5;236m\x1b[38;5;250m  {}\x1b[0m",
-                    old_str, new_str, content
-                )?;
-            }
+        if !color {
+            return format!("{} {}│{} {}", old_str, new_str, sign, content);
         }
 
-        Ok(())
+        format!(
+            "{}{}{} {}{}│{}{}{} {}{}",
+            if selected { theme.bg_selected } else { theme.bg_diff },
+            theme.fg_line_number,
+            old_str,
+            new_str,
+            theme.fg_separator,
+            bg,
+            fg,
+            sign,
+            content,
+            theme::RESET
+        )
     }
 
-    pub fn draw_status_bar(&self, stdout: &mut io::Stdout, scroll_offset: usize, total_lines: usize, visible_lines: usize, mouse_enabled: bool, _current_branch: &str) -> io::Result<()> {
-        execute!(stdout, MoveTo(0, self.term_height - 1))?;
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_status_bar(&self, screen: &mut Screen, scroll_offset: usize, total_lines: usize, visible_lines: usize, mouse_enabled: bool, diff_mode: &str, message: Option<&str>) -> io::Result<()> {
+        queue!(screen, MoveTo(0, self.term_height - 1))?;
         
         let scroll_info = if total_lines > visible_lines {
-            let percent = if total_lines == 0 {
-                100
-            } else {
-                ((scroll_offset + visible_lines) * 100 / total_lines).min(100)
-            };
-            format!(" {}% ", percent)
+            let percent = ((scroll_offset + visible_lines) * 100)
+                .checked_div(total_lines)
+                .map_or(100, |p| p.min(100));
+            format!(" {} │ {}% ", diff_mode, percent)
         } else {
-            " All ".to_string()
+            format!(" {} │ All ", diff_mode)
         };
         
         let mouse_status = if mouse_enabled { "m:Mouse" } else { "m:Select" };
-        let controls = format!(" b:Branch │ ←→ Commits │ ↑↓ Files │ j/k Scroll │ {} │ q Quit ", mouse_status);
-        let right_padding = (self.term_width as usize).saturating_sub(controls.len() + scroll_info.len());
+        let controls = match message {
+            Some(message) => format!(" {} ", message),
+            None if self.uses_drawer() => " Tab Lists │ ←→ Commits │ ↑↓ Files │ ? Help │ q Quit ".to_string(),
+            None => format!(" b:Branch │ ←→ Commits │ ↑↓ Files │ j/k Scroll │ a/w Diff │ t Theme │ {} │ ? Help │ q Quit ", mouse_status),
+        };
+        let right_padding = (self.term_width as usize).saturating_sub(text::width(&controls) + text::width(&scroll_info));
         let status = format!("{}{:>width$}{}", controls, "", scroll_info, width = right_padding);
+        let status = text::take_width(&status, self.term_width as usize);
         
         write!(
-            stdout,
+            screen,
             "{}{}{}{}",
-            theme::BG_HEADER,
-            theme::FG_DIM,
+            self.theme.bg_header,
+            self.theme.fg_dim,
             status,
             theme::RESET
         )
@@ -471,6 +1152,171 @@ impl Ui {
 
 impl Default for Ui {
     fn default() -> Self {
-        Self::new()
+        Self::new(Theme::default(), Layout::default(), LineFormat::default())
+    }
+}
+
+/// `value` moved by `delta`, stopping at zero
+fn offset(value: u16, delta: i32) -> u16 {
+    (value as i32 + delta).clamp(0, u16::MAX as i32) as u16
+}
+
+/// Content of `line` as (color, text) spans with tabs expanded and control characters made
+/// printable. `colors` are the theme and the line's background and text color, without
+/// which the spans are plain text.
+fn content_spans(line: &DiffLine, format: LineFormat, colors: Option<(&Theme, Paint, Paint)>) -> Vec<(String, String)> {
+    let pieces: Vec<(String, &str)> = match (&line.highlighted, colors) {
+        (Some(highlighted), Some(_)) => highlighted
+            .iter()
+            .map(|(style, span)| {
+                let color = Paint::fg(Color::Rgb(style.foreground.r, style.foreground.g, style.foreground.b));
+                (color.to_string(), span.as_str())
+            })
+            .collect(),
+        _ => vec![(String::new(), line.content.as_str())],
+    };
+
+    // Tab stops count from the start of the line, not of the span
+    let mut column = 0;
+    if !format.show_whitespace {
+        return pieces
+            .into_iter()
+            .map(|(color, piece)| {
+                let (expanded, next) = text::expand_tabs(&text::printable(piece), format.tab_width, column);
+                column = next;
+                (color, expanded)
+            })
+            .collect();
+    }
+
+    let trailing = line.content.trim_end_matches([' ', '\t', '\r']).len();
+    let errors = if line.tag == ChangeTag::Insert { whitespace_errors(&line.content) } else { Vec::new() };
+    let glyph_color = |error: bool| match colors {
+        Some((theme, bg, _)) => format!("{}{}", if error { theme.bg_whitespace_error } else { bg }, theme.fg_dim),
+        None => String::new(),
+    };
+
+    let mut spans: Vec<(String, String)> = Vec::new();
+    let mut push = |color: String, text: &str| match spans.last_mut() {
+        Some((last, existing)) if *last == color => existing.push_str(text),
+        _ => spans.push((color, text.to_string())),
+    };
+    let mut offset = 0;
+    for (color, piece) in pieces {
+        // Glyphs change the colors, so every piece starts from the line's own
+        let color = match colors {
+            Some((_, bg, fg)) => format!("{}{}{}", bg, fg, color),
+            None => color,
+        };
+        for (index, grapheme) in piece.grapheme_indices(true) {
+            let at = offset + index;
+            let error = errors.iter().any(|range| range.contains(&at));
+            match grapheme {
+                "\t" => {
+                    let spaces = format.tab_width.max(1) - column % format.tab_width.max(1);
+                    push(glyph_color(error), &format!("→{}", " ".repeat(spaces - 1)));
+                    column += spaces;
+                }
+                " " if at >= trailing => {
+                    push(glyph_color(error), "·");
+                    column += 1;
+                }
+                "\r" => {
+                    push(glyph_color(error), "␍");
+                    column += 1;
+                }
+                " " if error => {
+                    push(glyph_color(true), " ");
+                    column += 1;
+                }
+                _ => {
+                    let grapheme = text::printable(grapheme);
+                    push(color.clone(), &grapheme);
+                    column += grapheme.width();
+                }
+            }
+        }
+        offset += piece.len();
+    }
+    if line.no_newline {
+        push(glyph_color(false), " \\ No newline at end of file");
+    }
+    spans
+}
+
+/// Byte ranges of what `git diff --check` reports in an added line: whitespace at the end
+/// and spaces right before a tab in the indentation
+fn whitespace_errors(content: &str) -> Vec<Range<usize>> {
+    let mut errors = Vec::new();
+    let indent = content.len() - content.trim_start_matches([' ', '\t']).len();
+    let mut spaces = None;
+    for (index, byte) in content.bytes().take(indent).enumerate() {
+        if byte == b' ' {
+            spaces.get_or_insert(index);
+        } else if let Some(start) = spaces.take() {
+            errors.push(start..index);
+        }
+    }
+    let trailing = content.trim_end_matches([' ', '\t', '\r']).len();
+    if trailing < content.len() {
+        errors.push(trailing..content.len());
+    }
+    errors
+}
+
+/// Up to `limit` columns of colored `spans` from column `start` on, and the columns they
+/// take. A wide character cut in half at the start shows as a space.
+fn visible_content(spans: &[(String, String)], start: usize, limit: usize) -> (String, usize) {
+    let mut content = String::new();
+    let mut column = 0;
+    let mut used = 0;
+    for (color, span) in spans {
+        content.push_str(color);
+        for grapheme in span.graphemes(true) {
+            let width = grapheme.width();
+            if column < start {
+                column += width;
+                let shown = column.saturating_sub(start).min(limit - used);
+                content.push_str(&" ".repeat(shown));
+                used += shown;
+                continue;
+            }
+            if used + width > limit {
+                return (content, used);
+            }
+            content.push_str(grapheme);
+            column += width;
+            used += width;
+        }
+    }
+    (content, used)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn styles_hold_the_effective_colors() {
+        let mut screen = Screen::new(10, 1);
+        for _ in 0..100 {
+            write!(screen, "\x1b[38;5;1m\x1b[48;2;1;2;3m\x1b[1mx\x1b[22;39mx").unwrap();
+        }
+        assert_eq!(screen.styles, ["", "\x1b[38;5;1m", "\x1b[38;5;1;48;2;1;2;3m", "\x1b[1;38;5;1;48;2;1;2;3m", "\x1b[48;2;1;2;3m"]);
+        assert_eq!(screen.styles[screen.cells[1].style], "\x1b[48;2;1;2;3m");
+
+        write!(screen, "\x1b[mx").unwrap();
+        assert_eq!(screen.style_id, 0);
+    }
+
+    #[test]
+    fn escapes_in_content_are_drawn_as_text() {
+        let line = DiffLine { new_num: Some(1), ..DiffLine::notice("a\x1b[2J\x1b[31mb\x07\tc".to_string()) };
+        for show_whitespace in [false, true] {
+            let format = LineFormat { tab_width: 4, show_whitespace };
+            let plain = Ui::format_diff_line(&line, None, false, &Theme::default(), format);
+            assert!(!plain.contains(|c: char| c.is_control()), "{:?}", plain);
+            assert!(plain.contains("a␛[2J␛[31mb␇"));
+        }
     }
 }
@@ -0,0 +1,170 @@
+use git2::Repository;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+/// Quiet period before a burst of events (checkout, build, save-all) is reported
+const DEBOUNCE: Duration = Duration::from_millis(150);
+
+/// What changed since the last report
+#[derive(Default)]
+pub struct Changes {
+    /// HEAD, branches or packed-refs moved
+    pub refs: bool,
+    /// The index was written (stage/unstage, commit, checkout)
+    pub index: bool,
+    /// Repo-relative paths of modified worktree files
+    pub worktree: Vec<String>,
+    /// Unknown scope (poll fallback, dropped events): reload everything
+    pub rescan: bool,
+}
+
+impl Changes {
+    pub fn everything() -> Self {
+        Self {
+            refs: true,
+            index: true,
+            worktree: Vec::new(),
+            rescan: true,
+        }
+    }
+
+    pub fn touches(&self, path: &str) -> bool {
+        self.rescan || self.index || self.worktree.iter().any(|p| p == path)
+    }
+
+    fn is_empty(&self) -> bool {
+        !self.refs && !self.index && !self.rescan && self.worktree.is_empty()
+    }
+}
+
+/// inotify (or platform equivalent) watch on the worktree, index, HEAD and refs
+pub struct RepoWatcher {
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    repo: Repository,
+    git_dir: PathBuf,
+    workdir: PathBuf,
+    pending: Changes,
+    last_event: Option<Instant>,
+}
+
+impl RepoWatcher {
+    pub fn new() -> notify::Result<Self> {
+        let repo = Repository::discover(".").map_err(|e| notify::Error::generic(e.message()))?;
+        let workdir = repo
+            .workdir()
+            .ok_or_else(|| notify::Error::generic("bare repository"))?
+            .to_path_buf();
+        let git_dir = repo.path().to_path_buf();
+
+        let (tx, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+
+        // HEAD, index and packed-refs live directly in the git dir; branches under refs/
+        watcher.watch(&git_dir, RecursiveMode::NonRecursive)?;
+        watcher.watch(&git_dir.join("refs"), RecursiveMode::Recursive)?;
+
+        let mut this = Self {
+            watcher,
+            events,
+            repo,
+            git_dir,
+            workdir: workdir.clone(),
+            pending: Changes::default(),
+            last_event: None,
+        };
+        this.watch_tree(&workdir)?;
+        Ok(this)
+    }
+
+    /// Watch `dir` and its subdirectories one by one, skipping ignored ones
+    /// (target/, node_modules/, ...) so big build outputs don't exhaust watches.
+    fn watch_tree(&mut self, dir: &Path) -> notify::Result<()> {
+        let mut stack = vec![dir.to_path_buf()];
+        while let Some(dir) = stack.pop() {
+            self.watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+                if is_dir && !self.is_git_path(&path) && !self.is_ignored(&path) {
+                    stack.push(path);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_git_path(&self, path: &Path) -> bool {
+        path.starts_with(&self.git_dir)
+    }
+
+    fn is_ignored(&self, path: &Path) -> bool {
+        match path.strip_prefix(&self.workdir) {
+            Ok(relative) => {
+                relative.starts_with("target") || self.repo.is_path_ignored(relative).unwrap_or(false)
+            }
+            Err(_) => true,
+        }
+    }
+
+    /// Drain pending events; returns the accumulated changes once they have settled
+    pub fn poll(&mut self) -> Option<Changes> {
+        while let Ok(result) = self.events.try_recv() {
+            self.last_event = Some(Instant::now());
+            match result {
+                Ok(event) => self.record(event),
+                Err(_) => self.pending.rescan = true,
+            }
+        }
+
+        let settled = self.last_event.is_some_and(|t| t.elapsed() >= DEBOUNCE);
+        if !settled {
+            return None;
+        }
+        self.last_event = None;
+        let changes = std::mem::take(&mut self.pending);
+        (!changes.is_empty()).then_some(changes)
+    }
+
+    fn record(&mut self, event: Event) {
+        if event.need_rescan() {
+            self.pending.rescan = true;
+        }
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+
+        for path in event.paths {
+            if let Ok(relative) = path.strip_prefix(&self.git_dir) {
+                let relative = relative.to_string_lossy();
+                if relative == "index" {
+                    self.pending.index = true;
+                } else if (relative == "HEAD" || relative == "packed-refs" || relative.starts_with("refs"))
+                    && !relative.ends_with(".lock")
+                {
+                    self.pending.refs = true;
+                }
+                continue;
+            }
+
+            if self.is_ignored(&path) {
+                continue;
+            }
+            if matches!(event.kind, EventKind::Create(_)) && path.is_dir() {
+                // New directories need their own watch
+                let _ = self.watch_tree(&path);
+            }
+            if let Ok(relative) = path.strip_prefix(&self.workdir) {
+                let relative = relative.to_string_lossy().replace('\\', "/");
+                if !self.pending.worktree.contains(&relative) {
+                    self.pending.worktree.push(relative);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+
+use crate::export;
+use crate::highlighter::SyntaxTheme;
+use crate::source::DiffSource;
+use crate::theme::Theme;
+use crate::types::{BranchInfo, CommitInfo, DiffHunk, DiffLine, DiffSettings, FileChange, Highlights, LineRef};
+
+/// Independent request streams; a new request supersedes the pending one in the same slot
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Slot {
+    Commits,
+    Branches,
+    Files,
+    Diff,
+    Highlight,
+    Export,
+    Stage,
+}
+
+const SLOT_COUNT: usize = 7;
+
+pub enum Request {
+    Commits { branch: String, limit: usize },
+    Branches,
+    Files { commit: CommitInfo },
+    Diff { commit: CommitInfo, path: String },
+    Highlight { commit: CommitInfo, path: String, lines: Vec<LineRef> },
+    /// Lines to fill in above hunk `hunk`, from `from` up to `to`; superseded by a new diff like one
+    Context { commit: CommitInfo, path: String, hunk: usize, from: LineRef, to: LineRef },
+    StageLines { path: String, lines: Vec<LineRef> },
+    ExportPatch { commit: CommitInfo, file_name: String },
+    /// Colored like the viewer with `theme`
+    ExportHtml { commit: CommitInfo, file_name: String, theme: Theme },
+    /// Applied in order with the other requests, never cancelled
+    Settings(DiffSettings),
+    /// Name of the syntax theme to highlight with; applied like `Settings`
+    SyntaxTheme(String),
+}
+
+impl Request {
+    fn slot(&self) -> Option<Slot> {
+        match self {
+            Request::Commits { .. } => Some(Slot::Commits),
+            Request::Branches => Some(Slot::Branches),
+            Request::Files { .. } => Some(Slot::Files),
+            Request::Diff { .. } | Request::Context { .. } => Some(Slot::Diff),
+            Request::Highlight { .. } => Some(Slot::Highlight),
+            Request::ExportPatch { .. } | Request::ExportHtml { .. } => Some(Slot::Export),
+            Request::StageLines { .. } => Some(Slot::Stage),
+            Request::Settings(_) | Request::SyntaxTheme(_) => None,
+        }
+    }
+}
+
+pub enum Response {
+    Commits(Result<Vec<CommitInfo>, git2::Error>),
+    Branches(Result<Vec<BranchInfo>, git2::Error>),
+    Files(Result<Vec<FileChange>, git2::Error>),
+    Diff(Result<Vec<DiffHunk>, git2::Error>),
+    Highlights { lines: Vec<LineRef>, result: Result<Highlights, git2::Error> },
+    Context { hunk: usize, result: Result<Vec<DiffLine>, git2::Error> },
+    /// How many of the selected lines were staged
+    Staged { path: String, result: Result<usize, git2::Error> },
+    /// Status bar message describing the outcome
+    Exported(String),
+}
+
+struct Job {
+    generation: u64,
+    request: Request,
+}
+
+struct Reply {
+    slot: Slot,
+    generation: u64,
+    response: Response,
+}
+
+/// Latest generation requested per slot, shared with the worker thread so it can skip stale jobs
+#[derive(Default)]
+struct Generations([AtomicU64; SLOT_COUNT]);
+
+impl Generations {
+    fn bump(&self, slot: Slot) -> u64 {
+        self.0[slot as usize].fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    fn is_current(&self, slot: Slot, generation: u64) -> bool {
+        self.0[slot as usize].load(Ordering::SeqCst) == generation
+    }
+}
+
+/// What the UI needs from the source before its first frame
+pub struct Startup {
+    pub current_branch: String,
+    pub commits: Vec<CommitInfo>,
+    pub live: bool,
+    pub settings: DiffSettings,
+    pub syntax_themes: Vec<SyntaxTheme>,
+    pub syntax_theme: String,
+    /// Where the changed files are, for opening them in an editor
+    pub workdir: Option<PathBuf>,
+}
+
+/// Runs git and diff work off the UI thread. The source is opened on the worker thread and
+/// stays there, so it doesn't need to be `Send` (syntect parser states aren't).
+pub struct Worker {
+    requests: Sender<Job>,
+    replies: Receiver<Reply>,
+    generations: Arc<Generations>,
+    /// Slots with a request whose response has not arrived yet
+    pending: [bool; SLOT_COUNT],
+}
+
+impl Worker {
+    pub fn spawn<F>(open: F, commit_limit: usize) -> Result<(Self, Startup), git2::Error>
+    where
+        F: FnOnce() -> Result<Box<dyn DiffSource>, git2::Error> + Send + 'static,
+    {
+        let (requests, jobs) = mpsc::channel::<Job>();
+        let (results, replies) = mpsc::channel();
+        let (started, startup) = mpsc::channel();
+        let generations = Arc::new(Generations::default());
+
+        let shared = Arc::clone(&generations);
+        thread::spawn(move || {
+            let mut source = match open() {
+                Ok(source) => source,
+                Err(e) => {
+                    let _ = started.send(Err(e));
+                    return;
+                }
+            };
+            let current_branch = source.current_branch().unwrap_or("main").to_string();
+            let commits = source.load_commits_for_branch(&current_branch, commit_limit).unwrap_or_default();
+            let _ = started.send(Ok(Startup {
+                current_branch,
+                commits,
+                live: source.is_live(),
+                settings: source.settings(),
+                syntax_themes: source.syntax_themes(),
+                syntax_theme: source.syntax_theme().to_string(),
+                workdir: source.workdir().map(|dir| dir.to_path_buf()),
+            }));
+
+            while let Ok(job) = jobs.recv() {
+                let Some(slot) = job.request.slot() else {
+                    match job.request {
+                        Request::Settings(settings) => source.set_settings(settings),
+                        Request::SyntaxTheme(name) => {
+                            source.set_syntax_theme(&name);
+                        }
+                        _ => {}
+                    }
+                    continue;
+                };
+                // Selection moved on while this job was queued
+                if !shared.is_current(slot, job.generation) {
+                    continue;
+                }
+                let response = handle(source.as_mut(), job.request);
+                let reply = Reply { slot, generation: job.generation, response };
+                if results.send(reply).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let startup = startup
+            .recv()
+            .unwrap_or_else(|_| Err(git2::Error::from_str("worker thread exited")))?;
+        let worker = Self {
+            requests,
+            replies,
+            generations,
+            pending: [false; SLOT_COUNT],
+        };
+        Ok((worker, startup))
+    }
+
+    /// Queue a request, cancelling any older one for the same slot
+    pub fn send(&mut self, request: Request) {
+        let generation = match request.slot() {
+            Some(slot) => {
+                self.pending[slot as usize] = true;
+                self.generations.bump(slot)
+            }
+            None => 0,
+        };
+        let _ = self.requests.send(Job { generation, request });
+    }
+
+    /// Drop the pending request for `slot` so its response is never delivered
+    pub fn cancel(&mut self, slot: Slot) {
+        self.generations.bump(slot);
+        self.pending[slot as usize] = false;
+    }
+
+    /// Next response that is still current
+    pub fn try_recv(&mut self) -> Option<Response> {
+        while let Ok(reply) = self.replies.try_recv() {
+            if self.generations.is_current(reply.slot, reply.generation) {
+                self.pending[reply.slot as usize] = false;
+                return Some(reply.response);
+            }
+        }
+        None
+    }
+
+    pub fn is_pending(&self, slot: Slot) -> bool {
+        self.pending[slot as usize]
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.pending.iter().any(|p| *p)
+    }
+}
+
+fn handle(source: &mut dyn DiffSource, request: Request) -> Response {
+    match request {
+        Request::Commits { branch, limit } => Response::Commits(source.load_commits_for_branch(&branch, limit)),
+        Request::Branches => Response::Branches(source.load_branches()),
+        Request::Files { commit } => Response::Files(source.files_of(&commit)),
+        Request::Diff { commit, path } => Response::Diff(if commit.is_local_changes {
+            source.load_diff_for_file(&path)
+        } else {
+            source.load_diff_for_commit_file(&commit.sha, &path)
+        }),
+        Request::Context { commit, path, hunk, from, to } => {
+            let commit_sha = (!commit.is_local_changes).then_some(commit.sha.as_str());
+            Response::Context { hunk, result: source.context(commit_sha, &path, from, to) }
+        }
+        Request::StageLines { path, lines } => {
+            let result = source.stage_lines(&path, &lines);
+            Response::Staged { path, result }
+        }
+        Request::Highlight { commit, path, lines } => {
+            let commit_sha = (!commit.is_local_changes).then_some(commit.sha.as_str());
+            let result = source.highlight(commit_sha, &path, &lines);
+            Response::Highlights { lines, result }
+        }
+        Request::ExportPatch { commit, file_name } => {
+            let result = source
+                .format_patch(&commit)
+                .map_err(|e| e.to_string())
+                .and_then(|patch| std::fs::write(&file_name, patch).map_err(|e| e.to_string()));
+            Response::Exported(export_message(&file_name, result))
+        }
+        Request::ExportHtml { commit, file_name, theme } => {
+            let result = export::collect_files(source, &commit)
+                .map_err(|e| e.to_string())
+                .and_then(|files| {
+                    std::fs::write(&file_name, export::to_html(&[(commit, files)], &theme)).map_err(|e| e.to_string())
+                });
+            Response::Exported(export_message(&file_name, result))
+        }
+        Request::Settings(_) | Request::SyntaxTheme(_) => unreachable!("settings are applied without a response"),
+    }
+}
+
+fn export_message(file_name: &str, result: Result<(), String>) -> String {
+    match result {
+        Ok(()) => format!("Wrote {}", file_name),
+        Err(e) => format!("Export failed: {}", e),
+    }
+}